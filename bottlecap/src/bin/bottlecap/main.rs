@@ -14,12 +14,13 @@ use bottlecap::{
     config::{self, AwsConfig, Config},
     event_bus::bus::EventBus,
     events::Event,
-    lifecycle::{
-        flush_control::FlushControl,
-        invocation_context::{InvocationContext, InvocationContextBuffer},
-    },
+    lifecycle::{flush_control::FlushControl, invocation_context::InvocationContextBuffer},
     logger,
-    logs::{agent::LogsAgent, flusher::Flusher as LogsFlusher},
+    logs::{
+        agent::LogsAgent,
+        flusher::Flusher as LogsFlusher,
+        listener::{HttpLogsListener, TcpLogsListener},
+    },
     metrics::{
         aggregator::Aggregator as MetricsAggregator,
         constants::CONTEXTS,
@@ -27,18 +28,24 @@ use bottlecap::{
         enhanced::lambda::Lambda as enhanced_metrics,
         flusher::Flusher as MetricsFlusher,
     },
+    proxy::interceptor::{RuntimeApiProxy, RUNTIME_API_PROXY_PORT},
     secrets::decrypt,
     tags::{lambda, provider::Provider as TagProvider},
     telemetry::{
         self,
         client::TelemetryApiClient,
         events::TelemetryEvent,
-        events::{Status, TelemetryRecord},
+        events::{InitType, Status, TelemetryRecord},
         listener::TelemetryListener,
     },
     traces::{
+        invocation_span::SpanGenerator,
+        priority_sampler::PrioritySampler,
+        samplers::{ErrorSampler, RareSampler},
+        stats_concentrator::StatsConcentrator,
         stats_flusher::{self, StatsFlusher},
         stats_processor, trace_agent,
+        trace_filter::TraceFilter,
         trace_flusher::{self, TraceFlusher},
         trace_processor,
     },
@@ -46,11 +53,13 @@ use bottlecap::{
     EXTENSION_ID_HEADER, EXTENSION_NAME, EXTENSION_NAME_HEADER, EXTENSION_ROUTE,
     LAMBDA_RUNTIME_SLUG, TELEMETRY_PORT,
 };
+use chrono::Utc;
 use datadog_trace_obfuscation::obfuscation_config;
 use decrypt::resolve_secrets;
 use std::{
     collections::hash_map,
     collections::HashMap,
+    collections::VecDeque,
     env,
     io::Error,
     io::Result,
@@ -61,12 +70,13 @@ use std::{
 };
 use telemetry::listener::TelemetryListenerConfig;
 use tokio::sync::Mutex as TokioMutex;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 use tracing_subscriber::EnvFilter;
 
 use reqwest::Client;
 use serde::Deserialize;
 use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
 #[derive(Clone, Deserialize)]
@@ -261,12 +271,15 @@ async fn extension_loop_active(
     let mut event_bus = EventBus::run();
 
     let tags_provider = setup_tag_provider(aws_config, config, &r.account_id);
-    let (logs_agent_channel, logs_flusher) = start_logs_agent(
-        config,
-        resolved_api_key.clone(),
-        &tags_provider,
-        event_bus.get_sender_copy(),
-    );
+    let invocation_context_buffer = Arc::new(Mutex::new(InvocationContextBuffer::default()));
+    let (logs_agent_channel, logs_flusher, logs_agent_cancel_token, logs_agent_handle) =
+        start_logs_agent(
+            config,
+            resolved_api_key.clone(),
+            &tags_provider,
+            event_bus.get_sender_copy(),
+            &invocation_context_buffer,
+        );
 
     let metrics_aggr = Arc::new(Mutex::new(
         MetricsAggregator::<{ CONTEXTS }>::new(tags_provider.clone())
@@ -281,18 +294,32 @@ async fn extension_loop_active(
     let trace_flusher = Arc::new(trace_flusher::ServerlessTraceFlusher {
         buffer: Arc::new(TokioMutex::new(Vec::new())),
     });
+    let stats_concentrator = Arc::new(StatsConcentrator::new(
+        trace_agent::peer_tags(config),
+        config.env.as_deref(),
+    ));
     let trace_processor = Arc::new(trace_processor::ServerlessTraceProcessor {
         obfuscation_config: Arc::new(
             obfuscation_config::ObfuscationConfig::new()
                 .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?,
         ),
         resolved_api_key: resolved_api_key.clone(),
+        trace_filter: TraceFilter::new(config),
+        priority_sampler: Arc::new(PrioritySampler::new(
+            config.apm_target_tps,
+            config.env.as_deref(),
+        )),
+        stats_concentrator: Arc::clone(&stats_concentrator),
+        error_sampler: Arc::new(ErrorSampler::new(config.apm_error_tps)),
+        rare_sampler: Arc::new(RareSampler::new(config)),
     });
 
     let stats_flusher = Arc::new(stats_flusher::ServerlessStatsFlusher {
         buffer: Arc::new(TokioMutex::new(Vec::new())),
+        failed_payloads: Arc::new(TokioMutex::new(VecDeque::new())),
         config: Arc::clone(config),
         resolved_api_key: resolved_api_key.clone(),
+        stats_concentrator: Arc::clone(&stats_concentrator),
     });
     let stats_processor = Arc::new(stats_processor::ServerlessStatsProcessor {});
 
@@ -305,7 +332,8 @@ async fn extension_loop_active(
         trace_flusher: trace_flusher_clone,
         stats_processor,
         stats_flusher: stats_flusher_clone,
-        tags_provider,
+        tags_provider: Arc::clone(&tags_provider),
+        event_bus: event_bus.get_sender_copy(),
     });
     tokio::spawn(async move {
         let res = trace_agent.start_trace_agent().await;
@@ -316,11 +344,25 @@ async fn extension_loop_active(
     let lambda_enhanced_metrics = enhanced_metrics::new(Arc::clone(&metrics_aggr));
     let dogstatsd_cancel_token = start_dogstatsd(event_bus.get_sender_copy(), &metrics_aggr).await;
 
+    let logs_listeners_cancel_token = start_logs_listeners(config, &logs_agent_channel).await;
+    let proxy_cancel_token = start_runtime_api_proxy(config, event_bus.get_sender_copy());
     let telemetry_listener_cancel_token =
         setup_telemetry_client(&r.extension_id, logs_agent_channel).await?;
 
+    if config.capture_lambda_payload && !config.universal_instrumentation {
+        warn!("Payloads are captured on the spans built by the extension, DD_CAPTURE_LAMBDA_PAYLOAD needs DD_UNIVERSAL_INSTRUMENTATION to be enabled");
+    }
+    let mut span_generator = config.universal_instrumentation.then(|| {
+        SpanGenerator::new(
+            Arc::clone(config),
+            Arc::clone(&tags_provider),
+            resolved_api_key.clone(),
+            Arc::clone(&invocation_context_buffer),
+            stats_concentrator,
+        )
+    });
+
     let mut flush_control = FlushControl::new(config.serverless_flush_strategy);
-    let mut invocation_context_buffer = InvocationContextBuffer::default();
     let mut shutdown = false;
 
     loop {
@@ -338,6 +380,11 @@ async fn extension_loop_active(
                 if let Err(e) = lambda_enhanced_metrics.increment_invocation_metric() {
                     error!("Failed to increment invocation metric: {e:?}");
                 }
+                // Before any log of the invocation is processed, so that they
+                // can all be correlated with its span
+                if let Some(generator) = span_generator.as_mut() {
+                    generator.on_invocation_start(&request_id, Utc::now());
+                }
             }
             Ok(NextEventResponse::Shutdown {
                 shutdown_reason,
@@ -362,12 +409,55 @@ async fn extension_loop_active(
                         Event::Metric(event) => {
                             debug!("Metric event: {:?}", event);
                         }
+                        Event::LogsDropped(dropped) => {
+                            lambda_enhanced_metrics.set_logs_dropped_metric(dropped);
+                        }
+                        Event::LogsRedacted(rule_name, redactions) => {
+                            lambda_enhanced_metrics
+                                .set_logs_redacted_metric(&rule_name, redactions);
+                        }
+                        Event::InvocationPayload(request_id, payload) => {
+                            if let Some(generator) = span_generator.as_mut() {
+                                generator.on_invocation_payload(request_id.as_deref(), payload);
+                            }
+                        }
+                        Event::InvocationResponse(request_id, response) => {
+                            if let Some(generator) = span_generator.as_mut() {
+                                generator.on_invocation_response(&request_id, response);
+                            }
+                        }
+                        Event::InvocationError(request_id, error) => {
+                            if let Some(generator) = span_generator.as_mut() {
+                                generator.on_invocation_error(&request_id, error);
+                            }
+                        }
                         Event::Telemetry(event) => match event.record {
                             TelemetryRecord::PlatformStart { request_id, .. } => {
-                                invocation_context_buffer.insert(InvocationContext {
-                                    request_id,
-                                    runtime_duration_ms: 0.0,
-                                });
+                                if let Some(generator) = span_generator.as_mut() {
+                                    generator.on_platform_start(&request_id, event.time);
+                                }
+                            }
+                            TelemetryRecord::PlatformInitStart {
+                                initialization_type,
+                                ..
+                            } => {
+                                if let Some(generator) = span_generator.as_mut() {
+                                    generator.on_init_start(event.time, initialization_type);
+                                }
+                            }
+                            TelemetryRecord::PlatformRestoreStart { .. } => {
+                                if let Some(generator) = span_generator.as_mut() {
+                                    generator.on_init_start(event.time, InitType::SnapStart);
+                                }
+                            }
+                            TelemetryRecord::PlatformRestoreReport { metrics, .. } => {
+                                if let Some(generator) = span_generator.as_mut() {
+                                    if let Some(span) =
+                                        generator.on_init_report(metrics.duration_ms)
+                                    {
+                                        trace_flusher.add(generator.send_data(vec![span])).await;
+                                    }
+                                }
                             }
                             TelemetryRecord::PlatformInitReport {
                                 initialization_type,
@@ -375,6 +465,13 @@ async fn extension_loop_active(
                                 metrics,
                             } => {
                                 debug!("Platform init report for initialization_type: {:?} with phase: {:?} and metrics: {:?}", initialization_type, phase, metrics);
+                                if let Some(generator) = span_generator.as_mut() {
+                                    if let Some(span) =
+                                        generator.on_init_report(metrics.duration_ms)
+                                    {
+                                        trace_flusher.add(generator.send_data(vec![span])).await;
+                                    }
+                                }
                                 if let Err(e) = lambda_enhanced_metrics
                                     .set_init_duration_metric(metrics.duration_ms)
                                 {
@@ -384,11 +481,13 @@ async fn extension_loop_active(
                             TelemetryRecord::PlatformRuntimeDone {
                                 request_id,
                                 status,
+                                error_type,
                                 metrics,
-                                ..
                             } => {
                                 if let Some(metrics) = metrics {
                                     invocation_context_buffer
+                                        .lock()
+                                        .expect("lock poisoned")
                                         .add_runtime_duration(&request_id, metrics.duration_ms);
                                     lambda_enhanced_metrics
                                         .set_runtime_duration_metric(metrics.duration_ms);
@@ -412,6 +511,18 @@ async fn extension_loop_active(
                                     "Runtime done for request_id: {:?} with status: {:?}",
                                     request_id, status
                                 );
+                                if let Some(generator) = span_generator.as_mut() {
+                                    let duration_ms =
+                                        metrics.map_or(0.0, |metrics| metrics.duration_ms);
+                                    if let Some(spans) = generator.on_invocation_end(
+                                        &request_id,
+                                        status,
+                                        error_type,
+                                        duration_ms,
+                                    ) {
+                                        trace_flusher.add(generator.send_data(spans)).await;
+                                    }
+                                }
                                 // TODO(astuyve) it'll be easy to
                                 // pass the invocation deadline to
                                 // flush tasks here, so they can
@@ -435,12 +546,19 @@ async fn extension_loop_active(
                                     request_id, status
                                 );
                                 lambda_enhanced_metrics.set_report_log_metrics(&metrics);
-                                if let Some(invocation_context) =
-                                    invocation_context_buffer.remove(&request_id)
-                                {
-                                    if invocation_context.runtime_duration_ms > 0.0 {
-                                        let post_runtime_duration_ms = metrics.duration_ms
-                                            - invocation_context.runtime_duration_ms;
+                                if let Some(generator) = span_generator.as_mut() {
+                                    generator.on_invocation_report(&request_id);
+                                }
+                                // Kept for the logs of the invocation still to be processed
+                                let runtime_duration_ms = invocation_context_buffer
+                                    .lock()
+                                    .expect("lock poisoned")
+                                    .get(&request_id)
+                                    .map(|context| context.runtime_duration_ms);
+                                if let Some(runtime_duration_ms) = runtime_duration_ms {
+                                    if runtime_duration_ms > 0.0 {
+                                        let post_runtime_duration_ms =
+                                            metrics.duration_ms - runtime_duration_ms;
                                         lambda_enhanced_metrics.set_post_runtime_duration_metric(
                                             post_runtime_duration_ms,
                                         );
@@ -453,6 +571,35 @@ async fn extension_loop_active(
                                     break;
                                 }
                             }
+                            TelemetryRecord::PlatformInitRuntimeDone {
+                                status, error_type, ..
+                            } => {
+                                debug!(
+                                    "Platform init runtime done with status: {:?} and error_type: {:?}",
+                                    status, error_type
+                                );
+                                if status != Status::Success {
+                                    if let Err(e) =
+                                        lambda_enhanced_metrics.increment_init_errors_metric()
+                                    {
+                                        error!("Failed to increment init error metric: {e:?}");
+                                    }
+                                }
+                            }
+                            TelemetryRecord::PlatformLogsDropped {
+                                reason,
+                                dropped_records,
+                                dropped_bytes,
+                            } => {
+                                debug!(
+                                    "Lambda dropped {} log records ({} bytes): {}",
+                                    dropped_records, dropped_bytes, reason
+                                );
+                                lambda_enhanced_metrics.set_platform_logs_dropped_metrics(
+                                    dropped_records,
+                                    dropped_bytes,
+                                );
+                            }
                             _ => {
                                 debug!("Unforwarded Telemetry event: {:?}", event);
                             }
@@ -467,6 +614,18 @@ async fn extension_loop_active(
         if shutdown {
             dogstatsd_cancel_token.cancel();
             telemetry_listener_cancel_token.cancel();
+            logs_listeners_cancel_token.cancel();
+            proxy_cancel_token.cancel();
+            logs_agent_cancel_token.cancel();
+            // Wait for the pending and orphan logs to be processed
+            if let Err(e) = logs_agent_handle.await {
+                error!("Error waiting for the logs agent to shut down: {e:?}");
+            }
+            if let Some(generator) = span_generator.as_mut() {
+                if let Some(span) = generator.take_cold_start_span() {
+                    trace_flusher.add(generator.send_data(vec![span])).await;
+                }
+            }
             tokio::join!(
                 logs_flusher.flush(),
                 metrics_flusher.flush(),
@@ -501,18 +660,36 @@ fn start_logs_agent(
     resolved_api_key: String,
     tags_provider: &Arc<TagProvider>,
     event_bus: Sender<Event>,
-) -> (Sender<Vec<TelemetryEvent>>, LogsFlusher) {
-    let mut logs_agent = LogsAgent::new(Arc::clone(tags_provider), Arc::clone(config), event_bus);
+    invocation_context_buffer: &Arc<Mutex<InvocationContextBuffer>>,
+) -> (
+    Sender<Vec<TelemetryEvent>>,
+    LogsFlusher,
+    CancellationToken,
+    JoinHandle<()>,
+) {
+    let logs_agent_cancel_token = CancellationToken::new();
+    let mut logs_agent = LogsAgent::new(
+        Arc::clone(tags_provider),
+        Arc::clone(config),
+        event_bus,
+        Arc::clone(invocation_context_buffer),
+        logs_agent_cancel_token.clone(),
+    );
     let logs_agent_channel = logs_agent.get_sender_copy();
     let logs_flusher = LogsFlusher::new(
         resolved_api_key,
         Arc::clone(&logs_agent.aggregator),
-        config.site.clone(),
+        Arc::clone(config),
     );
-    tokio::spawn(async move {
+    let logs_agent_handle = tokio::spawn(async move {
         logs_agent.spin().await;
     });
-    (logs_agent_channel, logs_flusher)
+    (
+        logs_agent_channel,
+        logs_flusher,
+        logs_agent_cancel_token,
+        logs_agent_handle,
+    )
 }
 
 async fn start_dogstatsd(
@@ -539,6 +716,70 @@ async fn start_dogstatsd(
     dogstatsd_cancel_token
 }
 
+async fn start_logs_listeners(
+    config: &Arc<Config>,
+    logs_agent_channel: &Sender<Vec<TelemetryEvent>>,
+) -> CancellationToken {
+    let logs_listeners_cancel_token = CancellationToken::new();
+
+    if let Some(port) = config.logs_config_http_listener_port {
+        let http_listener = HttpLogsListener::new(
+            port,
+            logs_agent_channel.clone(),
+            logs_listeners_cancel_token.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = http_listener.spin().await {
+                error!("Error starting HTTP logs listener: {e:?}");
+            }
+        });
+    }
+
+    if let Some(port) = config.logs_config_tcp_listener_port {
+        match TcpLogsListener::new(
+            port,
+            logs_agent_channel.clone(),
+            logs_listeners_cancel_token.clone(),
+        )
+        .await
+        {
+            Ok(tcp_listener) => {
+                tokio::spawn(async move {
+                    tcp_listener.spin().await;
+                });
+            }
+            Err(e) => error!("Error starting TCP logs listener: {e}"),
+        }
+    }
+
+    logs_listeners_cancel_token
+}
+
+fn start_runtime_api_proxy(config: &Arc<Config>, event_bus: Sender<Event>) -> CancellationToken {
+    let proxy_cancel_token = CancellationToken::new();
+    if !config.experimental_enable_proxy {
+        return proxy_cancel_token;
+    }
+
+    let Ok(runtime_api) = env::var("AWS_LAMBDA_RUNTIME_API") else {
+        error!("AWS_LAMBDA_RUNTIME_API is not set, not starting the Runtime API proxy");
+        return proxy_cancel_token;
+    };
+    let proxy = RuntimeApiProxy::new(
+        RUNTIME_API_PROXY_PORT,
+        runtime_api,
+        event_bus,
+        proxy_cancel_token.clone(),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = proxy.spin().await {
+            error!("Error starting Runtime API proxy: {e:?}");
+        }
+    });
+
+    proxy_cancel_token
+}
+
 async fn setup_telemetry_client(
     extension_id: &str,
     logs_agent_channel: Sender<Vec<TelemetryEvent>>,
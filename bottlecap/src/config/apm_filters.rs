@@ -0,0 +1,32 @@
+use serde::Deserializer;
+
+use crate::config::deserialize_list;
+
+// Space separated in the environment,
+// i.e. `DD_APM_FILTER_TAGS_REJECT="http.status_code:404 env:dev"`
+pub fn deserialize_filter_tags<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list(deserializer, |s| s.split_whitespace().collect())
+}
+
+// Comma separated in the environment, as resources can contain spaces,
+// i.e. `DD_APM_IGNORE_RESOURCES="GET /health,^OPTIONS "`
+pub fn deserialize_ignore_resources<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list(deserializer, |s| s.split(',').map(str::trim).collect())
+}
+
+// Comma or space separated in the environment,
+// i.e. `DD_APM_PEER_TAGS="db.instance,peer.hostname"`
+pub fn deserialize_apm_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list(deserializer, |s| {
+        s.split(|c: char| c == ',' || c.is_whitespace()).collect()
+    })
+}
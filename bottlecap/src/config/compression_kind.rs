@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionKind {
+    /// Compress payloads with gzip.
+    #[default]
+    Gzip,
+    /// Compress payloads with zstd.
+    Zstd,
+}
+
+impl AsRef<str> for CompressionKind {
+    fn as_ref(&self) -> &str {
+        match self {
+            CompressionKind::Gzip => "gzip",
+            CompressionKind::Zstd => "zstd",
+        }
+    }
+}
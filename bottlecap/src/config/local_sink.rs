@@ -0,0 +1,25 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Deserializer};
+
+/// Local destination of the processed logs, to check what would be sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocalSink {
+    Stdout,
+    File(PathBuf),
+}
+
+// Local Sink can be either "stdout" or a file path
+impl<'de> Deserialize<'de> for LocalSink {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        match value.trim() {
+            "" => Err(serde::de::Error::custom("local sink can't be empty")),
+            "stdout" => Ok(LocalSink::Stdout),
+            path => Ok(LocalSink::File(PathBuf::from(path))),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value as JsonValue};
+
+// Attributes can be an object, or a JSON string when set through the environment,
+// i.e. `DD_LOGS_CONFIG_ATTRIBUTES='{"team":"payments"}'`
+pub fn deserialize_attributes<'de, D>(deserializer: D) -> Result<Map<String, JsonValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: JsonValue = Deserialize::deserialize(deserializer)?;
+
+    match value {
+        JsonValue::String(s) => serde_json::from_str(&s).map_err(|e| {
+            serde::de::Error::custom(format!("Failed to deserialize attributes: {e}"))
+        }),
+        JsonValue::Object(attributes) => Ok(attributes),
+        _ => Ok(Map::new()),
+    }
+}
@@ -1,6 +1,11 @@
+pub mod apm_filters;
+pub mod compression_kind;
 pub mod flush_strategy;
+pub mod local_sink;
+pub mod log_attributes;
 pub mod log_level;
 pub mod processing_rule;
+pub mod scrubbing_preset;
 
 use std::path::Path;
 
@@ -8,11 +13,71 @@ use figment::{
     providers::{Env, Format, Yaml},
     Figment,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
 
+use crate::config::apm_filters::{
+    deserialize_apm_list, deserialize_filter_tags, deserialize_ignore_resources,
+};
+use crate::config::compression_kind::CompressionKind;
 use crate::config::flush_strategy::FlushStrategy;
+use crate::config::local_sink::LocalSink;
+use crate::config::log_attributes::deserialize_attributes;
 use crate::config::log_level::LogLevel;
 use crate::config::processing_rule::{deserialize_processing_rules, ProcessingRule};
+use crate::config::scrubbing_preset::{deserialize_scrubbing_presets, ScrubbingPreset};
+use crate::logs::constants::MAX_BUFFER_SIZE_BYTES;
+
+/// Keys of the captured payloads whose values are replaced by `redacted`.
+const DEFAULT_REDACTED_KEYS: [&str; 9] = [
+    "password",
+    "passwd",
+    "pwd",
+    "secret",
+    "token",
+    "authorization",
+    "x-authorization",
+    "api_key",
+    "apikey",
+];
+
+// Comma separated in the environment,
+// i.e. `DD_CAPTURE_LAMBDA_PAYLOAD_REDACTED_KEYS="password,token"`
+fn deserialize_redacted_keys<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list(deserializer, |s| s.split(',').map(str::trim).collect())
+}
+
+/// Deserializes a list of strings, or a string cut with `split` as set through the
+/// environment. Empty and non-string values are skipped.
+pub(crate) fn deserialize_list<'de, D>(
+    deserializer: D,
+    split: fn(&str) -> Vec<&str>,
+) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Value = Deserialize::deserialize(deserializer)?;
+
+    let values = match value {
+        Value::String(s) => split(&s)
+            .into_iter()
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Value::Array(a) => a
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::String(s) => Some(s),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Ok(values)
+}
 
 #[derive(Debug, PartialEq, Deserialize, Clone)]
 #[serde(deny_unknown_fields)]
@@ -31,12 +96,51 @@ pub struct Config {
     pub serverless_logs_enabled: bool,
     #[serde(deserialize_with = "deserialize_processing_rules")]
     pub logs_config_processing_rules: Option<Vec<ProcessingRule>>,
+    #[serde(deserialize_with = "deserialize_scrubbing_presets")]
+    pub logs_config_scrubbing_presets: Vec<ScrubbingPreset>,
+    pub logs_config_keep_original_json: bool,
+    #[serde(deserialize_with = "deserialize_attributes")]
+    pub logs_config_attributes: Map<String, Value>,
+    pub logs_config_use_compression: bool,
+    pub logs_config_compression_kind: CompressionKind,
+    pub logs_config_compression_level: i32,
+    pub logs_config_max_buffer_size_bytes: usize,
+    pub logs_config_local_sink: Option<LocalSink>,
+    pub logs_config_local_sink_only: bool,
+    pub logs_config_http_listener_port: Option<u16>,
+    pub logs_config_tcp_listener_port: Option<u16>,
     pub apm_enabled: bool,
     pub lambda_handler: String,
+    #[serde(deserialize_with = "deserialize_filter_tags")]
+    pub apm_filter_tags_require: Vec<String>,
+    #[serde(deserialize_with = "deserialize_filter_tags")]
+    pub apm_filter_tags_reject: Vec<String>,
+    #[serde(deserialize_with = "deserialize_ignore_resources")]
+    pub apm_ignore_resources: Vec<String>,
+    #[serde(deserialize_with = "deserialize_apm_list")]
+    pub apm_features: Vec<String>,
+    pub apm_peer_tags_aggregation: bool,
+    #[serde(deserialize_with = "deserialize_apm_list")]
+    pub apm_peer_tags: Vec<String>,
+    pub apm_target_tps: f64,
+    pub apm_error_tps: f64,
+    pub apm_enable_rare_sampler: bool,
+    pub apm_rare_sampler_tps: f64,
+    /// Seconds before a span seen in a kept trace can be rare again.
+    pub apm_rare_sampler_cooldown: u64,
+    pub apm_rare_sampler_cardinality: usize,
+    pub compute_trace_stats: bool,
+    /// Whether traces are accepted on the OTLP/HTTP endpoint of the trace agent.
+    pub otlp_config_traces_enabled: bool,
     pub serverless_flush_strategy: FlushStrategy,
     pub trace_enabled: bool,
     pub serverless_trace_enabled: bool,
+    pub universal_instrumentation: bool,
     pub capture_lambda_payload: bool,
+    pub capture_lambda_payload_max_depth: usize,
+    #[serde(deserialize_with = "deserialize_redacted_keys")]
+    pub capture_lambda_payload_redacted_keys: Vec<String>,
+    pub experimental_enable_proxy: bool,
     // Deprecated or ignored, just here so we don't failover
     pub flush_to_log: bool,
     pub logs_injection: bool,
@@ -64,12 +168,44 @@ impl Default for Config {
             serverless_logs_enabled: true,
             // TODO(duncanista): Add serializer for YAML
             logs_config_processing_rules: None,
+            logs_config_scrubbing_presets: Vec::new(),
+            logs_config_keep_original_json: false,
+            logs_config_attributes: Map::new(),
+            logs_config_use_compression: true,
+            logs_config_compression_kind: CompressionKind::default(),
+            logs_config_compression_level: 6,
+            logs_config_max_buffer_size_bytes: MAX_BUFFER_SIZE_BYTES,
+            logs_config_local_sink: None,
+            logs_config_local_sink_only: false,
+            logs_config_http_listener_port: None,
+            logs_config_tcp_listener_port: None,
             // APM
             apm_enabled: false,
             lambda_handler: String::default(),
+            apm_filter_tags_require: Vec::new(),
+            apm_filter_tags_reject: Vec::new(),
+            apm_ignore_resources: Vec::new(),
+            apm_features: Vec::new(),
+            apm_peer_tags_aggregation: false,
+            apm_peer_tags: Vec::new(),
+            apm_target_tps: 10.0,
+            apm_error_tps: 10.0,
+            apm_enable_rare_sampler: false,
+            apm_rare_sampler_tps: 5.0,
+            apm_rare_sampler_cooldown: 300,
+            apm_rare_sampler_cardinality: 200,
+            compute_trace_stats: true,
+            otlp_config_traces_enabled: true,
             serverless_trace_enabled: true,
             trace_enabled: true,
+            universal_instrumentation: false,
             capture_lambda_payload: false,
+            capture_lambda_payload_max_depth: 10,
+            capture_lambda_payload_redacted_keys: DEFAULT_REDACTED_KEYS
+                .iter()
+                .map(|key| (*key).to_string())
+                .collect(),
+            experimental_enable_proxy: false,
             flush_to_log: false,
             logs_injection: false,
             merge_xray_traces: false,
@@ -385,7 +521,9 @@ pub mod tests {
                         kind: processing_rule::Kind::ExcludeAtMatch,
                         name: "exclude".to_string(),
                         pattern: "exclude".to_string(),
-                        replace_placeholder: None
+                        replace_placeholder: None,
+                        sample_rate: None,
+                        max_per_flush: None
                     }]),
                     extension_version: Some("next".to_string()),
                     ..Config::default()
@@ -395,6 +533,285 @@ pub mod tests {
         });
     }
 
+    #[test]
+    fn test_parse_logs_config_scrubbing_presets_from_env() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_LOGS_CONFIG_SCRUBBING_PRESETS", "credit_card, email");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    logs_config_scrubbing_presets: vec![
+                        ScrubbingPreset::CreditCard,
+                        ScrubbingPreset::Email
+                    ],
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_logs_config_scrubbing_presets_from_yaml() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.create_file(
+                "datadog.yaml",
+                r"
+                extension_version: next
+                logs_config_scrubbing_presets:
+                   - aws_access_key
+                   - jwt
+            ",
+            )?;
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    logs_config_scrubbing_presets: vec![
+                        ScrubbingPreset::AwsAccessKey,
+                        ScrubbingPreset::Jwt
+                    ],
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_logs_config_compression() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_LOGS_CONFIG_COMPRESSION_KIND", "zstd");
+            jail.set_env("DD_LOGS_CONFIG_COMPRESSION_LEVEL", "3");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    logs_config_compression_kind: CompressionKind::Zstd,
+                    logs_config_compression_level: 3,
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_logs_config_local_sink() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_LOGS_CONFIG_LOCAL_SINK", "stdout");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(config.logs_config_local_sink, Some(LocalSink::Stdout));
+            assert!(!config.logs_config_local_sink_only);
+
+            jail.set_env("DD_LOGS_CONFIG_LOCAL_SINK", "/tmp/logs.jsonl");
+            jail.set_env("DD_LOGS_CONFIG_LOCAL_SINK_ONLY", "true");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config.logs_config_local_sink,
+                Some(LocalSink::File("/tmp/logs.jsonl".into()))
+            );
+            assert!(config.logs_config_local_sink_only);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_logs_config_listener_ports() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_LOGS_CONFIG_HTTP_LISTENER_PORT", "10518");
+            jail.set_env("DD_LOGS_CONFIG_TCP_LISTENER_PORT", "10519");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    logs_config_http_listener_port: Some(10518),
+                    logs_config_tcp_listener_port: Some(10519),
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_universal_instrumentation() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_UNIVERSAL_INSTRUMENTATION", "true");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert!(config.universal_instrumentation);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_otlp_config_traces_enabled() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_OTLP_CONFIG_TRACES_ENABLED", "false");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert!(!config.otlp_config_traces_enabled);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_payload_capture() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_CAPTURE_LAMBDA_PAYLOAD", "true");
+            jail.set_env("DD_CAPTURE_LAMBDA_PAYLOAD_MAX_DEPTH", "3");
+            jail.set_env("DD_CAPTURE_LAMBDA_PAYLOAD_REDACTED_KEYS", "password, ssn");
+            jail.set_env("DD_EXPERIMENTAL_ENABLE_PROXY", "true");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    capture_lambda_payload: true,
+                    capture_lambda_payload_max_depth: 3,
+                    capture_lambda_payload_redacted_keys: vec![
+                        "password".to_string(),
+                        "ssn".to_string()
+                    ],
+                    experimental_enable_proxy: true,
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_logs_config_attributes() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env(
+                "DD_LOGS_CONFIG_ATTRIBUTES",
+                r#"{"team":"payments","tier":1}"#,
+            );
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            let mut attributes = Map::new();
+            attributes.insert("team".to_string(), Value::from("payments"));
+            attributes.insert("tier".to_string(), Value::from(1));
+            assert_eq!(
+                config,
+                Config {
+                    logs_config_attributes: attributes,
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_apm_filters_from_env() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env("DD_APM_FILTER_TAGS_REQUIRE", "env:prod region");
+            jail.set_env("DD_APM_FILTER_TAGS_REJECT", "http.status_code:404");
+            jail.set_env("DD_APM_IGNORE_RESOURCES", "GET /health, ^OPTIONS ");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    apm_filter_tags_require: vec!["env:prod".to_string(), "region".to_string()],
+                    apm_filter_tags_reject: vec!["http.status_code:404".to_string()],
+                    apm_ignore_resources: vec!["GET /health".to_string(), "^OPTIONS".to_string()],
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_apm_features_and_peer_tags() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.set_env(
+                "DD_APM_FEATURES",
+                "enable_cid_stats disable_operation_and_resource_name_logic_v2",
+            );
+            jail.set_env("DD_APM_PEER_TAGS_AGGREGATION", "true");
+            jail.set_env("DD_APM_PEER_TAGS", "db.instance,peer.hostname");
+            jail.set_env("DD_APM_TARGET_TPS", "5");
+            jail.set_env("DD_APM_ERROR_TPS", "0");
+            jail.set_env("DD_APM_ENABLE_RARE_SAMPLER", "true");
+            jail.set_env("DD_APM_RARE_SAMPLER_TPS", "1");
+            jail.set_env("DD_COMPUTE_TRACE_STATS", "false");
+            jail.set_env("DD_EXTENSION_VERSION", "next");
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config,
+                Config {
+                    apm_features: vec![
+                        "enable_cid_stats".to_string(),
+                        "disable_operation_and_resource_name_logic_v2".to_string()
+                    ],
+                    apm_peer_tags_aggregation: true,
+                    apm_peer_tags: vec!["db.instance".to_string(), "peer.hostname".to_string()],
+                    apm_target_tps: 5.0,
+                    apm_error_tps: 0.0,
+                    apm_enable_rare_sampler: true,
+                    apm_rare_sampler_tps: 1.0,
+                    compute_trace_stats: false,
+                    extension_version: Some("next".to_string()),
+                    ..Config::default()
+                }
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_parse_apm_filters_from_yaml() {
+        figment::Jail::expect_with(|jail| {
+            jail.clear_env();
+            jail.create_file(
+                "datadog.yaml",
+                r#"
+                extension_version: next
+                apm_filter_tags_reject: ["env:dev", "debug"]
+                apm_ignore_resources: ["GET /health, with comma"]
+            "#,
+            )?;
+            let config = get_config(Path::new("")).expect("should parse config");
+            assert_eq!(
+                config.apm_filter_tags_reject,
+                vec!["env:dev".to_string(), "debug".to_string()]
+            );
+            assert_eq!(
+                config.apm_ignore_resources,
+                vec!["GET /health, with comma".to_string()]
+            );
+            Ok(())
+        });
+    }
+
     #[test]
     fn test_parse_logs_config_processing_rules_from_yaml() {
         figment::Jail::expect_with(|jail| {
@@ -415,6 +832,11 @@ pub mod tests {
                      name: "mask"
                      pattern: "mask"
                      replace_placeholder: "REPLACED"
+                   - type: sample
+                     name: "sample"
+                     pattern: "DEBUG"
+                     sample_rate: 0.1
+                     max_per_flush: 100
             "#,
             )?;
             let config = get_config(Path::new("")).expect("should parse config");
@@ -426,19 +848,33 @@ pub mod tests {
                             kind: processing_rule::Kind::ExcludeAtMatch,
                             name: "exclude".to_string(),
                             pattern: "exclude".to_string(),
-                            replace_placeholder: None
+                            replace_placeholder: None,
+                            sample_rate: None,
+                            max_per_flush: None
                         },
                         ProcessingRule {
                             kind: processing_rule::Kind::IncludeAtMatch,
                             name: "include".to_string(),
                             pattern: "include".to_string(),
-                            replace_placeholder: None
+                            replace_placeholder: None,
+                            sample_rate: None,
+                            max_per_flush: None
                         },
                         ProcessingRule {
                             kind: processing_rule::Kind::MaskSequences,
                             name: "mask".to_string(),
                             pattern: "mask".to_string(),
-                            replace_placeholder: Some("REPLACED".to_string())
+                            replace_placeholder: Some("REPLACED".to_string()),
+                            sample_rate: None,
+                            max_per_flush: None
+                        },
+                        ProcessingRule {
+                            kind: processing_rule::Kind::Sample,
+                            name: "sample".to_string(),
+                            pattern: "DEBUG".to_string(),
+                            replace_placeholder: None,
+                            sample_rate: Some(0.1),
+                            max_per_flush: Some(100)
                         }
                     ]),
                     extension_version: Some("next".to_string()),
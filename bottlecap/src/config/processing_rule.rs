@@ -7,6 +7,8 @@ pub enum Kind {
     ExcludeAtMatch,
     IncludeAtMatch,
     MaskSequences,
+    MultiLine,
+    Sample,
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize)]
@@ -16,6 +18,10 @@ pub struct ProcessingRule {
     pub name: String,
     pub pattern: String,
     pub replace_placeholder: Option<String>,
+    /// Fraction of the matching logs kept by a `sample` rule, by `request_id`.
+    pub sample_rate: Option<f64>,
+    /// Maximum number of matching logs kept by a `sample` rule per flush.
+    pub max_per_flush: Option<u64>,
 }
 
 pub fn deserialize_processing_rules<'de, D>(
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Deserializer};
+use serde_json::Value as JsonValue;
+
+use crate::config::deserialize_list;
+
+/// Built-in rules masking sensitive data in logs.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrubbingPreset {
+    CreditCard,
+    AwsAccessKey,
+    AwsSecretKey,
+    Jwt,
+    BearerToken,
+    Email,
+    DatadogApiKey,
+}
+
+impl AsRef<str> for ScrubbingPreset {
+    fn as_ref(&self) -> &str {
+        match self {
+            ScrubbingPreset::CreditCard => "credit_card",
+            ScrubbingPreset::AwsAccessKey => "aws_access_key",
+            ScrubbingPreset::AwsSecretKey => "aws_secret_key",
+            ScrubbingPreset::Jwt => "jwt",
+            ScrubbingPreset::BearerToken => "bearer_token",
+            ScrubbingPreset::Email => "email",
+            ScrubbingPreset::DatadogApiKey => "datadog_api_key",
+        }
+    }
+}
+
+// Comma separated in the environment,
+// i.e. `DD_LOGS_CONFIG_SCRUBBING_PRESETS=credit_card,email`
+pub fn deserialize_scrubbing_presets<'de, D>(
+    deserializer: D,
+) -> Result<Vec<ScrubbingPreset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_list(deserializer, |s| s.split(',').map(str::trim).collect())?
+        .into_iter()
+        .map(|preset| {
+            serde_json::from_value(JsonValue::String(preset)).map_err(|e| {
+                serde::de::Error::custom(format!("Failed to deserialize scrubbing preset: {e}"))
+            })
+        })
+        .collect()
+}
@@ -1,3 +1,5 @@
+use serde_json::Value;
+
 use crate::telemetry::events::TelemetryEvent;
 
 #[derive(Debug)]
@@ -19,4 +21,15 @@ impl MetricEvent {
 pub enum Event {
     Metric(MetricEvent),
     Telemetry(TelemetryEvent),
+    /// Number of logs dropped because the logs buffer was full.
+    LogsDropped(usize),
+    /// Number of values masked in logs, by processing rule name.
+    LogsRedacted(String, u64),
+    /// Event which triggered an invocation, with its `request_id` when captured
+    /// by the Runtime API proxy rather than sent by the tracer.
+    InvocationPayload(Option<String>, Value),
+    /// Response of an invocation, by `request_id`.
+    InvocationResponse(String, Value),
+    /// Error reported by the runtime for an invocation, by `request_id`.
+    InvocationError(String, Value),
 }
@@ -24,6 +24,8 @@ pub mod lifecycle;
 pub mod logger;
 pub mod logs;
 pub mod metrics;
+pub mod proxy;
+pub mod retry;
 pub mod secrets;
 pub mod tags;
 pub mod telemetry;
@@ -39,6 +41,12 @@ pub const EXTENSION_ID_HEADER: &str = "Lambda-Extension-Identifier";
 pub const EXTENSION_ACCEPT_FEATURE_HEADER: &str = "Lambda-Extension-Accept-Feature";
 pub const EXTENSION_ROUTE: &str = "2020-01-01/extension";
 pub const LAMBDA_RUNTIME_SLUG: &str = "lambda";
+/// Version of the extension, set by the release build through the
+/// `EXTENSION_VERSION` environment variable.
+pub const EXTENSION_VERSION: &str = match option_env!("EXTENSION_VERSION") {
+    Some(version) if !version.is_empty() => version,
+    _ => env!("CARGO_PKG_VERSION"),
+};
 
 // todo: make sure we can override those with environment variables
 pub const DOGSTATSD_PORT: u16 = 8185;
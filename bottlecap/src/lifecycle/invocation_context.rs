@@ -6,9 +6,16 @@ use tracing::debug;
 pub struct InvocationContext {
     pub request_id: String,
     pub runtime_duration_ms: f64,
+    /// Trace id of the invocation root span, `0` when unknown.
+    pub trace_id: u64,
+    /// Span id of the invocation root span, `0` when unknown.
+    pub span_id: u64,
 }
 
+/// Contexts of the last invocations. They're only evicted by the ones of newer
+/// invocations, so that logs processed after their invocation ends still find them.
 #[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
 pub struct InvocationContextBuffer {
     buffer: VecDeque<InvocationContext>,
 }
@@ -66,6 +73,26 @@ impl InvocationContextBuffer {
             self.insert(InvocationContext {
                 request_id: request_id.to_string(),
                 runtime_duration_ms,
+                trace_id: 0,
+                span_id: 0,
+            });
+        }
+    }
+
+    pub fn add_span_ids(&mut self, request_id: &String, trace_id: u64, span_id: u64) {
+        if let Some(context) = self
+            .buffer
+            .iter_mut()
+            .find(|context| context.request_id == *request_id)
+        {
+            context.trace_id = trace_id;
+            context.span_id = span_id;
+        } else {
+            self.insert(InvocationContext {
+                request_id: request_id.to_string(),
+                runtime_duration_ms: 0.0,
+                trace_id,
+                span_id,
             });
         }
     }
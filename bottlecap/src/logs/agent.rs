@@ -1,8 +1,10 @@
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::{self, Sender};
+use tokio_util::sync::CancellationToken;
 
 use crate::events::Event;
-use crate::logs::{aggregator::Aggregator, processor::LogsProcessor};
+use crate::lifecycle::invocation_context::InvocationContextBuffer;
+use crate::logs::{aggregator::Aggregator, constants, processor::LogsProcessor};
 use crate::tags;
 use crate::telemetry::events::TelemetryEvent;
 use crate::{config, LAMBDA_RUNTIME_SLUG};
@@ -13,6 +15,7 @@ pub struct LogsAgent {
     tx: Sender<Vec<TelemetryEvent>>,
     rx: mpsc::Receiver<Vec<TelemetryEvent>>,
     processor: LogsProcessor,
+    cancel_token: CancellationToken,
 }
 
 impl LogsAgent {
@@ -21,13 +24,21 @@ impl LogsAgent {
         tags_provider: Arc<tags::provider::Provider>,
         datadog_config: Arc<config::Config>,
         event_bus: Sender<Event>,
+        invocation_context_buffer: Arc<Mutex<InvocationContextBuffer>>,
+        cancel_token: CancellationToken,
     ) -> LogsAgent {
-        let aggregator: Arc<Mutex<Aggregator>> = Arc::new(Mutex::new(Aggregator::default()));
+        let aggregator: Arc<Mutex<Aggregator>> = Arc::new(Mutex::new(Aggregator::new(
+            constants::MAX_BATCH_ENTRIES_SIZE,
+            constants::MAX_CONTENT_SIZE_BYTES,
+            constants::MAX_LOG_SIZE_BYTES,
+            datadog_config.logs_config_max_buffer_size_bytes,
+        )));
         let processor = LogsProcessor::new(
             Arc::clone(&datadog_config),
             tags_provider,
             event_bus,
             LAMBDA_RUNTIME_SLUG.to_string(),
+            invocation_context_buffer,
         );
 
         let (tx, rx) = mpsc::channel::<Vec<TelemetryEvent>>(1000);
@@ -37,12 +48,25 @@ impl LogsAgent {
             tx,
             rx,
             processor,
+            cancel_token,
         }
     }
 
     pub async fn spin(&mut self) {
-        while let Some(events) = self.rx.recv().await {
-            self.processor.process(events, &self.aggregator).await;
+        loop {
+            tokio::select! {
+                Some(events) = self.rx.recv() => {
+                    self.processor.process(events, &self.aggregator).await;
+                }
+                () = self.cancel_token.cancelled() => {
+                    // Process what was already received before shutting down
+                    while let Ok(events) = self.rx.try_recv() {
+                        self.processor.process(events, &self.aggregator).await;
+                    }
+                    self.processor.flush_pending(&self.aggregator).await;
+                    break;
+                }
+            }
         }
     }
 
@@ -5,45 +5,124 @@ use crate::logs::constants;
 
 pub struct Aggregator {
     messages: VecDeque<String>,
+    messages_size_bytes: usize,
+    failed_batches: VecDeque<Vec<u8>>,
+    failed_batches_size_bytes: usize,
+    flush_window: u64,
     max_batch_entries_size: usize,
     max_content_size_bytes: usize,
     max_log_size_bytes: usize,
+    max_buffer_size_bytes: usize,
+    max_retry_buffer_size_bytes: usize,
 }
 
 impl Default for Aggregator {
     fn default() -> Self {
         Aggregator {
             messages: VecDeque::new(),
+            messages_size_bytes: 0,
+            failed_batches: VecDeque::new(),
+            failed_batches_size_bytes: 0,
+            flush_window: 0,
             max_batch_entries_size: constants::MAX_BATCH_ENTRIES_SIZE,
             max_content_size_bytes: constants::MAX_CONTENT_SIZE_BYTES,
             max_log_size_bytes: constants::MAX_LOG_SIZE_BYTES,
+            max_buffer_size_bytes: constants::MAX_BUFFER_SIZE_BYTES,
+            max_retry_buffer_size_bytes: constants::MAX_RETRY_BUFFER_SIZE_BYTES,
         }
     }
 }
 
 impl Aggregator {
-    #[allow(dead_code)]
     #[allow(clippy::must_use_candidate)]
     pub fn new(
         max_batch_entries_size: usize,
         max_content_size_bytes: usize,
         max_log_size_bytes: usize,
+        max_buffer_size_bytes: usize,
     ) -> Self {
         Aggregator {
             messages: VecDeque::new(),
+            messages_size_bytes: 0,
+            failed_batches: VecDeque::new(),
+            failed_batches_size_bytes: 0,
+            flush_window: 0,
             max_batch_entries_size,
             max_content_size_bytes,
             max_log_size_bytes,
+            max_buffer_size_bytes,
+            max_retry_buffer_size_bytes: constants::MAX_RETRY_BUFFER_SIZE_BYTES,
         }
     }
 
-    pub fn add_batch(&mut self, logs: Vec<String>) {
+    /// Queues logs to be sent. When the buffer size is exceeded, the oldest
+    /// logs are dropped, returning how many were.
+    pub fn add_batch(&mut self, logs: Vec<String>) -> usize {
         for log in logs {
+            self.messages_size_bytes += log.len();
             self.messages.push_back(log);
         }
+
+        let mut dropped = 0;
+        while self.messages_size_bytes > self.max_buffer_size_bytes {
+            let Some(log) = self.messages.pop_front() else {
+                break;
+            };
+            self.messages_size_bytes -= log.len();
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Marks the start of a flush, logs added from now on belong to the next one.
+    pub fn start_flush_window(&mut self) {
+        self.flush_window = self.flush_window.wrapping_add(1);
+    }
+
+    #[must_use]
+    pub fn flush_window(&self) -> u64 {
+        self.flush_window
+    }
+
+    /// Number of batches waiting to be retried, served first by `get_batch`.
+    #[must_use]
+    pub fn failed_batches_count(&self) -> usize {
+        self.failed_batches.len()
+    }
+
+    /// Puts back a batch that failed to be sent, so it's retried before
+    /// any new logs. When the retry buffer is full, the oldest batches are
+    /// dropped.
+    pub fn requeue_batch(&mut self, batch: Vec<u8>) {
+        if batch.len() > self.max_retry_buffer_size_bytes {
+            warn!(
+                "Dropping {} bytes of logs, payload exceeds the retry buffer size",
+                batch.len()
+            );
+            return;
+        }
+
+        self.failed_batches_size_bytes += batch.len();
+        self.failed_batches.push_back(batch);
+
+        while self.failed_batches_size_bytes > self.max_retry_buffer_size_bytes {
+            if let Some(dropped) = self.failed_batches.pop_front() {
+                self.failed_batches_size_bytes -= dropped.len();
+                warn!(
+                    "Retry buffer is full, dropping {} bytes of logs",
+                    dropped.len()
+                );
+            }
+        }
     }
 
     pub fn get_batch(&mut self) -> Vec<u8> {
+        // Batches that failed on a previous flush go first
+        if let Some(batch) = self.failed_batches.pop_front() {
+            self.failed_batches_size_bytes -= batch.len();
+            return batch;
+        }
+
         let mut buffer: Vec<u8> = Vec::with_capacity(self.max_content_size_bytes);
         buffer.extend(b"[");
 
@@ -52,10 +131,20 @@ impl Aggregator {
             if let Some(log) = self.messages.pop_front() {
                 // Check if the buffer will be full after adding the log
                 if buffer.len() + log.len() > self.max_content_size_bytes {
+                    // It wouldn't fit in any batch, keeping it would block the queue
+                    if buffer.len() == 1 {
+                        warn!(
+                            "Dropping log of {} bytes, it exceeds the maximum payload size",
+                            log.len()
+                        );
+                        self.messages_size_bytes -= log.len();
+                        continue;
+                    }
                     // Put the log back in the queue
                     self.messages.push_front(log);
                     break;
                 }
+                self.messages_size_bytes -= log.len();
 
                 if log.len() > self.max_log_size_bytes {
                     warn!(
@@ -106,6 +195,7 @@ mod tests {
             service: "service".to_string(),
             tags: "tags".to_string(),
             source: "source".to_string(),
+            attributes: serde_json::Map::new(),
         };
         let serialized_log = serde_json::to_string(&log).unwrap();
         aggregator.add_batch(vec![serialized_log.clone()]);
@@ -130,6 +220,7 @@ mod tests {
             service: "service".to_string(),
             tags: "tags".to_string(),
             source: "source".to_string(),
+            attributes: serde_json::Map::new(),
         };
         let serialized_log = serde_json::to_string(&log).unwrap();
         aggregator.add_batch(vec![serialized_log.clone()]);
@@ -141,7 +232,7 @@ mod tests {
 
     #[test]
     fn test_get_batch_full_entries() {
-        let mut aggregator = Aggregator::new(2, 1_024, 1_024);
+        let mut aggregator = Aggregator::new(2, 1_024, 1_024, 4_096);
         let log = IntakeLog {
             message: Message {
                 message: "test".to_string(),
@@ -156,6 +247,7 @@ mod tests {
             service: "service".to_string(),
             tags: "tags".to_string(),
             source: "source".to_string(),
+            attributes: serde_json::Map::new(),
         };
         // Add 3 logs
         let serialized_log = serde_json::to_string(&log).unwrap();
@@ -181,7 +273,7 @@ mod tests {
 
     #[test]
     fn test_get_batch_full_payload() {
-        let mut aggregator = Aggregator::new(2, 256, 1_024);
+        let mut aggregator = Aggregator::new(2, 256, 1_024, 4_096);
         let log = IntakeLog {
             message: Message {
                 message: "test".to_string(),
@@ -196,6 +288,7 @@ mod tests {
             service: "service".to_string(),
             tags: "tags".to_string(),
             source: "source".to_string(),
+            attributes: serde_json::Map::new(),
         };
         // Add 2 logs
         let serialized_log = serde_json::to_string(&log).unwrap();
@@ -216,4 +309,61 @@ mod tests {
         // so we never send it, but we still keep it in the queue.
         assert_eq!(aggregator.messages.len(), 1);
     }
+
+    #[test]
+    fn test_requeue_batch() {
+        let mut aggregator = Aggregator::default();
+        aggregator.add_batch(vec!["{\"message\":\"new\"}".to_string()]);
+        aggregator.requeue_batch(b"[{\"message\":\"failed\"}]".to_vec());
+
+        // Failed batches are sent before new logs
+        assert_eq!(aggregator.get_batch(), b"[{\"message\":\"failed\"}]");
+        assert_eq!(aggregator.get_batch(), b"[{\"message\":\"new\"}]");
+        assert_eq!(aggregator.get_batch(), b"[]");
+    }
+
+    #[test]
+    fn test_requeue_batch_drops_oldest() {
+        let mut aggregator = Aggregator {
+            max_retry_buffer_size_bytes: 10,
+            ..Aggregator::default()
+        };
+        aggregator.requeue_batch(b"[\"1111\"]".to_vec());
+        aggregator.requeue_batch(b"[\"2222\"]".to_vec());
+        // Bigger than the whole buffer
+        aggregator.requeue_batch(b"[\"33333333\"]".to_vec());
+
+        assert_eq!(aggregator.failed_batches.len(), 1);
+        assert_eq!(aggregator.failed_batches_size_bytes, 8);
+        assert_eq!(aggregator.get_batch(), b"[\"2222\"]");
+        assert_eq!(aggregator.failed_batches_size_bytes, 0);
+    }
+
+    #[test]
+    fn test_add_batch_drops_oldest_over_buffer_size() {
+        let mut aggregator = Aggregator::new(10, 1_024, 1_024, 25);
+        let dropped = aggregator.add_batch(vec![
+            "\"first-log\"".to_string(),
+            "\"second-log\"".to_string(),
+        ]);
+        assert_eq!(dropped, 0);
+        assert_eq!(aggregator.messages_size_bytes, 23);
+
+        let dropped = aggregator.add_batch(vec!["\"third-and-longest-log\"".to_string()]);
+        assert_eq!(dropped, 2);
+        assert_eq!(aggregator.messages.len(), 1);
+        assert_eq!(aggregator.get_batch(), b"[\"third-and-longest-log\"]");
+        assert_eq!(aggregator.messages_size_bytes, 0);
+    }
+
+    #[test]
+    fn test_get_batch_drops_log_bigger_than_payload() {
+        let mut aggregator = Aggregator::new(2, 256, 1_024, 4_096);
+        let big_log = format!("\"{}\"", "a".repeat(256));
+        aggregator.add_batch(vec![big_log, "\"next-log\"".to_string()]);
+
+        assert_eq!(aggregator.get_batch(), b"[\"next-log\"]");
+        assert_eq!(aggregator.messages.len(), 0);
+        assert_eq!(aggregator.messages_size_bytes, 0);
+    }
 }
@@ -8,3 +8,19 @@ pub const MAX_LOG_SIZE_BYTES: usize = 1_024 * 1_024;
 
 /// Maximum logs array size accepted.
 pub const MAX_BATCH_ENTRIES_SIZE: usize = 1000;
+
+/// Maximum size in bytes of a log aggregated by a `multi_line` rule,
+/// following lines start a new log.
+pub const MAX_MULTI_LINE_SIZE_BYTES: usize = 256 * 1_024;
+
+/// Maximum size in bytes of the payloads kept in memory after a
+/// failed flush, to be retried on the next one.
+pub const MAX_RETRY_BUFFER_SIZE_BYTES: usize = 2 * MAX_CONTENT_SIZE_BYTES;
+
+/// Maximum size in bytes of the logs waiting to be flushed, before
+/// dropping the oldest ones.
+pub const MAX_BUFFER_SIZE_BYTES: usize = 2 * MAX_CONTENT_SIZE_BYTES;
+
+/// Prefix of the logs written to stdout by the local sink, which come
+/// back through the Telemetry API and must not be processed again.
+pub const LOCAL_SINK_PREFIX: &str = "DD_EXTENSION | LOCAL_SINK | ";
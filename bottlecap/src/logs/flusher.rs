@@ -1,77 +1,254 @@
+use crate::config::{compression_kind::CompressionKind, local_sink::LocalSink, Config};
 use crate::logs::aggregator::Aggregator;
+use crate::logs::constants::LOCAL_SINK_PREFIX;
+use crate::retry::{self, Attempt, MAX_ATTEMPTS};
+use flate2::{write::GzEncoder, Compression};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::task::JoinSet;
 use tracing::{debug, error};
 
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct Flusher {
     api_key: String,
-    site: String,
     client: reqwest::Client,
     aggregator: Arc<Mutex<Aggregator>>,
+    config: Arc<Config>,
 }
 
-#[allow(clippy::await_holding_lock)]
 impl Flusher {
-    pub fn new(api_key: String, aggregator: Arc<Mutex<Aggregator>>, site: String) -> Self {
-        let client = reqwest::Client::new();
+    pub fn new(api_key: String, aggregator: Arc<Mutex<Aggregator>>, config: Arc<Config>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_else(|e| {
+                error!("Failed to build logs client, using default: {e}");
+                reqwest::Client::new()
+            });
         Flusher {
             api_key,
-            site,
             client,
             aggregator,
+            config,
         }
     }
     pub async fn flush(&self) {
-        let mut guard = self.aggregator.lock().expect("lock poisoned");
+        let (batches, retried_batches) = {
+            let mut guard = self.aggregator.lock().expect("lock poisoned");
+            guard.start_flush_window();
+            // Batches retried from a previous flush were already written locally
+            let retried_batches = guard.failed_batches_count();
+            let mut batches = Vec::new();
+            // It could be an empty JSON array: []
+            let mut logs = guard.get_batch();
+            while logs.len() > 2 {
+                batches.push(logs);
+                logs = guard.get_batch();
+            }
+            (batches, retried_batches)
+        };
+
+        let local_write = self.config.logs_config_local_sink.is_some().then(|| {
+            let config = Arc::clone(&self.config);
+            let new_batches = batches.get(retried_batches..).unwrap_or_default().to_vec();
+            tokio::task::spawn_blocking(move || {
+                let Some(sink) = &config.logs_config_local_sink else {
+                    return;
+                };
+                for logs in new_batches {
+                    if let Err(e) = write_local(sink, &logs) {
+                        error!("Failed to write logs to the local sink: {e}");
+                    }
+                }
+            })
+        });
+
         let mut set = JoinSet::new();
-        // It could be an empty JSON array: []
-        let mut logs = guard.get_batch();
-        while logs.len() > 2 {
-            let api_key = self.api_key.clone();
-            let site = self.site.clone();
-            let cloned_client = self.client.clone();
-            set.spawn(async move { Self::send(cloned_client, api_key, site, logs).await });
-            logs = guard.get_batch();
+        if !self.config.logs_config_local_sink_only {
+            for logs in batches {
+                let api_key = self.api_key.clone();
+                let config = Arc::clone(&self.config);
+                let cloned_client = self.client.clone();
+                set.spawn(async move { Self::send(cloned_client, api_key, config, logs).await });
+            }
+        }
+        if let Some(local_write) = local_write {
+            if let Err(e) = local_write.await {
+                error!("Failed to write logs to the local sink: {e}");
+            }
         }
-        drop(guard);
         while let Some(res) = set.join_next().await {
-            if let Err(e) = res {
-                debug!("Failed to send logs to datadog: {}", e);
+            match res {
+                Ok(Ok(())) => {}
+                Ok(Err(failed)) => {
+                    let mut guard = self.aggregator.lock().expect("lock poisoned");
+                    guard.requeue_batch(failed);
+                }
+                Err(e) => {
+                    debug!("Failed to send logs to datadog: {}", e);
+                }
             }
         }
     }
 
+    /// Sends a payload to the logs intake, retrying on server errors, throttling
+    /// and timeouts. Returns the payload back if every attempt failed.
     async fn send(
         client: reqwest::Client,
         api_key: String,
-        site: String,
+        config: Arc<Config>,
         data: Vec<u8>,
-    ) -> Result<(), String> {
-        let url = format!("https://http-intake.logs.{site}/api/v2/logs");
-
+    ) -> Result<(), Vec<u8>> {
         // It could be an empty JSON array: []
-        if data.len() > 2 {
-            let resp: Result<reqwest::Response, reqwest::Error> = client
+        if data.len() <= 2 {
+            return Ok(());
+        }
+
+        let url = format!("https://http-intake.logs.{}/api/v2/logs", config.site);
+        let compression = if config.logs_config_use_compression {
+            match compress(
+                config.logs_config_compression_kind,
+                config.logs_config_compression_level,
+                &data,
+            ) {
+                Ok(compressed) => Some((config.logs_config_compression_kind, compressed)),
+                Err(e) => {
+                    error!("Failed to compress logs, sending them uncompressed: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let outcome = retry::send_with_backoff(|attempt| {
+            let mut request = client
                 .post(&url)
-                .header("DD-API-KEY", api_key)
+                .header("DD-API-KEY", &api_key)
                 .header("DD-PROTOCOL", "agent-json")
-                .header("Content-Type", "application/json")
-                .body(data)
-                .send()
-                .await;
-
-            match resp {
-                Ok(resp) => {
-                    if resp.status() != 202 {
-                        debug!("Failed to send logs to datadog: {}", resp.status());
+                .header("Content-Type", "application/json");
+            request = match &compression {
+                Some((kind, body)) => request
+                    .header("Content-Encoding", kind.as_ref())
+                    .body(body.clone()),
+                None => request.body(data.clone()),
+            };
+
+            async move {
+                match request.send().await {
+                    Ok(resp) if resp.status().is_success() => Attempt::Sent,
+                    Ok(resp) => {
+                        let status = resp.status();
+                        if !retry::is_retryable(status.as_u16()) {
+                            error!("Failed to send logs to datadog, dropping them: {status}");
+                            return Attempt::Rejected;
+                        }
+                        debug!("Failed to send logs to datadog on attempt {attempt}: {status}");
+                        Attempt::Failed
+                    }
+                    Err(e) => {
+                        debug!("Failed to send logs to datadog on attempt {attempt}: {e}");
+                        Attempt::Failed
                     }
-                }
-                Err(e) => {
-                    error!("Failed to send logs to datadog: {}", e);
                 }
             }
+        })
+        .await;
+        if outcome != Attempt::Failed {
+            return Ok(());
         }
 
-        Ok(())
+        error!("Failed to send logs to datadog after {MAX_ATTEMPTS} attempts, will retry on next flush");
+        Err(data)
+    }
+}
+
+/// Writes each log of a payload as a JSON line to the local sink.
+fn write_local(sink: &LocalSink, data: &[u8]) -> std::io::Result<()> {
+    let lines = format_local(sink, data)?;
+    match sink {
+        LocalSink::Stdout => std::io::stdout().lock().write_all(lines.as_bytes()),
+        LocalSink::File(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(lines.as_bytes()),
+    }
+}
+
+fn format_local(sink: &LocalSink, data: &[u8]) -> std::io::Result<String> {
+    let logs: Vec<serde_json::Value> = serde_json::from_slice(data)?;
+    let mut lines = String::new();
+    for log in logs {
+        // Our stdout is forwarded as extension logs, which must not be sent again
+        if *sink == LocalSink::Stdout {
+            lines.push_str(LOCAL_SINK_PREFIX);
+        }
+        lines.push_str(&log.to_string());
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+fn compress(kind: CompressionKind, level: i32, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    match kind {
+        CompressionKind::Gzip => {
+            let mut encoder = GzEncoder::new(
+                Vec::new(),
+                Compression::new(level.clamp(0, 9).unsigned_abs()),
+            );
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+        CompressionKind::Zstd => zstd::bulk::compress(data, level),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_compress_gzip() {
+        let data = br#"[{"message":"hello"}]"#;
+        let compressed = compress(CompressionKind::Gzip, 6, data).unwrap();
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_compress_zstd() {
+        let data = br#"[{"message":"hello"}]"#;
+        let compressed = compress(CompressionKind::Zstd, 3, data).unwrap();
+
+        let decompressed = zstd::bulk::decompress(&compressed, data.len()).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn test_format_local() {
+        let data = br#"[{"message":"hello"},{"message":"world"}]"#;
+
+        let lines = format_local(&LocalSink::File("logs.jsonl".into()), data).unwrap();
+        assert_eq!(lines, "{\"message\":\"hello\"}\n{\"message\":\"world\"}\n");
+
+        let lines = format_local(&LocalSink::Stdout, data).unwrap();
+        assert_eq!(
+            lines,
+            format!(
+                "{LOCAL_SINK_PREFIX}{{\"message\":\"hello\"}}\n{LOCAL_SINK_PREFIX}{{\"message\":\"world\"}}\n"
+            )
+        );
     }
 }
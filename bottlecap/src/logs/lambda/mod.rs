@@ -1,4 +1,5 @@
 use serde::Serialize;
+use serde_json::{Map, Value};
 
 pub mod processor;
 
@@ -15,6 +16,9 @@ pub struct IntakeLog {
     pub tags: String,
     #[serde(rename(serialize = "ddsource"))]
     pub source: String,
+    /// Fields extracted from structured logs, sent as top-level attributes.
+    #[serde(flatten)]
+    pub attributes: Map<String, Value>,
 }
 
 ///
@@ -1,20 +1,61 @@
+use chrono::Utc;
+use regex::Regex;
+use serde_json::{Map, Value};
 use std::error::Error;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::sync::mpsc::Sender;
 
-use tracing::error;
+use tracing::{error, warn};
 
-use crate::config;
+use crate::config::{self, processing_rule};
 use crate::events::Event;
-use crate::lifecycle::invocation_context::InvocationContext;
+use crate::lifecycle::invocation_context::{InvocationContext, InvocationContextBuffer};
 use crate::logs::aggregator::Aggregator;
+use crate::logs::constants::{LOCAL_SINK_PREFIX, MAX_LOG_SIZE_BYTES, MAX_MULTI_LINE_SIZE_BYTES};
 use crate::logs::processor::{Processor, Rule};
 use crate::tags::provider;
-use crate::telemetry::events::{TelemetryEvent, TelemetryRecord};
+use crate::telemetry::events::{Status, TelemetryEvent, TelemetryRecord};
 use crate::LAMBDA_RUNTIME_SLUG;
 
 use crate::logs::lambda::{IntakeLog, Message};
 
+/// Attribute holding the raw text of a structured log, when asked to keep it.
+const ORIGINAL_JSON_ATTRIBUTE: &str = "original_json";
+/// Top-level keys of an `IntakeLog` which structured log fields can't override.
+const RESERVED_ATTRIBUTES: [&str; 5] = ["message", "hostname", "service", "ddtags", "ddsource"];
+/// Structured log fields overriding the source, service and tags of the log.
+const SOURCE_ATTRIBUTE: &str = "ddsource";
+const SERVICE_ATTRIBUTE: &str = "service";
+const TAGS_ATTRIBUTE: &str = "ddtags";
+/// Attributes used by Datadog to correlate logs with traces.
+const TRACE_ID_ATTRIBUTE: &str = "dd.trace_id";
+const SPAN_ID_ATTRIBUTE: &str = "dd.span_id";
+/// Attribute marking the logs emitted before any invocation.
+const PHASE_ATTRIBUTE: &str = "lambda.phase";
+const INIT_PHASE: &str = "init";
+/// Attributes correlating the chunks of a log split for being too big.
+const SPLIT_ID_ATTRIBUTE: &str = "dd.split_id";
+const SPLIT_INDEX_ATTRIBUTE: &str = "dd.split_index";
+const SPLIT_TOTAL_ATTRIBUTE: &str = "dd.split_total";
+/// Upper bound of the size taken by the split attributes once serialized.
+const SPLIT_ATTRIBUTES_MAX_SIZE_BYTES: usize = 256;
+/// Size of a control character escaped as `\uXXXX`.
+const MAX_ESCAPED_CHAR_SIZE_BYTES: usize = 6;
+/// Trace context injected by tracers in plain text logs, i.e.
+/// `[dd.service=svc dd.env=prod dd.trace_id=123 dd.span_id=456]`.
+const TEXT_TRACE_CONTEXT_PATTERN: &str = r#"dd\.trace_id="?(\d+)"?\s+dd\.span_id="?(\d+)"?"#;
+
+/// Fields of a structured (JSON object) log record.
+type StructuredFields = Map<String, Value>;
+
+fn text_trace_context_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(TEXT_TRACE_CONTEXT_PATTERN).expect("invalid trace context regex")
+    })
+}
+
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone, Debug)]
 pub struct LambdaProcessor {
@@ -23,10 +64,30 @@ pub struct LambdaProcessor {
     tags: String,
     // Global Processing Rules
     rules: Option<Vec<Rule>>,
+    // Pattern matching the start of a new log, from the `multi_line` rule
+    multi_line_pattern: Option<Regex>,
+    // Function log being aggregated by the `multi_line` rule
+    multi_line_event: Option<TelemetryEvent>,
+    // Keep the raw text of structured logs as an attribute
+    keep_original_json: bool,
+    // Attributes added to every log
+    attributes: serde_json::Map<String, Value>,
+    // Correlate logs with traces
+    logs_injection: bool,
     // Current Invocation Context
     invocation_context: InvocationContext,
-    // Logs which don't have a `request_id`
-    orphan_logs: Vec<IntakeLog>,
+    // Invocation contexts known by the extension, holding their root span ids
+    invocation_context_buffer: Arc<Mutex<InvocationContextBuffer>>,
+    // Logs which don't have a `request_id`, with the fields of the structured ones
+    orphan_logs: Vec<(IntakeLog, Option<StructuredFields>)>,
+    // Whether an invocation started, logs before it are from the init phase
+    seen_invocation: bool,
+    // Logs bigger than this are split
+    max_log_size_bytes: usize,
+    // Logs split so far, to correlate their chunks
+    split_count: u64,
+    // Aggregator flush window the `sample` rules rate limits are counted in
+    flush_window: u64,
     // Main event bus
     event_bus: Sender<Event>,
 }
@@ -39,23 +100,58 @@ impl LambdaProcessor {
         tags_provider: Arc<provider::Provider>,
         datadog_config: Arc<config::Config>,
         event_bus: Sender<Event>,
+        invocation_context_buffer: Arc<Mutex<InvocationContextBuffer>>,
     ) -> Self {
         let service = datadog_config.service.clone().unwrap_or_default();
         let tags = tags_provider.get_tags_string();
         let function_arn = tags_provider.get_canonical_id().unwrap_or_default();
 
         let processing_rules = &datadog_config.logs_config_processing_rules;
-        let rules = LambdaProcessor::compile_rules(processing_rules);
+        let mut rules = LambdaProcessor::compile_rules(processing_rules).unwrap_or_default();
+        rules.extend(LambdaProcessor::compile_scrubbing_presets(
+            &datadog_config.logs_config_scrubbing_presets,
+        ));
+        let rules = (!rules.is_empty()).then_some(rules);
+        let attributes = datadog_config
+            .logs_config_attributes
+            .iter()
+            .filter(|(key, _)| {
+                let reserved = RESERVED_ATTRIBUTES.contains(&key.as_str());
+                if reserved {
+                    warn!("Ignoring reserved attribute `{key}` from the logs attributes");
+                }
+                !reserved
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let multi_line_pattern = rules.as_ref().and_then(|rules| {
+            rules
+                .iter()
+                .find(|rule| rule.kind == processing_rule::Kind::MultiLine)
+                .map(|rule| rule.regex.clone())
+        });
         LambdaProcessor {
             function_arn,
             service,
             tags,
             rules,
+            multi_line_pattern,
+            multi_line_event: None,
+            keep_original_json: datadog_config.logs_config_keep_original_json,
+            attributes,
+            logs_injection: datadog_config.logs_injection,
             invocation_context: InvocationContext {
                 request_id: String::new(),
                 runtime_duration_ms: 0.0,
+                trace_id: 0,
+                span_id: 0,
             },
+            invocation_context_buffer,
             orphan_logs: Vec::new(),
+            seen_invocation: false,
+            max_log_size_bytes: MAX_LOG_SIZE_BYTES,
+            split_count: 0,
+            flush_window: 0,
             event_bus,
         }
     }
@@ -64,9 +160,15 @@ impl LambdaProcessor {
     async fn get_message(&mut self, event: TelemetryEvent) -> Result<Message, Box<dyn Error>> {
         let copy = event.clone();
         match event.record {
-            TelemetryRecord::Function(v) | TelemetryRecord::Extension(v) => {
+            // Logs written to stdout by the local sink come back as extension logs
+            TelemetryRecord::Extension(Value::String(s)) if s.starts_with(LOCAL_SINK_PREFIX) => {
+                Err("Log written by the local sink, skipping it".into())
+            }
+            TelemetryRecord::Function(v)
+            | TelemetryRecord::Extension(v)
+            | TelemetryRecord::Listener(v) => {
+                // Structured records were taken out of the event by `make_log`
                 let message = match v {
-                    serde_json::Value::Object(obj) => Some(serde_json::to_string(&obj).unwrap_or_default()),
                     serde_json::Value::String(s) => Some(s),
                     _ => None,
                 };
@@ -87,6 +189,10 @@ impl LambdaProcessor {
                 runtime_version_arn,
                 .. // TODO: check if we could do something with this metrics: `initialization_type` and `phase`
             } => {
+                if let Err(e) = self.event_bus.send(Event::Telemetry(copy)).await {
+                    error!("Failed to send PlatformInitStart to the main event bus: {}", e);
+                }
+
                 let rv = runtime_version.unwrap_or("?".to_string()); // TODO: check what does containers display
                 let rv_arn = runtime_version_arn.unwrap_or("?".to_string()); // TODO: check what do containers display
                 Ok(Message::new(
@@ -104,6 +210,36 @@ impl LambdaProcessor {
                 // We don't need to process any log for this event
                 Err("Unsupported event type".into())
             }
+            TelemetryRecord::PlatformRestoreStart { runtime_version, runtime_version_arn } => {
+                if let Err(e) = self.event_bus.send(Event::Telemetry(copy)).await {
+                    error!("Failed to send PlatformRestoreStart to the main event bus: {}", e);
+                }
+
+                let rv = runtime_version.unwrap_or("?".to_string());
+                let rv_arn = runtime_version_arn.unwrap_or("?".to_string());
+                Ok(Message::new(
+                    format!("RESTORE_START Runtime Version: {rv} Runtime Version ARN: {rv_arn}"),
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                ))
+            },
+            TelemetryRecord::PlatformRestoreReport { status, metrics, .. } => {
+                if let Err(e) = self.event_bus.send(Event::Telemetry(copy)).await {
+                    error!("Failed to send PlatformRestoreReport to the main event bus: {}", e);
+                }
+
+                let mut message = Message::new(
+                    format!("RESTORE_REPORT Restore Duration: {} ms", metrics.duration_ms),
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                );
+                if status != Status::Success {
+                    message.status = "error".to_string();
+                }
+                Ok(message)
+            },
             // This is the first log where `request_id` is available
             // So we set it here and use it in the unprocessed and following logs.
             TelemetryRecord::PlatformStart {
@@ -115,6 +251,7 @@ impl LambdaProcessor {
                 }
                 // Set request_id for unprocessed and future logs
                 self.invocation_context.request_id.clone_from(&request_id);
+                self.seen_invocation = true;
 
                 let version = version.unwrap_or("$LATEST".to_string());
                 Ok(Message::new(
@@ -177,16 +314,65 @@ impl LambdaProcessor {
                     event.time.timestamp_millis(),
                 ))
             },
-            // TODO: PlatformInitRuntimeDone
-            // TODO: PlatformInitReport
-            // TODO: PlatformExtension
-            // TODO: PlatformTelemetrySubscription
-            // TODO: PlatformLogsDropped
-            _ => Err("Unsupported event type".into()),
+            TelemetryRecord::PlatformInitRuntimeDone { status, error_type, .. } => {
+                if let Err(e) = self.event_bus.send(Event::Telemetry(copy)).await {
+                    error!("Failed to send PlatformInitRuntimeDone to the main event bus: {}", e);
+                }
+
+                let mut message = format!("INIT_RUNTIME_DONE Status: {}", format!("{status:?}").to_lowercase());
+                if let Some(error_type) = error_type {
+                    message = format!("{message} Error Type: {error_type}");
+                }
+
+                let mut message = Message::new(
+                    message,
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                );
+                if status != Status::Success {
+                    message.status = "error".to_string();
+                }
+                Ok(message)
+            },
+            TelemetryRecord::PlatformExtension { name, state, events } => {
+                Ok(Message::new(
+                    format!("EXTENSION Name: {name} State: {state} Events: [{}]", events.join(", ")),
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                ))
+            },
+            TelemetryRecord::PlatformTelemetrySubscription { name, state, types } => {
+                Ok(Message::new(
+                    format!("TELEMETRY Name: {name} State: {state} Types: [{}]", types.join(", ")),
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                ))
+            },
+            TelemetryRecord::PlatformLogsDropped { reason, dropped_records, dropped_bytes } => {
+                if let Err(e) = self.event_bus.send(Event::Telemetry(copy)).await {
+                    error!("Failed to send PlatformLogsDropped to the main event bus: {}", e);
+                }
+
+                let mut message = Message::new(
+                    format!("Lambda dropped {dropped_records} log records ({dropped_bytes} bytes) before they reached the extension: {reason}"),
+                    None,
+                    self.function_arn.clone(),
+                    event.time.timestamp_millis(),
+                );
+                message.status = "warn".to_string();
+                Ok(message)
+            },
         }
     }
 
-    fn get_intake_log(&mut self, mut lambda_message: Message) -> Result<IntakeLog, Box<dyn Error>> {
+    fn get_intake_log(
+        &mut self,
+        mut lambda_message: Message,
+        fields: Option<StructuredFields>,
+    ) -> Result<(IntakeLog, Option<StructuredFields>), Box<dyn Error>> {
         let request_id = match lambda_message.lambda.request_id {
             // Log already has a `request_id`
             Some(request_id) => Some(request_id.clone()),
@@ -197,26 +383,245 @@ impl LambdaProcessor {
 
         lambda_message.lambda.request_id = request_id;
 
-        let log = IntakeLog {
+        let mut log = IntakeLog {
             hostname: self.function_arn.clone(),
             source: LAMBDA_RUNTIME_SLUG.to_string(),
             service: self.service.clone(),
             tags: self.tags.clone(),
             message: lambda_message,
+            attributes: self.attributes.clone(),
         };
 
         if log.message.lambda.request_id.is_some() {
-            Ok(log)
+            Ok((log, fields))
         } else {
             // We haven't seen a `request_id`, this is an orphan log
-            self.orphan_logs.push(log);
+            if !self.seen_invocation {
+                log.attributes.insert(
+                    PHASE_ATTRIBUTE.to_string(),
+                    Value::String(INIT_PHASE.to_string()),
+                );
+            }
+            self.orphan_logs.push((log, fields));
             Err("No request_id available, queueing for later".into())
         }
     }
 
-    async fn make_log(&mut self, event: TelemetryEvent) -> Result<IntakeLog, Box<dyn Error>> {
+    /// Takes the fields of a structured (JSON object) record out of its event, so
+    /// that they're expanded as they are rather than from their text.
+    fn take_structured_fields(event: &mut TelemetryEvent) -> Option<StructuredFields> {
+        let (TelemetryRecord::Function(record)
+        | TelemetryRecord::Extension(record)
+        | TelemetryRecord::Listener(record)) = &mut event.record
+        else {
+            return None;
+        };
+        if !record.is_object() {
+            return None;
+        }
+        match std::mem::replace(record, Value::String(String::new())) {
+            Value::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// Parses the fields of a text log holding a JSON object.
+    fn parse_structured_message(message: &str) -> Option<StructuredFields> {
+        if !message.trim_start().starts_with('{') {
+            return None;
+        }
+        match serde_json::from_str::<Value>(message) {
+            Ok(Value::Object(fields)) => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// Expands a structured (JSON object) log: its `message` or `msg` field becomes
+    /// the log message, its `ddsource`, `service` and `ddtags` fields override the
+    /// ones of the function, and the rest of its fields become top-level attributes.
+    ///
+    /// Runs after the processing rules, so masking also applies to structured fields.
+    fn expand_structured_log(&self, log: &mut IntakeLog, mut fields: StructuredFields) {
+        let message = match fields.remove("message").or_else(|| fields.remove("msg")) {
+            Some(Value::String(s)) => s,
+            Some(Value::Null) | None => String::new(),
+            Some(v) => v.to_string(),
+        };
+        let original = std::mem::replace(&mut log.message.message, message);
+
+        if let Some(source) = take_non_empty_string(&mut fields, SOURCE_ATTRIBUTE) {
+            log.source = source;
+        }
+        if let Some(service) = take_non_empty_string(&mut fields, SERVICE_ATTRIBUTE) {
+            log.service = service;
+        }
+        if let Some(tags) = take_non_empty_string(&mut fields, TAGS_ATTRIBUTE) {
+            // Tags from the log add up to the function ones
+            log.tags = if log.tags.is_empty() {
+                tags
+            } else {
+                format!("{},{tags}", log.tags)
+            };
+        }
+
+        for (key, value) in fields {
+            if RESERVED_ATTRIBUTES.contains(&key.as_str()) {
+                continue;
+            }
+            log.attributes.insert(key, value);
+        }
+
+        if self.keep_original_json {
+            log.attributes
+                .insert(ORIGINAL_JSON_ATTRIBUTE.to_string(), Value::String(original));
+        }
+    }
+
+    /// Sets the trace correlation attributes from the ids injected by the tracer,
+    /// either in a structured log or in its text. Falls back to the root span of
+    /// the invocation, if the extension knows it.
+    fn add_trace_context(&self, log: &mut IntakeLog) {
+        if !self.logs_injection {
+            return;
+        }
+
+        // Ids taken from a nested `dd` object are written back into it
+        let nested = !log.attributes.contains_key(TRACE_ID_ATTRIBUTE)
+            && log
+                .attributes
+                .get("dd")
+                .and_then(Value::as_object)
+                .is_some_and(|dd| dd.contains_key("trace_id"));
+        let ids = Self::take_structured_trace_context(log)
+            .or_else(|| Self::parse_text_trace_context(&log.message.message))
+            .or_else(|| self.get_root_span_context(log.message.lambda.request_id.as_ref()));
+
+        match log.attributes.get_mut("dd") {
+            Some(Value::Object(dd)) if nested => {
+                if let Some(ids) = ids {
+                    Self::insert_trace_context(dd, ids, "trace_id", "span_id");
+                } else if dd.is_empty() {
+                    log.attributes.remove("dd");
+                }
+            }
+            _ => {
+                if let Some(ids) = ids {
+                    Self::insert_trace_context(
+                        &mut log.attributes,
+                        ids,
+                        TRACE_ID_ATTRIBUTE,
+                        SPAN_ID_ATTRIBUTE,
+                    );
+                }
+            }
+        }
+    }
+
+    fn insert_trace_context(
+        attributes: &mut serde_json::Map<String, Value>,
+        (trace_id, span_id): (String, Option<String>),
+        trace_id_key: &str,
+        span_id_key: &str,
+    ) {
+        attributes.insert(trace_id_key.to_string(), Value::String(trace_id));
+        if let Some(span_id) = span_id {
+            attributes.insert(span_id_key.to_string(), Value::String(span_id));
+        }
+    }
+
+    /// Takes the trace context out of a structured log, either from the
+    /// `dd.trace_id` and `dd.span_id` fields, or from a nested `dd` object.
+    fn take_structured_trace_context(log: &mut IntakeLog) -> Option<(String, Option<String>)> {
+        fn id_to_string(id: Value) -> Option<String> {
+            match id {
+                Value::String(s) if !s.is_empty() => Some(s),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            }
+        }
+
+        if let Some(trace_id) = log.attributes.remove(TRACE_ID_ATTRIBUTE) {
+            let span_id = log.attributes.remove(SPAN_ID_ATTRIBUTE);
+            return id_to_string(trace_id).map(|t| (t, span_id.and_then(id_to_string)));
+        }
+
+        let Some(Value::Object(dd)) = log.attributes.get_mut("dd") else {
+            return None;
+        };
+        let trace_id = dd.remove("trace_id")?;
+        let span_id = dd.remove("span_id");
+        id_to_string(trace_id).map(|t| (t, span_id.and_then(id_to_string)))
+    }
+
+    fn parse_text_trace_context(message: &str) -> Option<(String, Option<String>)> {
+        if !message.contains(TRACE_ID_ATTRIBUTE) {
+            return None;
+        }
+        let captures = text_trace_context_regex().captures(message)?;
+        Some((captures[1].to_string(), Some(captures[2].to_string())))
+    }
+
+    fn get_root_span_context(
+        &self,
+        request_id: Option<&String>,
+    ) -> Option<(String, Option<String>)> {
+        let buffer = self
+            .invocation_context_buffer
+            .lock()
+            .expect("lock poisoned");
+        let context = buffer.get(request_id?)?;
+        if context.trace_id == 0 {
+            return None;
+        }
+        Some((
+            context.trace_id.to_string(),
+            (context.span_id != 0).then(|| context.span_id.to_string()),
+        ))
+    }
+
+    /// Aggregates function log lines into a single log, i.e. a stack trace, by merging
+    /// every line which doesn't match the `multi_line` rule pattern into the previous one.
+    ///
+    /// Any other event ends the aggregation, so logs are never merged across
+    /// invocations, nor with the logs of the TCP and HTTP listeners, which are
+    /// already whole. Returns the events ready to be processed, in order.
+    fn aggregate_multi_line(&mut self, event: TelemetryEvent) -> Vec<TelemetryEvent> {
+        let Some(pattern) = &self.multi_line_pattern else {
+            return vec![event];
+        };
+
+        if let TelemetryRecord::Function(Value::String(line)) = &event.record {
+            if let Some(TelemetryEvent {
+                record: TelemetryRecord::Function(Value::String(previous)),
+                ..
+            }) = &mut self.multi_line_event
+            {
+                if !pattern.is_match(line)
+                    && previous.len() + line.len() < MAX_MULTI_LINE_SIZE_BYTES
+                {
+                    if !previous.ends_with('\n') {
+                        previous.push('\n');
+                    }
+                    previous.push_str(line);
+                    return Vec::new();
+                }
+            }
+
+            return self.multi_line_event.replace(event).into_iter().collect();
+        }
+
+        let mut ready: Vec<TelemetryEvent> = self.multi_line_event.take().into_iter().collect();
+        ready.push(event);
+        ready
+    }
+
+    async fn make_log(
+        &mut self,
+        mut event: TelemetryEvent,
+    ) -> Result<(IntakeLog, Option<StructuredFields>), Box<dyn Error>> {
+        let fields = Self::take_structured_fields(&mut event);
         match self.get_message(event).await {
-            Ok(lambda_message) => self.get_intake_log(lambda_message),
+            Ok(lambda_message) => self.get_intake_log(lambda_message, fields),
             // TODO: Check what to do when we can't process the event
             Err(e) => Err(e),
         }
@@ -226,41 +631,279 @@ impl LambdaProcessor {
         &mut self,
         events: Vec<TelemetryEvent>,
         aggregator: &Arc<Mutex<Aggregator>>,
+    ) {
+        let events: Vec<TelemetryEvent> = events
+            .into_iter()
+            .flat_map(|event| self.aggregate_multi_line(event))
+            .collect();
+
+        self.process_events(events, false, aggregator).await;
+    }
+
+    /// Processes the logs held until now, when no more events are coming: the
+    /// one being aggregated by a `multi_line` rule, and the orphan logs, which
+    /// are sent without a `request_id`.
+    pub async fn flush_pending(&mut self, aggregator: &Arc<Mutex<Aggregator>>) {
+        let events: Vec<TelemetryEvent> = self.multi_line_event.take().into_iter().collect();
+
+        self.process_events(events, true, aggregator).await;
+    }
+
+    async fn process_events(
+        &mut self,
+        events: Vec<TelemetryEvent>,
+        flush_orphans: bool,
+        aggregator: &Arc<Mutex<Aggregator>>,
     ) {
         let mut to_send = Vec::<String>::new();
+        let mut oversized_logs = 0;
+
+        let flush_window = aggregator.lock().expect("lock poisoned").flush_window();
+        if flush_window != self.flush_window {
+            self.flush_window = flush_window;
+            LambdaProcessor::reset_sampling(&self.rules);
+        }
 
         for event in events {
-            if let Ok(mut log) = self.make_log(event).await {
-                let should_send_log =
-                    LambdaProcessor::apply_rules(&self.rules, &mut log.message.message);
-                if should_send_log {
-                    if let Ok(serialized_log) = serde_json::to_string(&log) {
-                        // explicitly drop log so we don't accidentally re-use it and push
-                        // duplicate logs to the aggregator
-                        drop(log);
-                        to_send.push(serialized_log);
-                    }
+            if let Ok((log, fields)) = self.make_log(event).await {
+                let request_id = log.message.lambda.request_id.clone();
+                match self.process_log(log, fields) {
+                    Some(serialized_logs) => to_send.extend(serialized_logs),
+                    None => oversized_logs += 1,
                 }
 
                 // Process orphan logs, since we have a `request_id` now
-                for mut orphan_log in self.orphan_logs.drain(..) {
-                    orphan_log.message.lambda.request_id =
-                        Some(self.invocation_context.request_id.clone());
-                    if should_send_log {
-                        if let Ok(serialized_log) = serde_json::to_string(&orphan_log) {
-                            drop(orphan_log);
-                            to_send.push(serialized_log);
-                        }
+                for (mut orphan_log, fields) in std::mem::take(&mut self.orphan_logs) {
+                    orphan_log.message.lambda.request_id.clone_from(&request_id);
+                    match self.process_log(orphan_log, fields) {
+                        Some(serialized_logs) => to_send.extend(serialized_logs),
+                        None => oversized_logs += 1,
                     }
                 }
             }
         }
 
+        if flush_orphans {
+            for (orphan_log, fields) in std::mem::take(&mut self.orphan_logs) {
+                match self.process_log(orphan_log, fields) {
+                    Some(serialized_logs) => to_send.extend(serialized_logs),
+                    None => oversized_logs += 1,
+                }
+            }
+        }
+
+        self.report_redactions().await;
+
+        if oversized_logs > 0 {
+            self.report_dropped_logs(
+                oversized_logs,
+                "they exceed the maximum log size and can't be split",
+                aggregator,
+            )
+            .await;
+        }
+
         if to_send.is_empty() {
             return;
         }
-        let mut aggregator = aggregator.lock().expect("lock poisoned");
-        aggregator.add_batch(to_send);
+        let dropped = aggregator.lock().expect("lock poisoned").add_batch(to_send);
+        if dropped > 0 {
+            self.report_dropped_logs(dropped, "the extension logs buffer is full", aggregator)
+                .await;
+        }
+    }
+
+    /// Applies the processing rules to a log, then enriches and serializes it.
+    ///
+    /// Returns no logs when filtered out by the rules, and `None` when the log
+    /// is too big to be sent.
+    fn process_log(
+        &mut self,
+        mut log: IntakeLog,
+        fields: Option<StructuredFields>,
+    ) -> Option<Vec<String>> {
+        // The rules and the original JSON need the text of structured logs,
+        // which is only rendered for them
+        if let Some(fields) = &fields {
+            if self.rules.is_some() || self.keep_original_json {
+                log.message.message = serde_json::to_string(fields).unwrap_or_default();
+            }
+        }
+
+        let Some(masked) = LambdaProcessor::filter_and_mask(&self.rules, &mut log.message.message)
+        else {
+            return Some(Vec::new());
+        };
+        if !LambdaProcessor::apply_sampling(
+            &self.rules,
+            &log.message.message,
+            log.message.lambda.request_id.as_deref().unwrap_or_default(),
+        ) {
+            return Some(Vec::new());
+        }
+
+        // Masked structured logs are parsed back from their text
+        let fields = match fields {
+            Some(fields) if !masked => Some(fields),
+            _ => Self::parse_structured_message(&log.message.message),
+        };
+        if let Some(fields) = fields {
+            self.expand_structured_log(&mut log, fields);
+        }
+        self.add_trace_context(&mut log);
+        self.serialize_log(log)
+    }
+
+    /// Serializes a log, splitting its message in ordered chunks sharing a
+    /// `dd.split_id` when it exceeds the maximum log size.
+    ///
+    /// Returns `None` when the log is too big, even once split.
+    fn serialize_log(&mut self, mut log: IntakeLog) -> Option<Vec<String>> {
+        let serialized_log = serde_json::to_string(&log).ok()?;
+        if serialized_log.len() <= self.max_log_size_bytes {
+            return Some(vec![serialized_log]);
+        }
+
+        // Room left for the message once everything else is serialized,
+        // so that any escaped character still fits.
+        let message_size = escaped_len(&log.message.message);
+        let chunk_size = self
+            .max_log_size_bytes
+            .checked_sub(serialized_log.len() - message_size + SPLIT_ATTRIBUTES_MAX_SIZE_BYTES)
+            .filter(|size| *size >= MAX_ESCAPED_CHAR_SIZE_BYTES)?;
+
+        let message = std::mem::take(&mut log.message.message);
+        let chunks = split_message(&message, chunk_size);
+        let total = chunks.len();
+        self.split_count = self.split_count.wrapping_add(1);
+        let split_id = format!("{:x}-{:x}", log.message.timestamp, self.split_count);
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut part = log.clone();
+                part.message.message = chunk;
+                part.attributes.insert(
+                    SPLIT_ID_ATTRIBUTE.to_string(),
+                    Value::from(split_id.clone()),
+                );
+                part.attributes
+                    .insert(SPLIT_INDEX_ATTRIBUTE.to_string(), Value::from(index));
+                part.attributes
+                    .insert(SPLIT_TOTAL_ATTRIBUTE.to_string(), Value::from(total));
+                serde_json::to_string(&part).ok()
+            })
+            .collect()
+    }
+
+    /// Sends how many values each masking rule redacted since last reported.
+    async fn report_redactions(&self) {
+        let Some(rules) = &self.rules else {
+            return;
+        };
+
+        for rule in rules {
+            let redactions = rule.redactions.swap(0, Ordering::Relaxed);
+            if redactions == 0 {
+                continue;
+            }
+            if let Err(e) = self
+                .event_bus
+                .send(Event::LogsRedacted(rule.name.clone(), redactions))
+                .await
+            {
+                error!(
+                    "Failed to send logs redacted event to the main event bus: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Lets users know logs were lost, with a log line of its own and a metric.
+    async fn report_dropped_logs(
+        &self,
+        dropped: usize,
+        reason: &str,
+        aggregator: &Arc<Mutex<Aggregator>>,
+    ) {
+        let request_id = (!self.invocation_context.request_id.is_empty())
+            .then(|| self.invocation_context.request_id.clone());
+        let mut message = Message::new(
+            format!("Dropped {dropped} logs, {reason}"),
+            request_id,
+            self.function_arn.clone(),
+            Utc::now().timestamp_millis(),
+        );
+        message.status = "warn".to_string();
+        let log = IntakeLog {
+            hostname: self.function_arn.clone(),
+            source: LAMBDA_RUNTIME_SLUG.to_string(),
+            service: self.service.clone(),
+            tags: self.tags.clone(),
+            message,
+            attributes: self.attributes.clone(),
+        };
+        // The notice can push more logs out of a full buffer, they're counted too
+        let dropped = match serde_json::to_string(&log) {
+            Ok(serialized_log) => {
+                dropped
+                    + aggregator
+                        .lock()
+                        .expect("lock poisoned")
+                        .add_batch(vec![serialized_log])
+            }
+            Err(_) => dropped,
+        };
+        warn!("Dropped {dropped} logs, {reason}");
+
+        if let Err(e) = self.event_bus.send(Event::LogsDropped(dropped)).await {
+            error!(
+                "Failed to send logs dropped event to the main event bus: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Size of a character once escaped in a JSON string.
+fn escaped_char_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{08}' | '\u{0c}' => 2,
+        c if c < ' ' => MAX_ESCAPED_CHAR_SIZE_BYTES,
+        c => c.len_utf8(),
+    }
+}
+
+fn escaped_len(message: &str) -> usize {
+    message.chars().map(escaped_char_len).sum()
+}
+
+/// Splits a message in chunks which, once escaped, are at most `max_size` bytes.
+fn split_message(message: &str, max_size: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_size = 0;
+    for c in message.chars() {
+        let c_size = escaped_char_len(c);
+        if chunk_size + c_size > max_size && !chunk.is_empty() {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_size = 0;
+        }
+        chunk.push(c);
+        chunk_size += c_size;
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+fn take_non_empty_string(fields: &mut serde_json::Map<String, Value>, key: &str) -> Option<String> {
+    match fields.remove(key) {
+        Some(Value::String(s)) if !s.is_empty() => Some(s),
+        _ => None,
     }
 }
 
@@ -273,10 +916,13 @@ mod tests {
     use serde_json::{Number, Value};
     use std::collections::hash_map::HashMap;
 
+    use crate::config::scrubbing_preset::ScrubbingPreset;
     use crate::logs::lambda::Lambda;
     use crate::telemetry::events::{
-        InitPhase, InitType, ReportMetrics, RuntimeDoneMetrics, Status,
+        InitPhase, InitType, ReportMetrics, RestoreReportMetrics, RuntimeDoneMetrics, Status,
     };
+    use crate::traces::invocation_span::SpanGenerator;
+    use crate::traces::stats_concentrator::StatsConcentrator;
 
     macro_rules! get_message_tests {
         ($($name:ident: $value:expr,)*) => {
@@ -305,6 +951,7 @@ mod tests {
                             tags: Some("test:tag,env:test".to_string()),
                             ..config::Config::default()}),
                         tx.clone(),
+                        Arc::new(Mutex::new(InvocationContextBuffer::default())),
                     );
 
                     let result = processor.get_message(input.clone()).await.unwrap();
@@ -373,6 +1020,47 @@ mod tests {
                 },
         ),
 
+        // platform restore start
+        platform_restore_start: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformRestoreStart {
+                    runtime_version: Some("test".to_string()),
+                    runtime_version_arn: Some("test".to_string()),
+                }
+            },
+            Message {
+                    message: "RESTORE_START Runtime Version: test Runtime Version ARN: test".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "info".to_string(),
+                },
+        ),
+
+        // platform restore report
+        platform_restore_report: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformRestoreReport {
+                    status: Status::Success,
+                    error_type: None,
+                    metrics: RestoreReportMetrics { duration_ms: 200.0 },
+                }
+            },
+            Message {
+                    message: "RESTORE_REPORT Restore Duration: 200 ms".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "info".to_string(),
+                },
+        ),
+
         // platform start
         platform_start: (
             &TelemetryEvent {
@@ -446,23 +1134,113 @@ mod tests {
                     status: "info".to_string(),
                 },
         ),
-    }
-
-    #[tokio::test]
-    async fn test_get_message_function_unsupported_value() {
-        let config = Arc::new(config::Config {
-            ..config::Config::default()
-        });
-
-        let tags_provider = Arc::new(provider::Provider::new(
-            Arc::clone(&config),
-            LAMBDA_RUNTIME_SLUG.to_string(),
-            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
-        ));
 
-        let (tx, _) = tokio::sync::mpsc::channel(2);
+        // platform init runtime done
+        platform_init_runtime_done: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformInitRuntimeDone {
+                    initialization_type: InitType::OnDemand,
+                    phase: Some(InitPhase::Init),
+                    status: Status::Failure,
+                    error_type: Some("Runtime.ExitError".to_string()),
+                }
+            },
+            Message {
+                    message: "INIT_RUNTIME_DONE Status: failure Error Type: Runtime.ExitError".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "error".to_string(),
+                },
+        ),
 
-        let mut processor = LambdaProcessor::new(tags_provider, Arc::clone(&config), tx.clone());
+        // platform extension
+        platform_extension: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformExtension {
+                    name: "datadog-agent".to_string(),
+                    state: "Ready".to_string(),
+                    events: vec!["INVOKE".to_string(), "SHUTDOWN".to_string()],
+                }
+            },
+            Message {
+                    message: "EXTENSION Name: datadog-agent State: Ready Events: [INVOKE, SHUTDOWN]".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "info".to_string(),
+                },
+        ),
+
+        // platform telemetry subscription
+        platform_telemetry_subscription: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformTelemetrySubscription {
+                    name: "datadog-agent".to_string(),
+                    state: "Subscribed".to_string(),
+                    types: vec!["platform".to_string(), "function".to_string()],
+                }
+            },
+            Message {
+                    message: "TELEMETRY Name: datadog-agent State: Subscribed Types: [platform, function]".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "info".to_string(),
+                },
+        ),
+
+        // platform logs dropped
+        platform_logs_dropped: (
+            &TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::PlatformLogsDropped {
+                    reason: "Consumer seems to have fallen behind".to_string(),
+                    dropped_records: 42,
+                    dropped_bytes: 4_096,
+                }
+            },
+            Message {
+                    message: "Lambda dropped 42 log records (4096 bytes) before they reached the extension: Consumer seems to have fallen behind".to_string(),
+                    lambda: Lambda {
+                        arn: "test-arn".to_string(),
+                        request_id: None,
+                    },
+                    timestamp: 1_673_061_827_000,
+                    status: "warn".to_string(),
+                },
+        ),
+    }
+
+    #[tokio::test]
+    async fn test_get_message_function_unsupported_value() {
+        let config = Arc::new(config::Config {
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _) = tokio::sync::mpsc::channel(2);
+
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -477,6 +1255,36 @@ mod tests {
             .contains("Unable to parse log"));
     }
 
+    #[tokio::test]
+    async fn test_get_message_skips_local_sink_logs() {
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _) = tokio::sync::mpsc::channel(2);
+
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Extension(Value::String(format!(
+                "{LOCAL_SINK_PREFIX}{{\"message\":\"hello\"}}"
+            ))),
+        };
+
+        let result = processor.get_message(event).await;
+        assert!(result.is_err());
+    }
+
     // get_intake_log
     #[tokio::test]
     async fn test_get_intake_log() {
@@ -493,7 +1301,12 @@ mod tests {
         ));
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
-        let mut processor = LambdaProcessor::new(tags_provider, Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -504,7 +1317,7 @@ mod tests {
         };
 
         let lambda_message = processor.get_message(event.clone()).await.unwrap();
-        let intake_log = processor.get_intake_log(lambda_message).unwrap();
+        let (intake_log, _) = processor.get_intake_log(lambda_message, None).unwrap();
 
         assert_eq!(intake_log.source, LAMBDA_RUNTIME_SLUG.to_string());
         assert_eq!(intake_log.hostname, "test-arn".to_string());
@@ -539,7 +1352,12 @@ mod tests {
         ));
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
-        let mut processor = LambdaProcessor::new(tags_provider, Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -549,7 +1367,7 @@ mod tests {
         let lambda_message = processor.get_message(event.clone()).await.unwrap();
         assert_eq!(lambda_message.lambda.request_id, None);
 
-        let intake_log = processor.get_intake_log(lambda_message).unwrap_err();
+        let intake_log = processor.get_intake_log(lambda_message, None).unwrap_err();
         assert_eq!(
             intake_log.to_string(),
             "No request_id available, queueing for later"
@@ -572,7 +1390,12 @@ mod tests {
         ));
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
-        let mut processor = LambdaProcessor::new(tags_provider, Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let start_event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -583,7 +1406,9 @@ mod tests {
         };
 
         let start_lambda_message = processor.get_message(start_event.clone()).await.unwrap();
-        processor.get_intake_log(start_lambda_message).unwrap();
+        processor
+            .get_intake_log(start_lambda_message, None)
+            .unwrap();
 
         // This could be any event that doesn't have a `request_id`
         let event = TelemetryEvent {
@@ -592,7 +1417,7 @@ mod tests {
         };
 
         let lambda_message = processor.get_message(event.clone()).await.unwrap();
-        let intake_log = processor.get_intake_log(lambda_message).unwrap();
+        let (intake_log, _) = processor.get_intake_log(lambda_message, None).unwrap();
         assert_eq!(
             intake_log.message.lambda.request_id,
             Some("test-request-id".to_string())
@@ -617,8 +1442,12 @@ mod tests {
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
 
-        let mut processor =
-            LambdaProcessor::new(Arc::clone(&tags_provider), Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            Arc::clone(&tags_provider),
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -646,6 +1475,7 @@ mod tests {
             source: LAMBDA_RUNTIME_SLUG.to_string(),
             service: "test-service".to_string(),
             tags: tags_provider.get_tags_string(),
+            attributes: serde_json::Map::new(),
         };
         let serialized_log = format!("[{}]", serde_json::to_string(&log).unwrap());
         assert_eq!(batch, serialized_log.as_bytes());
@@ -668,7 +1498,12 @@ mod tests {
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
 
-        let mut processor = LambdaProcessor::new(tags_provider, Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -700,8 +1535,12 @@ mod tests {
 
         let (tx, _rx) = tokio::sync::mpsc::channel(2);
 
-        let mut processor =
-            LambdaProcessor::new(Arc::clone(&tags_provider), Arc::clone(&config), tx.clone());
+        let mut processor = LambdaProcessor::new(
+            Arc::clone(&tags_provider),
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
 
         let start_event = TelemetryEvent {
             time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
@@ -744,6 +1583,7 @@ mod tests {
             source: LAMBDA_RUNTIME_SLUG.to_string(),
             service: "test-service".to_string(),
             tags: tags_provider.get_tags_string(),
+            attributes: serde_json::Map::new(),
         };
         let function_log = IntakeLog {
             message: Message {
@@ -759,6 +1599,7 @@ mod tests {
             source: LAMBDA_RUNTIME_SLUG.to_string(),
             service: "test-service".to_string(),
             tags: tags_provider.get_tags_string(),
+            attributes: serde_json::Map::new(),
         };
         let serialized_log = format!(
             "[{},{}]",
@@ -767,4 +1608,949 @@ mod tests {
         );
         assert_eq!(batch, serialized_log.as_bytes());
     }
+
+    #[tokio::test]
+    async fn test_process_orphan_logs_apply_rules_individually() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_processing_rules: Some(vec![processing_rule::ProcessingRule {
+                kind: processing_rule::Kind::ExcludeAtMatch,
+                name: "exclude_debug".to_string(),
+                pattern: "DEBUG".to_string(),
+                replace_placeholder: None,
+                sample_rate: None,
+                max_per_flush: None,
+            }]),
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+
+        let function_event = |line: &str| TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(line.to_string())),
+        };
+
+        processor
+            .process(
+                vec![
+                    function_event("DEBUG loading config"),
+                    function_event("INFO config loaded"),
+                ],
+                &aggregator,
+            )
+            .await;
+        assert_eq!(processor.orphan_logs.len(), 2);
+
+        let start_event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::PlatformStart {
+                request_id: "test-request-id".to_string(),
+                version: Some("test".to_string()),
+            },
+        };
+        processor.process(vec![start_event], &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(
+            logs[0]["message"]["message"],
+            "START RequestId: test-request-id Version: test"
+        );
+        assert_eq!(logs[1]["message"]["message"], "INFO config loaded");
+        assert_eq!(
+            logs[1]["message"]["lambda"]["request_id"],
+            "test-request-id"
+        );
+        assert_eq!(logs[1]["lambda.phase"], "init");
+    }
+
+    #[tokio::test]
+    async fn test_flush_pending_orphan_logs() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String("init failed".to_string())),
+        };
+        processor.process(vec![event], &aggregator).await;
+        assert_eq!(aggregator.lock().unwrap().get_batch(), "[]".as_bytes());
+
+        processor.flush_pending(&aggregator).await;
+        assert!(processor.orphan_logs.is_empty());
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0]["message"]["message"], "init failed");
+        assert!(logs[0]["message"]["lambda"]["request_id"].is_null());
+        assert_eq!(logs[0]["lambda.phase"], "init");
+    }
+
+    #[tokio::test]
+    async fn test_process_structured_log() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            service: Some("test-service".to_string()),
+            tags: Some("test:tags".to_string()),
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let object_event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(serde_json::json!({
+                "message": "hello",
+                "user": {"id": 42},
+                "service": "billing",
+                "ddsource": "python",
+                "ddtags": "team:payments",
+                "hostname": "ignored",
+            })),
+        };
+        let string_event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(
+                r#"{"msg":"world","level":"debug"}"#.to_string(),
+            )),
+        };
+
+        processor
+            .process(vec![object_event, string_event], &aggregator)
+            .await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+
+        assert_eq!(logs[0]["message"]["message"], "hello");
+        assert_eq!(logs[0]["user"]["id"], 42);
+        assert_eq!(logs[0]["service"], "billing");
+        assert_eq!(logs[0]["ddsource"], "python");
+        assert!(logs[0]["ddtags"]
+            .as_str()
+            .unwrap()
+            .ends_with(",team:payments"));
+        assert_eq!(logs[0]["hostname"], "test-arn");
+        assert!(logs[0].get("original_json").is_none());
+
+        assert_eq!(logs[1]["message"]["message"], "world");
+        assert_eq!(logs[1]["level"], "debug");
+        assert_eq!(logs[1]["service"], "test-service");
+        assert_eq!(logs[1]["ddsource"], LAMBDA_RUNTIME_SLUG);
+    }
+
+    #[tokio::test]
+    async fn test_process_static_attributes() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let mut attributes = serde_json::Map::new();
+        attributes.insert("team".to_string(), Value::from("payments"));
+        attributes.insert("service".to_string(), Value::from("reserved"));
+        let config = Arc::new(config::Config {
+            service: Some("test-service".to_string()),
+            logs_config_attributes: attributes,
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let plain_event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String("hello".to_string())),
+        };
+        let structured_event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(serde_json::json!({
+                "message": "world",
+                "team": "checkout",
+            })),
+        };
+
+        processor
+            .process(vec![plain_event, structured_event], &aggregator)
+            .await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+
+        assert_eq!(logs[0]["team"], "payments");
+        assert_eq!(logs[0]["service"], "test-service");
+        // Fields of the log take precedence
+        assert_eq!(logs[1]["team"], "checkout");
+    }
+
+    #[tokio::test]
+    async fn test_process_structured_log_keep_original_json() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_keep_original_json: true,
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let raw = r#"{"message":"hello","count":1}"#;
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(raw.to_string())),
+        };
+
+        processor.process(vec![event], &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs[0]["message"]["message"], "hello");
+        assert_eq!(logs[0]["count"], 1);
+        assert_eq!(logs[0]["original_json"], raw);
+    }
+
+    #[tokio::test]
+    async fn test_process_trace_context() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_injection: true,
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let invocation_context_buffer = Arc::new(Mutex::new(InvocationContextBuffer::default()));
+        invocation_context_buffer.lock().unwrap().add_span_ids(
+            &"test-request-id".to_string(),
+            111,
+            222,
+        );
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::clone(&invocation_context_buffer),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let events = vec![
+            // Text
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(Value::String(
+                    "[INFO] [dd.service=svc dd.env=prod dd.trace_id=123 dd.span_id=456] hello"
+                        .to_string(),
+                )),
+            },
+            // Structured, nested `dd` object
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(serde_json::json!({
+                    "message": "hello",
+                    "dd": {"trace_id": "789", "span_id": "101112", "env": "prod"},
+                })),
+            },
+            // No ids, falls back to the root span of the invocation
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(Value::String("hello".to_string())),
+            },
+            // Nested `dd` object with numeric ids and other keys
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(serde_json::json!({
+                    "message": "hello",
+                    "dd": {"service": "svc", "trace_id": 131_415, "span_id": 161_718, "version": "1"},
+                    "user": "me",
+                })),
+            },
+        ];
+
+        processor.process(events, &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 4);
+
+        assert_eq!(logs[0]["dd.trace_id"], "123");
+        assert_eq!(logs[0]["dd.span_id"], "456");
+
+        assert!(logs[1].get("dd.trace_id").is_none());
+        assert_eq!(
+            logs[1]["dd"],
+            serde_json::json!({"trace_id": "789", "span_id": "101112", "env": "prod"})
+        );
+
+        assert_eq!(logs[2]["dd.trace_id"], "111");
+        assert_eq!(logs[2]["dd.span_id"], "222");
+
+        assert!(logs[3].get("dd.trace_id").is_none());
+        assert_eq!(
+            logs[3]["dd"],
+            serde_json::json!({
+                "service": "svc",
+                "trace_id": "131415",
+                "span_id": "161718",
+                "version": "1",
+            })
+        );
+        assert_eq!(logs[3]["user"], "me");
+    }
+
+    #[tokio::test]
+    async fn test_process_root_span_context_in_event_order() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_injection: true,
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let invocation_context_buffer = Arc::new(Mutex::new(InvocationContextBuffer::default()));
+        let mut generator = SpanGenerator::new(
+            Arc::clone(&config),
+            Arc::clone(&tags_provider),
+            "api-key".to_string(),
+            Arc::clone(&invocation_context_buffer),
+            Arc::new(StatsConcentrator::new(None, None)),
+        );
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::clone(&invocation_context_buffer),
+        );
+
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+        let function_log = |message: &str| TelemetryEvent {
+            time,
+            record: TelemetryRecord::Function(Value::String(message.to_string())),
+        };
+
+        // The extension is invoked before the telemetry of the invocation comes in
+        let (trace_id, span_id) = generator.on_invocation_start("test-request-id", time);
+
+        processor
+            .process(
+                vec![
+                    TelemetryEvent {
+                        time,
+                        record: TelemetryRecord::PlatformStart {
+                            request_id: "test-request-id".to_string(),
+                            version: Some("test".to_string()),
+                        },
+                    },
+                    function_log("hello"),
+                ],
+                &aggregator,
+            )
+            .await;
+        generator.on_platform_start("test-request-id", time);
+        processor
+            .process(
+                vec![
+                    function_log("world"),
+                    TelemetryEvent {
+                        time,
+                        record: TelemetryRecord::PlatformRuntimeDone {
+                            request_id: "test-request-id".to_string(),
+                            status: Status::Success,
+                            error_type: None,
+                            metrics: None,
+                        },
+                    },
+                    TelemetryEvent {
+                        time,
+                        record: TelemetryRecord::PlatformReport {
+                            error_type: None,
+                            status: Status::Success,
+                            request_id: "test-request-id".to_string(),
+                            metrics: ReportMetrics {
+                                duration_ms: 100.0,
+                                billed_duration_ms: 128,
+                                memory_size_mb: 256,
+                                max_memory_used_mb: 64,
+                                init_duration_ms: None,
+                                restore_duration_ms: None,
+                            },
+                        },
+                    },
+                ],
+                &aggregator,
+            )
+            .await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        let function_logs: Vec<&Value> = logs
+            .iter()
+            .filter(|log| matches!(log["message"]["message"].as_str(), Some("hello" | "world")))
+            .collect();
+        assert_eq!(function_logs.len(), 2);
+        for log in function_logs {
+            assert_eq!(log["dd.trace_id"], trace_id.to_string());
+            assert_eq!(log["dd.span_id"], span_id.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_trace_context_disabled() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(
+                "[dd.trace_id=123 dd.span_id=456] hello".to_string(),
+            )),
+        };
+
+        processor.process(vec![event], &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert!(logs[0].get("dd.trace_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_process_multi_line() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_processing_rules: Some(vec![processing_rule::ProcessingRule {
+                kind: processing_rule::Kind::MultiLine,
+                name: "new_log_start_with_date".to_string(),
+                pattern: "\\d{4}-\\d{2}-\\d{2}".to_string(),
+                replace_placeholder: None,
+                sample_rate: None,
+                max_per_flush: None,
+            }]),
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let function_event = |line: &str| TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(line.to_string())),
+        };
+
+        processor
+            .process(
+                vec![
+                    function_event("2023-01-07 ERROR java.lang.RuntimeException: boom"),
+                    function_event("\tat com.example.Handler.handle(Handler.java:42)"),
+                ],
+                &aggregator,
+            )
+            .await;
+
+        // Still waiting for more lines
+        assert_eq!(aggregator.lock().unwrap().get_batch(), "[]".as_bytes());
+
+        processor
+            .process(
+                vec![
+                    function_event("\tat com.example.Main.main(Main.java:7)"),
+                    function_event("2023-01-07 INFO next log"),
+                    TelemetryEvent {
+                        time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                        record: TelemetryRecord::PlatformRuntimeDone {
+                            request_id: "test-request-id".to_string(),
+                            status: Status::Success,
+                            error_type: None,
+                            metrics: None,
+                        },
+                    },
+                ],
+                &aggregator,
+            )
+            .await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 3);
+        assert_eq!(
+            logs[0]["message"]["message"],
+            "2023-01-07 ERROR java.lang.RuntimeException: boom\n\tat com.example.Handler.handle(Handler.java:42)\n\tat com.example.Main.main(Main.java:7)"
+        );
+        assert_eq!(
+            logs[0]["message"]["lambda"]["request_id"],
+            "test-request-id"
+        );
+        assert_eq!(logs[1]["message"]["message"], "2023-01-07 INFO next log");
+        assert_eq!(
+            logs[2]["message"]["message"],
+            "END RequestId: test-request-id"
+        );
+
+        // Logs of the listeners are already whole
+        processor
+            .process(
+                vec![
+                    function_event("2023-01-07 ERROR boom"),
+                    TelemetryEvent {
+                        time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                        record: TelemetryRecord::Listener(Value::String(
+                            "\tat com.example.Listener.log(Listener.java:1)".to_string(),
+                        )),
+                    },
+                ],
+                &aggregator,
+            )
+            .await;
+        processor.flush_pending(&aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0]["message"]["message"], "2023-01-07 ERROR boom");
+    }
+
+    #[tokio::test]
+    async fn test_flush_pending_multi_line() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_processing_rules: Some(vec![processing_rule::ProcessingRule {
+                kind: processing_rule::Kind::MultiLine,
+                name: "new_log_start_with_date".to_string(),
+                pattern: "\\d{4}-\\d{2}-\\d{2}".to_string(),
+                replace_placeholder: None,
+                sample_rate: None,
+                max_per_flush: None,
+            }]),
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(
+                "2023-01-07 ERROR timed out".to_string(),
+            )),
+        };
+        processor.process(vec![event], &aggregator).await;
+        assert_eq!(aggregator.lock().unwrap().get_batch(), "[]".as_bytes());
+
+        processor.flush_pending(&aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0]["message"]["message"], "2023-01-07 ERROR timed out");
+    }
+
+    #[tokio::test]
+    async fn test_process_logs_dropped() {
+        // Only fits a couple of logs
+        let aggregator = Arc::new(Mutex::new(Aggregator::new(1_000, 1_024, 1_024, 700)));
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let events = (0..5)
+            .map(|i| TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(Value::String(format!("log number {i}"))),
+            })
+            .collect();
+        processor.process(events, &aggregator).await;
+
+        let Some(Event::LogsDropped(dropped)) = rx.recv().await else {
+            panic!("expected a logs dropped event");
+        };
+        assert!(dropped > 0);
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        // Every log is either kept or counted as dropped, the notice included
+        assert_eq!(logs.len() - 1 + dropped, 5);
+        let last_log = logs.last().unwrap();
+        assert!(last_log["message"]["message"]
+            .as_str()
+            .unwrap()
+            .ends_with("logs, the extension logs buffer is full"));
+        assert_eq!(last_log["message"]["status"], "warn");
+    }
+
+    #[tokio::test]
+    async fn test_process_split_oversized_log() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+        processor.max_log_size_bytes = 1_024;
+
+        let message = "a".repeat(1_500);
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(message.clone())),
+        };
+        processor.process(vec![event], &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 3);
+
+        let mut joined = String::new();
+        for (index, log) in logs.iter().enumerate() {
+            assert!(serde_json::to_string(log).unwrap().len() <= 1_024);
+            assert_eq!(log["dd.split_id"], logs[0]["dd.split_id"]);
+            assert_eq!(log["dd.split_index"], index);
+            assert_eq!(log["dd.split_total"], 3);
+            joined.push_str(log["message"]["message"].as_str().unwrap());
+        }
+        assert_eq!(joined, message);
+    }
+
+    #[tokio::test]
+    async fn test_process_drops_unsplittable_log() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config::default());
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+        processor.max_log_size_bytes = 1_024;
+        // Everything but the message already exceeds the maximum log size
+        processor.tags = "a".repeat(1_024);
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String("too big".to_string())),
+        };
+        processor.process(vec![event], &aggregator).await;
+
+        let Some(Event::LogsDropped(dropped)) = rx.recv().await else {
+            panic!("expected a logs dropped event");
+        };
+        assert_eq!(dropped, 1);
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(
+            logs[0]["message"]["message"],
+            "Dropped 1 logs, they exceed the maximum log size and can't be split"
+        );
+    }
+
+    #[test]
+    fn test_split_message_escaped_size() {
+        let chunks = split_message("ab\"cd\n\u{1}é", 4);
+        assert_eq!(chunks, vec!["ab\"", "cd\n", "\u{1}", "é"]);
+    }
+
+    #[tokio::test]
+    async fn test_process_scrubbing_presets() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_scrubbing_presets: vec![ScrubbingPreset::Email],
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let event = TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(
+                "sent to jane@example.com and john@example.com".to_string(),
+            )),
+        };
+        processor.process(vec![event], &aggregator).await;
+
+        let Some(Event::LogsRedacted(rule_name, redactions)) = rx.recv().await else {
+            panic!("expected a logs redacted event");
+        };
+        assert_eq!(rule_name, "email");
+        assert_eq!(redactions, 2);
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(
+            logs[0]["message"]["message"],
+            "sent to [REDACTED_EMAIL] and [REDACTED_EMAIL]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_scrubbing_presets_structured_log() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_scrubbing_presets: vec![ScrubbingPreset::Email],
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let events = vec![
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(serde_json::json!({
+                    "message": "signed up",
+                    "user": {"id": 42, "email": "jane@example.com"},
+                })),
+            },
+            // Not masked, expanded as it is
+            TelemetryEvent {
+                time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+                record: TelemetryRecord::Function(serde_json::json!({
+                    "message": "logged in",
+                    "user": {"id": 43},
+                })),
+            },
+        ];
+        processor.process(events, &aggregator).await;
+
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs[0]["message"]["message"], "signed up");
+        assert_eq!(logs[0]["user"]["id"], 42);
+        assert_eq!(logs[0]["user"]["email"], "[REDACTED_EMAIL]");
+        assert_eq!(logs[1]["message"]["message"], "logged in");
+        assert_eq!(logs[1]["user"]["id"], 43);
+    }
+
+    #[tokio::test]
+    async fn test_process_sample_max_per_flush() {
+        let aggregator = Arc::new(Mutex::new(Aggregator::default()));
+        let config = Arc::new(config::Config {
+            logs_config_processing_rules: Some(vec![processing_rule::ProcessingRule {
+                kind: processing_rule::Kind::Sample,
+                name: "chatty_sdk".to_string(),
+                pattern: "DEBUG".to_string(),
+                replace_placeholder: None,
+                sample_rate: None,
+                max_per_flush: Some(1),
+            }]),
+            ..config::Config::default()
+        });
+
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([("function_arn".to_string(), "test-arn".to_string())]),
+        ));
+
+        let (tx, _rx) = tokio::sync::mpsc::channel(2);
+        let mut processor = LambdaProcessor::new(
+            tags_provider,
+            Arc::clone(&config),
+            tx.clone(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+        );
+        processor.invocation_context.request_id = "test-request-id".to_string();
+
+        let function_event = |line: &str| TelemetryEvent {
+            time: Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap(),
+            record: TelemetryRecord::Function(Value::String(line.to_string())),
+        };
+        let events = vec![
+            function_event("DEBUG first"),
+            function_event("DEBUG second"),
+            function_event("INFO third"),
+        ];
+
+        processor.process(events.clone(), &aggregator).await;
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0]["message"]["message"], "DEBUG first");
+        assert_eq!(logs[1]["message"]["message"], "INFO third");
+
+        // Another invocation in the same flush window
+        processor.invocation_context.request_id = "other-request-id".to_string();
+        processor.process(events.clone(), &aggregator).await;
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0]["message"]["message"], "INFO third");
+
+        aggregator.lock().unwrap().start_flush_window();
+        processor.process(events, &aggregator).await;
+        let batch = aggregator.lock().unwrap().get_batch();
+        let logs: Vec<Value> = serde_json::from_slice(&batch).unwrap();
+        assert_eq!(logs.len(), 2);
+    }
 }
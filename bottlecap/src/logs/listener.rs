@@ -0,0 +1,461 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use chrono::Utc;
+use hyper::body::{Bytes, HttpBody};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{http, Body, Method, Request, Response, Server, StatusCode};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::logs::constants::{MAX_BATCH_ENTRIES_SIZE, MAX_CONTENT_SIZE_BYTES};
+use crate::telemetry::events::{TelemetryEvent, TelemetryRecord};
+
+const LOGS_ENDPOINT_PATH: &str = "/v1/input";
+/// Maximum size in bytes of a line read over TCP, longer lines are truncated.
+/// Lines are split in logs of `MAX_LOG_SIZE_BYTES` by the processor.
+const MAX_LINE_SIZE_BYTES: usize = MAX_CONTENT_SIZE_BYTES;
+
+/// Accepts logs from local processes, as a JSON array (or a single value) of
+/// logs `POST`ed to `/v1/input`.
+///
+/// Logs are forwarded as function logs, so they go through the same processing.
+#[allow(clippy::module_name_repetitions)]
+pub struct HttpLogsListener {
+    addr: SocketAddr,
+    logs_tx: Sender<Vec<TelemetryEvent>>,
+    cancel_token: CancellationToken,
+}
+
+impl HttpLogsListener {
+    #[must_use]
+    pub fn new(
+        port: u16,
+        logs_tx: Sender<Vec<TelemetryEvent>>,
+        cancel_token: CancellationToken,
+    ) -> HttpLogsListener {
+        HttpLogsListener {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            logs_tx,
+            cancel_token,
+        }
+    }
+
+    /// Serves requests until cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the address cannot be bound.
+    pub async fn spin(self) -> Result<(), Box<dyn std::error::Error>> {
+        let logs_tx = self.logs_tx;
+        let make_svc = make_service_fn(move |_| {
+            let logs_tx = logs_tx.clone();
+            let service = service_fn(move |req| Self::handle_request(req, logs_tx.clone()));
+
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server = Server::try_bind(&self.addr)?.serve(make_svc);
+        debug!("HTTP logs listener started: listening on {}", self.addr);
+
+        let cancel_token = self.cancel_token;
+        server
+            .with_graceful_shutdown(async move { cancel_token.cancelled().await })
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_request(
+        req: Request<Body>,
+        logs_tx: Sender<Vec<TelemetryEvent>>,
+    ) -> http::Result<Response<Body>> {
+        if (req.method(), req.uri().path()) != (&Method::POST, LOGS_ENDPOINT_PATH) {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty());
+        }
+
+        let headers = req.headers();
+        let chunked = headers
+            .get(hyper::header::TRANSFER_ENCODING)
+            .and_then(|encoding| encoding.to_str().ok())
+            .is_some_and(|encoding| encoding.contains("chunked"));
+        let content_length = headers
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        match content_length {
+            None if !chunked => {
+                return Response::builder()
+                    .status(StatusCode::LENGTH_REQUIRED)
+                    .body(Body::empty());
+            }
+            Some(length) if length > MAX_CONTENT_SIZE_BYTES => {
+                return Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::empty());
+            }
+            _ => {}
+        }
+
+        let body = match read_body(req.into_body(), MAX_CONTENT_SIZE_BYTES).await {
+            Ok(Some(body)) => body,
+            Ok(None) => {
+                return Response::builder()
+                    .status(StatusCode::PAYLOAD_TOO_LARGE)
+                    .body(Body::empty());
+            }
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Failed to read body: {e}")));
+            }
+        };
+
+        let events = match parse_http_logs(&body) {
+            Ok(events) => events,
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Failed to parse logs: {e}")));
+            }
+        };
+
+        if !events.is_empty() {
+            if let Err(e) = logs_tx.send(events).await {
+                error!("Failed to forward logs received over HTTP: {e}");
+                return Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::empty());
+            }
+        }
+
+        Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .body(Body::empty())
+    }
+}
+
+/// Accepts newline delimited logs from local processes.
+///
+/// Each line is forwarded as a function log, so it goes through the same processing.
+/// Lines longer than `MAX_LINE_SIZE_BYTES` are truncated.
+#[allow(clippy::module_name_repetitions)]
+pub struct TcpLogsListener {
+    listener: TcpListener,
+    logs_tx: Sender<Vec<TelemetryEvent>>,
+    cancel_token: CancellationToken,
+}
+
+impl TcpLogsListener {
+    /// Binds the listener to the given port.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the address cannot be bound.
+    pub async fn new(
+        port: u16,
+        logs_tx: Sender<Vec<TelemetryEvent>>,
+        cancel_token: CancellationToken,
+    ) -> Result<TcpLogsListener, String> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(TcpLogsListener {
+            listener,
+            logs_tx,
+            cancel_token,
+        })
+    }
+
+    pub async fn spin(self) {
+        loop {
+            tokio::select! {
+                biased;
+                stream = self.listener.accept() => {
+                    match stream {
+                        Ok((stream, _)) => {
+                            let logs_tx = self.logs_tx.clone();
+                            let cancel_token = self.cancel_token.clone();
+                            tokio::spawn(async move {
+                                Self::handle_stream(stream, logs_tx, cancel_token).await;
+                            });
+                        }
+                        Err(e) => {
+                            error!("Error accepting connection: {:?}", e);
+                        }
+                    }
+                }
+                () = self.cancel_token.cancelled() => {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn handle_stream(
+        stream: TcpStream,
+        logs_tx: Sender<Vec<TelemetryEvent>>,
+        cancel_token: CancellationToken,
+    ) {
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        let mut closed = false;
+        while !closed {
+            // Lines received together are forwarded together
+            let mut events = Vec::new();
+            loop {
+                let read = tokio::select! {
+                    read = read_line(&mut reader, &mut line) => read,
+                    () = cancel_token.cancelled() => return,
+                };
+                match read {
+                    Ok(0) => closed = true,
+                    Ok(_) => events.extend(parse_tcp_log(&String::from_utf8_lossy(&line))),
+                    Err(e) => {
+                        error!("Error reading logs from stream: {e}");
+                        closed = true;
+                    }
+                }
+                if closed
+                    || events.len() >= MAX_BATCH_ENTRIES_SIZE
+                    || !reader.buffer().contains(&b'\n')
+                {
+                    break;
+                }
+            }
+
+            if events.is_empty() {
+                continue;
+            }
+            if let Err(e) = logs_tx.send(events).await {
+                error!("Failed to forward logs received over TCP: {e}");
+                return;
+            }
+        }
+    }
+}
+
+/// Reads a line of at most `MAX_LINE_SIZE_BYTES`, the rest of a longer line is
+/// skipped. Returns the number of bytes read, `0` once the stream is closed.
+async fn read_line(
+    reader: &mut BufReader<TcpStream>,
+    line: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    line.clear();
+    let limit = MAX_LINE_SIZE_BYTES as u64;
+    let read = (&mut *reader).take(limit).read_until(b'\n', line).await?;
+    if read as u64 == limit && line.last() != Some(&b'\n') {
+        truncate_to_char_boundary(line);
+        loop {
+            let buffer = reader.fill_buf().await?;
+            if buffer.is_empty() {
+                break;
+            }
+            if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                reader.consume(end + 1);
+                break;
+            }
+            let skipped = buffer.len();
+            reader.consume(skipped);
+        }
+    }
+    Ok(read)
+}
+
+/// Drops the end of a char cut at the end of the line, which would otherwise be
+/// decoded as U+FFFD.
+fn truncate_to_char_boundary(line: &mut Vec<u8>) {
+    let tail = line.len().saturating_sub(4);
+    let Some((start, &lead)) = line
+        .iter()
+        .enumerate()
+        .skip(tail)
+        .rev()
+        .find(|(_, byte)| *byte & 0xC0 != 0x80)
+    else {
+        return;
+    };
+    let char_len = match lead {
+        lead if lead >= 0xF0 => 4,
+        lead if lead >= 0xE0 => 3,
+        lead if lead >= 0xC0 => 2,
+        _ => 1,
+    };
+    if start + char_len > line.len() {
+        line.truncate(start);
+    }
+}
+
+/// Reads a body of at most `limit` bytes, `None` when it's bigger.
+async fn read_body(mut body: Body, limit: usize) -> Result<Option<Bytes>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(Bytes::from(bytes)))
+}
+
+fn listener_log(record: Value) -> TelemetryEvent {
+    TelemetryEvent {
+        time: Utc::now(),
+        record: TelemetryRecord::Listener(record),
+    }
+}
+
+fn parse_http_logs(body: &[u8]) -> Result<Vec<TelemetryEvent>, serde_json::Error> {
+    let logs = match serde_json::from_slice(body)? {
+        Value::Array(logs) => logs,
+        log => vec![log],
+    };
+
+    Ok(logs
+        .into_iter()
+        .filter(|log| log.is_string() || log.is_object())
+        .map(listener_log)
+        .collect())
+}
+
+fn parse_tcp_log(line: &str) -> Option<TelemetryEvent> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(listener_log(Value::String(line.to_string())))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::logs::constants::MAX_LOG_SIZE_BYTES;
+    use serde_json::json;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_parse_http_logs() {
+        let events = parse_http_logs(br#"["hello", {"message": "world"}, 42]"#).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].record,
+            TelemetryRecord::Listener(Value::String("hello".to_string()))
+        );
+        assert_eq!(
+            events[1].record,
+            TelemetryRecord::Listener(json!({"message": "world"}))
+        );
+
+        let events = parse_http_logs(br#"{"message": "single"}"#).unwrap();
+        assert_eq!(events.len(), 1);
+
+        assert!(parse_http_logs(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_tcp_log() {
+        assert_eq!(
+            parse_tcp_log("hello\r").unwrap().record,
+            TelemetryRecord::Listener(Value::String("hello".to_string()))
+        );
+        assert!(parse_tcp_log("  ").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_listener() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let cancel_token = CancellationToken::new();
+        let listener = TcpLogsListener::new(0, tx, cancel_token.clone())
+            .await
+            .unwrap();
+        let addr = listener.listener.local_addr().unwrap();
+        tokio::spawn(listener.spin());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"first\n\nsecond\n").await.unwrap();
+
+        // Received together, forwarded together
+        let events = rx.recv().await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].record,
+            TelemetryRecord::Listener(Value::String("first".to_string()))
+        );
+        assert_eq!(
+            events[1].record,
+            TelemetryRecord::Listener(Value::String("second".to_string()))
+        );
+
+        // Longer than a log, split by the processor
+        let mut long_line = vec![b'a'; MAX_LOG_SIZE_BYTES + 10];
+        long_line.extend_from_slice(b"\n");
+        // Longer than a line, truncated in the middle of a char
+        long_line.push(b'b');
+        long_line.extend_from_slice(&"é".repeat(MAX_LINE_SIZE_BYTES / 2).into_bytes());
+        long_line.extend_from_slice(b"\nthird\n");
+        stream.write_all(&long_line).await.unwrap();
+        drop(stream);
+
+        let mut events = Vec::new();
+        while let Some(batch) = rx.recv().await {
+            events.extend(batch);
+            if events.len() == 3 {
+                break;
+            }
+        }
+        let TelemetryRecord::Listener(Value::String(line)) = &events[0].record else {
+            panic!("expected a function log");
+        };
+        assert_eq!(line.len(), MAX_LOG_SIZE_BYTES + 10);
+        let TelemetryRecord::Listener(Value::String(truncated)) = &events[1].record else {
+            panic!("expected a function log");
+        };
+        assert_eq!(truncated.len(), MAX_LINE_SIZE_BYTES - 1);
+        assert!(!truncated.contains('\u{FFFD}'));
+        assert_eq!(
+            events[2].record,
+            TelemetryRecord::Listener(Value::String("third".to_string()))
+        );
+
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_http_listener_body_size() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+
+        let req = Request::post(LOGS_ENDPOINT_PATH)
+            .body(Body::from(r#"["hello"]"#))
+            .unwrap();
+        let response = HttpLogsListener::handle_request(req, tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::LENGTH_REQUIRED);
+
+        let req = Request::post(LOGS_ENDPOINT_PATH)
+            .header(hyper::header::TRANSFER_ENCODING, "chunked")
+            .body(Body::from(r#"["hello"]"#))
+            .unwrap();
+        let response = HttpLogsListener::handle_request(req, tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(rx.recv().await.unwrap().len(), 1);
+
+        // Bigger than its Content-Length says
+        let req = Request::post(LOGS_ENDPOINT_PATH)
+            .header(hyper::header::CONTENT_LENGTH, "9")
+            .body(Body::from(vec![b' '; MAX_CONTENT_SIZE_BYTES + 1]))
+            .unwrap();
+        let response = HttpLogsListener::handle_request(req, tx).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
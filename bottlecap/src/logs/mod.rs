@@ -3,4 +3,6 @@ pub mod aggregator;
 pub mod constants;
 pub mod flusher;
 pub mod lambda;
+pub mod listener;
 pub mod processor;
+pub mod scrubbing;
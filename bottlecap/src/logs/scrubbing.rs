@@ -0,0 +1,121 @@
+use crate::config::scrubbing_preset::ScrubbingPreset;
+
+/// Pattern matching the sensitive data of a preset.
+#[must_use]
+pub fn pattern(preset: ScrubbingPreset) -> &'static str {
+    match preset {
+        // Numbers of the known issuers, with their lengths, either in one
+        // block or in groups, so that other long numbers like timestamps aren't
+        // taken for cards
+        ScrubbingPreset::CreditCard => concat!(
+            r"\b(?:",
+            // Visa, Mastercard, American Express, Diners Club, Discover and JCB
+            r"4\d{12}(?:\d{3}){0,2}",
+            r"|5[1-5]\d{14}|2(?:22[1-9]|2[3-9]\d|[3-6]\d{2}|7[01]\d|720)\d{12}",
+            r"|3[47]\d{13}|3(?:0[0-5]|[68]\d)\d{11}",
+            r"|6(?:011|5\d{2})\d{12}(?:\d{3})?|35(?:2[89]|[3-8]\d)\d{12}",
+            // Groups of 4 digits, or 4-6-5 for American Express
+            r"|[2-6]\d{3}(?: \d{4}){3}|[2-6]\d{3}(?:-\d{4}){3}",
+            r"|3[47]\d{2} \d{6} \d{5}|3[47]\d{2}-\d{6}-\d{5}",
+            r")\b",
+        ),
+        ScrubbingPreset::AwsAccessKey => {
+            r"\b(?:AKIA|ASIA|AGPA|AIDA|AROA|AIPA|ANPA|ANVA)[A-Z0-9]{16}\b"
+        }
+        // A secret key is only told apart from any other base64 string by its name
+        ScrubbingPreset::AwsSecretKey => {
+            r#"(?i)(aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?)[A-Za-z0-9/+=]{40}"#
+        }
+        ScrubbingPreset::Jwt => r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*",
+        ScrubbingPreset::BearerToken => r"(?i)\b(bearer\s+)[A-Za-z0-9\-._~+/]+=*",
+        ScrubbingPreset::Email => r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b",
+        // Same as secret keys, API keys look like any other hexadecimal hash
+        ScrubbingPreset::DatadogApiKey => {
+            r#"(?i)((?:dd|datadog)_?api_?key["']?\s*[:=]\s*["']?)[a-f0-9]{32}\b"#
+        }
+    }
+}
+
+/// Replacement of the data matched by a preset, keeping the key name when
+/// the pattern captures it.
+#[must_use]
+pub fn placeholder(preset: ScrubbingPreset) -> &'static str {
+    match preset {
+        ScrubbingPreset::CreditCard => "[REDACTED_CREDIT_CARD]",
+        ScrubbingPreset::AwsAccessKey => "[REDACTED_AWS_ACCESS_KEY]",
+        ScrubbingPreset::AwsSecretKey => "${1}[REDACTED_AWS_SECRET_KEY]",
+        ScrubbingPreset::Jwt => "[REDACTED_JWT]",
+        ScrubbingPreset::BearerToken => "${1}[REDACTED_BEARER_TOKEN]",
+        ScrubbingPreset::Email => "[REDACTED_EMAIL]",
+        ScrubbingPreset::DatadogApiKey => "${1}[REDACTED_DATADOG_API_KEY]",
+    }
+}
+
+/// Check run on every match of a preset, before redacting it.
+#[must_use]
+pub fn validator(preset: ScrubbingPreset) -> Option<fn(&str) -> bool> {
+    match preset {
+        ScrubbingPreset::CreditCard => Some(is_luhn_valid),
+        _ => None,
+    }
+}
+
+/// Whether a card number, ignoring separators, passes the Luhn checksum.
+fn is_luhn_valid(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| {
+            if i % 2 == 0 {
+                digit
+            } else if digit * 2 > 9 {
+                digit * 2 - 9
+            } else {
+                digit * 2
+            }
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_luhn_valid() {
+        assert!(is_luhn_valid("4111111111111111"));
+        assert!(is_luhn_valid("4111 1111 1111 1111"));
+        assert!(is_luhn_valid("5500-0000-0000-0004"));
+        assert!(!is_luhn_valid("4111111111111112"));
+        // Too short to be a card number
+        assert!(!is_luhn_valid("4242"));
+    }
+
+    #[test]
+    fn test_credit_card_pattern() {
+        let regex = regex::Regex::new(pattern(ScrubbingPreset::CreditCard)).unwrap();
+        for card in [
+            "4111111111111111",
+            "4111 1111 1111 1111",
+            "5500-0000-0000-0004",
+            "2221000000000009",
+            "378282246310005",
+            "3782 822463 10005",
+            "6011111111111117",
+        ] {
+            assert!(regex.is_match(&format!("card={card};")), "{card}");
+        }
+        // Epoch timestamps in milliseconds, and numbers of unknown issuers
+        for number in ["1673061827000", "1673061827000123", "9111111111111111"] {
+            assert!(!regex.is_match(&format!("ts={number};")), "{number}");
+        }
+    }
+}
@@ -20,6 +20,12 @@ pub const PRODUCED_BYTES_METRIC: &str = "aws.lambda.enhanced.produced_bytes";
 pub const OUT_OF_MEMORY_METRIC: &str = "aws.lambda.enhanced.out_of_memory";
 pub const TIMEOUTS_METRIC: &str = "aws.lambda.enhanced.timeouts";
 pub const ERRORS_METRIC: &str = "aws.lambda.enhanced.errors";
+pub const INIT_ERRORS_METRIC: &str = "aws.lambda.enhanced.init_errors";
 pub const INVOCATIONS_METRIC: &str = "aws.lambda.enhanced.invocations";
+pub const LOGS_DROPPED_METRIC: &str = "aws.lambda.enhanced.logs_dropped";
+pub const LOGS_REDACTED_METRIC: &str = "aws.lambda.enhanced.logs_redacted";
+pub const PLATFORM_LOGS_DROPPED_METRIC: &str = "aws.lambda.enhanced.platform_logs_dropped";
+pub const PLATFORM_LOGS_DROPPED_BYTES_METRIC: &str =
+    "aws.lambda.enhanced.platform_logs_dropped_bytes";
 //pub const ASM_INVOCATIONS_METRIC: &str = "aws.lambda.enhanced.asm.invocations";
 pub const ENHANCED_METRICS_ENV_VAR: &str = "DD_ENHANCED_METRICS";
@@ -24,6 +24,11 @@ impl Lambda {
         self.increment_metric(constants::ERRORS_METRIC)
     }
 
+    /// Counts the initializations which failed, before any invocation.
+    pub fn increment_init_errors_metric(&self) -> Result<(), errors::Insert> {
+        self.increment_metric(constants::INIT_ERRORS_METRIC)
+    }
+
     pub fn increment_timeout_metric(&self) -> Result<(), errors::Insert> {
         self.increment_metric(constants::TIMEOUTS_METRIC)
     }
@@ -90,6 +95,63 @@ impl Lambda {
         }
     }
 
+    pub fn set_logs_dropped_metric(&self, dropped: usize) {
+        let metric = metric::Metric::new(
+            constants::LOGS_DROPPED_METRIC.into(),
+            metric::Type::Distribution,
+            dropped.to_string().into(),
+            None,
+        );
+        if let Err(e) = self
+            .aggregator
+            .lock()
+            .expect("lock poisoned")
+            .insert(&metric)
+        {
+            error!("failed to insert logs dropped metric: {}", e);
+        }
+    }
+
+    pub fn set_logs_redacted_metric(&self, rule_name: &str, redactions: u64) {
+        let metric = metric::Metric::new(
+            constants::LOGS_REDACTED_METRIC.into(),
+            metric::Type::Distribution,
+            redactions.to_string().into(),
+            Some(format!("rule:{rule_name}").into()),
+        );
+        if let Err(e) = self
+            .aggregator
+            .lock()
+            .expect("lock poisoned")
+            .insert(&metric)
+        {
+            error!("failed to insert logs redacted metric: {}", e);
+        }
+    }
+
+    pub fn set_platform_logs_dropped_metrics(&self, dropped_records: u64, dropped_bytes: u64) {
+        let mut aggr: std::sync::MutexGuard<Aggregator<1024>> =
+            self.aggregator.lock().expect("lock poisoned");
+        let metric = metric::Metric::new(
+            constants::PLATFORM_LOGS_DROPPED_METRIC.into(),
+            metric::Type::Distribution,
+            dropped_records.to_string().into(),
+            None,
+        );
+        if let Err(e) = aggr.insert(&metric) {
+            error!("failed to insert platform logs dropped metric: {}", e);
+        }
+        let metric = metric::Metric::new(
+            constants::PLATFORM_LOGS_DROPPED_BYTES_METRIC.into(),
+            metric::Type::Distribution,
+            dropped_bytes.to_string().into(),
+            None,
+        );
+        if let Err(e) = aggr.insert(&metric) {
+            error!("failed to insert platform logs dropped bytes metric: {}", e);
+        }
+    }
+
     fn calculate_estimated_cost_usd(billed_duration_ms: u64, memory_size_mb: u64) -> f64 {
         let gb_seconds = (billed_duration_ms as f64 * constants::MS_TO_SEC)
             * (memory_size_mb as f64 / constants::MB_TO_GB);
@@ -224,6 +286,66 @@ mod tests {
         };
     }
 
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_increment_init_errors_metric() {
+        let metrics_aggr = setup();
+        let lambda = Lambda::new(metrics_aggr.clone());
+        lambda.increment_init_errors_metric().unwrap();
+        let mut aggr = metrics_aggr.lock().expect("lock poisoned");
+        match aggr.get_value_by_id(constants::INIT_ERRORS_METRIC.into(), None) {
+            Some(ValueVariant::DDSketch(pbuf)) => assert_eq!(1f64, pbuf.sum().unwrap()),
+            _ => panic!("failed to get value by id"),
+        };
+        assert!(aggr
+            .get_value_by_id(constants::ERRORS_METRIC.into(), None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_set_logs_dropped_metric() {
+        let metrics_aggr = setup();
+        let lambda = Lambda::new(metrics_aggr.clone());
+        lambda.set_logs_dropped_metric(42);
+        let mut aggr = metrics_aggr.lock().expect("lock poisoned");
+
+        assert_value(&mut aggr, 42.0, vec![constants::LOGS_DROPPED_METRIC]);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_set_logs_redacted_metric() {
+        let metrics_aggr = setup();
+        let lambda = Lambda::new(metrics_aggr.clone());
+        lambda.set_logs_redacted_metric("credit_card", 3);
+        match metrics_aggr.lock().expect("lock poisoned").get_value_by_id(
+            constants::LOGS_REDACTED_METRIC.into(),
+            Some("rule:credit_card".into()),
+        ) {
+            Some(ValueVariant::DDSketch(pbuf)) => assert_eq!(3f64, pbuf.sum().unwrap()),
+            _ => panic!("failed to get value by id"),
+        };
+    }
+
+    #[test]
+    fn test_set_platform_logs_dropped_metrics() {
+        let metrics_aggr = setup();
+        let lambda = Lambda::new(metrics_aggr.clone());
+        lambda.set_platform_logs_dropped_metrics(3, 1_024);
+        let mut aggr = metrics_aggr.lock().expect("lock poisoned");
+
+        assert_value(
+            &mut aggr,
+            3.0,
+            vec![constants::PLATFORM_LOGS_DROPPED_METRIC],
+        );
+        assert_value(
+            &mut aggr,
+            1_024.0,
+            vec![constants::PLATFORM_LOGS_DROPPED_BYTES_METRIC],
+        );
+    }
+
     #[test]
     fn test_set_report_log_metrics() {
         let metrics_aggr = setup();
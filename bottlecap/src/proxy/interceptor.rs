@@ -0,0 +1,317 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{http, Body, Method, Request, Response, Server, StatusCode};
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::events::Event;
+
+pub const RUNTIME_API_PROXY_PORT: u16 = 9000;
+const INVOCATION_ROUTE: &str = "/runtime/invocation/";
+const REQUEST_ID_HEADER: &str = "lambda-runtime-aws-request-id";
+/// Headers about the connection or the framing of the body, which the client
+/// of each side sets for itself.
+const HOP_BY_HOP_HEADERS: [&str; 10] = [
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Runtime API calls the proxy looks into.
+#[derive(Debug, PartialEq)]
+enum Route {
+    Next,
+    Response(String),
+    Error(String),
+    Other,
+}
+
+impl Route {
+    fn from_request(method: &Method, path: &str) -> Route {
+        let Some((_, invocation_path)) = path.split_once(INVOCATION_ROUTE) else {
+            return Route::Other;
+        };
+        match (method, invocation_path.split_once('/')) {
+            (&Method::GET, None) if invocation_path == "next" => Route::Next,
+            (&Method::POST, Some((request_id, "response"))) => {
+                Route::Response(request_id.to_string())
+            }
+            (&Method::POST, Some((request_id, "error"))) => Route::Error(request_id.to_string()),
+            _ => Route::Other,
+        }
+    }
+}
+
+/// Proxy between the runtime and the Lambda Runtime API, capturing the
+/// invocation events, responses and errors for the spans built by the extension.
+///
+/// The runtime has to call it instead of the Runtime API, by setting
+/// `AWS_LAMBDA_RUNTIME_API` to `127.0.0.1:9000` in the `AWS_LAMBDA_EXEC_WRAPPER` script.
+pub struct RuntimeApiProxy {
+    addr: SocketAddr,
+    runtime_api: String,
+    event_bus: Sender<Event>,
+    cancel_token: CancellationToken,
+}
+
+impl RuntimeApiProxy {
+    #[must_use]
+    pub fn new(
+        port: u16,
+        runtime_api: String,
+        event_bus: Sender<Event>,
+        cancel_token: CancellationToken,
+    ) -> RuntimeApiProxy {
+        RuntimeApiProxy {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            runtime_api,
+            event_bus,
+            cancel_token,
+        }
+    }
+
+    /// Serves requests until cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Function will error if the address cannot be bound.
+    pub async fn spin(self) -> Result<(), Box<dyn std::error::Error>> {
+        let client = reqwest::Client::new();
+        let runtime_api = self.runtime_api;
+        let event_bus = self.event_bus;
+        let make_svc = make_service_fn(move |_| {
+            let client = client.clone();
+            let runtime_api = runtime_api.clone();
+            let event_bus = event_bus.clone();
+            let service = service_fn(move |req| {
+                Self::handle_request(req, client.clone(), runtime_api.clone(), event_bus.clone())
+            });
+
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server = Server::try_bind(&self.addr)?.serve(make_svc);
+        debug!("Runtime API proxy started: listening on {}", self.addr);
+
+        let cancel_token = self.cancel_token;
+        server
+            .with_graceful_shutdown(async move { cancel_token.cancelled().await })
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_request(
+        req: Request<Body>,
+        client: reqwest::Client,
+        runtime_api: String,
+        event_bus: Sender<Event>,
+    ) -> http::Result<Response<Body>> {
+        let (parts, body) = req.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(e) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Failed to read body: {e}")));
+            }
+        };
+
+        let route = Route::from_request(&parts.method, parts.uri.path());
+        let result_event = match &route {
+            Route::Response(request_id) => Some(Event::InvocationResponse(
+                request_id.clone(),
+                parse_body(&body),
+            )),
+            Route::Error(request_id) => Some(Event::InvocationError(
+                request_id.clone(),
+                parse_body(&body),
+            )),
+            Route::Next | Route::Other => None,
+        };
+
+        let path = parts
+            .uri
+            .path_and_query()
+            .map_or(parts.uri.path(), http::uri::PathAndQuery::as_str);
+        let Ok(method) = reqwest::Method::from_bytes(parts.method.as_str().as_bytes()) else {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::empty());
+        };
+        let mut request = client.request(method, format!("http://{runtime_api}{path}"));
+        for (name, value) in &parts.headers {
+            if !is_hop_by_hop(name.as_str()) {
+                request = request.header(name.as_str(), value.as_bytes());
+            }
+        }
+
+        let response = match request.body(body).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Error forwarding request to the Runtime API: {e}");
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::empty());
+            }
+        };
+
+        let mut builder = Response::builder().status(response.status().as_u16());
+        for (name, value) in response.headers() {
+            if !is_hop_by_hop(name.as_str()) {
+                builder = builder.header(name.as_str(), value.as_bytes());
+            }
+        }
+        let request_id = response
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Error reading response from the Runtime API: {e}");
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::empty());
+            }
+        };
+
+        let event = if route == Route::Next {
+            Some(Event::InvocationPayload(request_id, parse_body(&body)))
+        } else {
+            result_event
+        };
+        // The runtime doesn't wait for the main loop to take the event
+        if let Some(event) = event {
+            tokio::spawn(async move {
+                if let Err(e) = event_bus.send(event).await {
+                    error!("Failed to send invocation data to the main event bus: {e}");
+                }
+            });
+        }
+        builder.body(Body::from(body))
+    }
+}
+
+fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name)
+}
+
+/// Payloads are usually JSON, otherwise they're kept as a string.
+fn parse_body(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(
+            Route::from_request(&Method::GET, "/2018-06-01/runtime/invocation/next"),
+            Route::Next
+        );
+        assert_eq!(
+            Route::from_request(&Method::POST, "/2018-06-01/runtime/invocation/abc/response"),
+            Route::Response("abc".to_string())
+        );
+        assert_eq!(
+            Route::from_request(&Method::POST, "/2018-06-01/runtime/invocation/abc/error"),
+            Route::Error("abc".to_string())
+        );
+        assert_eq!(
+            Route::from_request(&Method::POST, "/2018-06-01/runtime/init/error"),
+            Route::Other
+        );
+    }
+
+    #[test]
+    fn test_parse_body() {
+        assert_eq!(parse_body(br#"{"a": 1}"#), json!({"a": 1}));
+        assert_eq!(parse_body(b"plain text"), json!("plain text"));
+    }
+
+    #[test]
+    fn test_is_hop_by_hop() {
+        assert!(is_hop_by_hop("transfer-encoding"));
+        assert!(is_hop_by_hop("content-length"));
+        assert!(!is_hop_by_hop(REQUEST_ID_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_captures_invocation() {
+        // Runtime API returning an event on `next`, accepting responses
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                if req.uri().path().ends_with("/next") {
+                    Response::builder()
+                        .header(REQUEST_ID_HEADER, "request-1")
+                        .header("connection", "keep-alive")
+                        .body(Body::from(r#"{"hello": "world"}"#))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::ACCEPTED)
+                        .body(Body::empty())
+                }
+            }))
+        });
+        let server = Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap()
+            .serve(make_svc);
+        let runtime_api = server.local_addr().to_string();
+        tokio::spawn(server);
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        let client = reqwest::Client::new();
+
+        let req = Request::get("/2018-06-01/runtime/invocation/next")
+            .body(Body::empty())
+            .unwrap();
+        let response =
+            RuntimeApiProxy::handle_request(req, client.clone(), runtime_api.clone(), tx.clone())
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "request-1");
+        assert!(!response.headers().contains_key("connection"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, r#"{"hello": "world"}"#);
+        match rx.recv().await.unwrap() {
+            Event::InvocationPayload(request_id, payload) => {
+                assert_eq!(request_id.as_deref(), Some("request-1"));
+                assert_eq!(payload, json!({"hello": "world"}));
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+
+        let req = Request::post("/2018-06-01/runtime/invocation/request-1/response")
+            .body(Body::from(r#"{"statusCode": 200}"#))
+            .unwrap();
+        let response = RuntimeApiProxy::handle_request(req, client, runtime_api, tx)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        match rx.recv().await.unwrap() {
+            Event::InvocationResponse(request_id, response) => {
+                assert_eq!(request_id, "request-1");
+                assert_eq!(response, json!({"statusCode": 200}));
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+    }
+}
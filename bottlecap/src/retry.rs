@@ -0,0 +1,130 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retries shared by the flushers. A payload is sent up to `MAX_ATTEMPTS`
+//! times with an exponential backoff, then kept for the next flush in a
+//! buffer bounded in size.
+
+use std::future::Future;
+use std::time::Duration;
+
+/// Number of times a payload is sent before being kept for the next flush.
+pub const MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, doubled on each following attempt.
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Outcome of an attempt at sending a payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attempt {
+    Sent,
+    /// Rejected by the intake, sending it again won't help.
+    Rejected,
+    /// Failed on a network error or a status worth retrying.
+    Failed,
+}
+
+/// Calls `send` until the payload is sent or rejected, or `MAX_ATTEMPTS` is
+/// reached. `send` is given the attempt number, starting at 1.
+pub async fn send_with_backoff<F, Fut>(mut send: F) -> Attempt
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Attempt>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let outcome = send(attempt).await;
+        if outcome != Attempt::Failed || attempt == MAX_ATTEMPTS {
+            return outcome;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+    Attempt::Failed
+}
+
+/// Whether a response status is worth retrying: server errors, throttling and
+/// timeouts.
+#[must_use]
+pub fn is_retryable(status: u16) -> bool {
+    (500..600).contains(&status) || status == 408 || status == 429
+}
+
+/// Number of payloads to drop, oldest first, for the rest to fit in
+/// `max_size_bytes`, given the sizes of the payloads from the oldest.
+pub fn oldest_to_drop<I>(sizes: I, max_size_bytes: usize) -> usize
+where
+    I: IntoIterator<Item = usize>,
+    I::IntoIter: Clone,
+{
+    let sizes = sizes.into_iter();
+    let mut size: usize = sizes.clone().sum();
+    let mut dropped = 0;
+    for payload_size in sizes {
+        if size <= max_size_bytes {
+            break;
+        }
+        size -= payload_size;
+        dropped += 1;
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_send_with_backoff() {
+        let attempts = Cell::new(0);
+        let outcome = send_with_backoff(|attempt| {
+            attempts.set(attempt);
+            async move {
+                if attempt < 2 {
+                    Attempt::Failed
+                } else {
+                    Attempt::Sent
+                }
+            }
+        })
+        .await;
+        assert_eq!(outcome, Attempt::Sent);
+        assert_eq!(attempts.get(), 2);
+
+        let outcome = send_with_backoff(|attempt| {
+            attempts.set(attempt);
+            async { Attempt::Rejected }
+        })
+        .await;
+        assert_eq!(outcome, Attempt::Rejected);
+        assert_eq!(attempts.get(), 1);
+
+        let outcome = send_with_backoff(|attempt| {
+            attempts.set(attempt);
+            async { Attempt::Failed }
+        })
+        .await;
+        assert_eq!(outcome, Attempt::Failed);
+        assert_eq!(attempts.get(), MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(500));
+        assert!(is_retryable(503));
+        assert!(is_retryable(429));
+        assert!(is_retryable(408));
+        assert!(!is_retryable(400));
+        assert!(!is_retryable(403));
+        assert!(!is_retryable(200));
+    }
+
+    #[test]
+    fn test_oldest_to_drop() {
+        assert_eq!(oldest_to_drop([4, 4], 10), 0);
+        assert_eq!(oldest_to_drop([4, 4, 4], 10), 1);
+        assert_eq!(oldest_to_drop([8, 1, 1], 2), 1);
+        assert_eq!(oldest_to_drop([20], 10), 1);
+        assert_eq!(oldest_to_drop(Vec::new(), 10), 0);
+    }
+}
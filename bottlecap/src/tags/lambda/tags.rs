@@ -125,10 +125,14 @@ fn tags_from_env(
         }
     }
 
-    tags_map.insert(
-        COMPUTE_STATS_KEY.to_string(),
-        COMPUTE_STATS_VALUE.to_string(),
-    );
+    // Stats of the tracers' spans and of the generated ones are computed by
+    // the extension, unless disabled
+    if !config.compute_trace_stats {
+        tags_map.insert(
+            COMPUTE_STATS_KEY.to_string(),
+            COMPUTE_STATS_VALUE.to_string(),
+        );
+    }
     tags_map
 }
 
@@ -172,11 +176,8 @@ mod tests {
     fn test_new_from_config() {
         let metadata = hash_map::HashMap::new();
         let tags = Lambda::new_from_config(Arc::new(config::Config::default()), &metadata);
-        assert_eq!(tags.tags_map.len(), 2);
-        assert_eq!(
-            tags.tags_map.get(COMPUTE_STATS_KEY).unwrap(),
-            COMPUTE_STATS_VALUE
-        );
+        assert_eq!(tags.tags_map.len(), 1);
+        assert!(!tags.tags_map.contains_key(COMPUTE_STATS_KEY));
         let arch = arch_to_platform();
         assert_eq!(
             tags.tags_map.get(ARCHITECTURE_KEY).unwrap(),
@@ -184,6 +185,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_new_from_config_without_stats_computation() {
+        let metadata = hash_map::HashMap::new();
+        let config = Config {
+            compute_trace_stats: false,
+            ..Config::default()
+        };
+        let tags = Lambda::new_from_config(Arc::new(config), &metadata);
+        assert_eq!(
+            tags.tags_map.get(COMPUTE_STATS_KEY).unwrap(),
+            COMPUTE_STATS_VALUE
+        );
+    }
+
     #[test]
     fn test_new_with_function_arn_metadata() {
         let mut metadata = hash_map::HashMap::new();
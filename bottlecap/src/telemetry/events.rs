@@ -23,6 +23,11 @@ pub enum TelemetryRecord {
     /// Extension log records
     Extension(Value),
 
+    /// Function log records received by the extension's TCP and HTTP log
+    /// listeners, rather than through the Telemetry API
+    #[serde(skip_deserializing)]
+    Listener(Value),
+
     /// Platform init start record
     #[serde(rename = "platform.initStart", rename_all = "camelCase")]
     PlatformInitStart {
@@ -59,6 +64,25 @@ pub enum TelemetryRecord {
         metrics: InitReportMetrics,
     },
 
+    /// Platform restore start record, for `SnapStart` functions
+    #[serde(rename = "platform.restoreStart", rename_all = "camelCase")]
+    PlatformRestoreStart {
+        /// Lambda runtime version
+        runtime_version: Option<String>,
+        /// Lambda runtime version ARN
+        runtime_version_arn: Option<String>,
+    },
+
+    /// Platform restore report record, for `SnapStart` functions
+    #[serde(rename = "platform.restoreReport", rename_all = "camelCase")]
+    PlatformRestoreReport {
+        /// Status of the restore
+        status: Status,
+        /// When unsuccessful, the `error_type` describes what kind of error occurred
+        error_type: Option<String>,
+        metrics: RestoreReportMetrics,
+    },
+
     /// Record marking start of an invocation
     #[serde(rename = "platform.start", rename_all = "camelCase")]
     PlatformStart {
@@ -167,6 +191,14 @@ pub struct InitReportMetrics {
     pub duration_ms: f64,
 }
 
+/// Restore report metrics
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreReportMetrics {
+    /// Duration of the restore
+    pub duration_ms: f64,
+}
+
 /// Runtime done metrics
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -268,6 +300,25 @@ mod tests {
             }
         ),
 
+        // platform.restoreStart
+        platform_restore_start: (
+            r#"{"time":"2022-10-19T13:52:15.636Z","type":"platform.restoreStart","record":{"runtimeVersion":"java:21.v6","runtimeVersionArn":"arn:aws:lambda:us-east-1::runtime:2e5a1e10"}}"#,
+            TelemetryRecord::PlatformRestoreStart {
+                runtime_version: Some("java:21.v6".to_string()),
+                runtime_version_arn: Some("arn:aws:lambda:us-east-1::runtime:2e5a1e10".to_string()),
+            },
+        ),
+
+        // platform.restoreReport
+        platform_restore_report: (
+            r#"{"time":"2022-10-19T13:52:15.836Z","type":"platform.restoreReport","record":{"status":"success","metrics":{"durationMs":200.0}}}"#,
+            TelemetryRecord::PlatformRestoreReport {
+                status: Status::Success,
+                error_type: None,
+                metrics: RestoreReportMetrics { duration_ms: 200.0 },
+            },
+        ),
+
         // platform.start
         platform_start: (
             r#"{"time":"2022-10-21T14:05:03.165Z","type":"platform.start","record":{"requestId":"459921b5-681c-4a96-beb0-81e0aa586026","version":"$LATEST","tracing":{"spanId":"24cd7d670fa455f0","type":"X-Amzn-Trace-Id","value":"Root=1-6352a70e-1e2c502e358361800241fd45;Parent=35465b3a9e2f7c6a;Sampled=1"}}}"#,
@@ -0,0 +1,657 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use datadog_trace_protobuf::pb;
+use serde_json::Value;
+
+/// Span of the upstream service which triggered an invocation, inferred from
+/// the invocation event payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InferredSpan {
+    pub span: pb::Span,
+    /// Whether the trigger waits for the invocation, in which case the span
+    /// lasts until the invocation ends. Otherwise it ends when the invocation
+    /// starts.
+    pub is_async: bool,
+}
+
+/// Builds the span of the trigger of an invocation, with the same names and
+/// tags as the Datadog Lambda libraries. Returns `None` for unknown triggers.
+#[must_use]
+pub fn infer_span(payload: &Value) -> Option<InferredSpan> {
+    if let Some(request_context) = payload.get("requestContext") {
+        // Function URLs have an `apiId` too
+        if str_at(request_context, "/domainName").contains(".lambda-url.") {
+            return Some(http_api(payload, request_context, "aws.lambda.url"));
+        }
+        if request_context.get("apiId").is_some() {
+            if request_context.get("connectionId").is_some() {
+                return Some(api_gateway_websocket(request_context));
+            }
+            if payload.get("version").and_then(Value::as_str) == Some("2.0") {
+                return Some(http_api(payload, request_context, "aws.httpapi"));
+            }
+            return Some(api_gateway_rest(payload, request_context));
+        }
+    }
+
+    if payload.get("detail-type").is_some() && payload.get("source").is_some() {
+        return Some(eventbridge(payload));
+    }
+
+    let record = payload.get("Records")?.get(0)?;
+    let event_source = record
+        .get("eventSource")
+        .or_else(|| record.get("EventSource"))
+        .and_then(Value::as_str)?;
+    match event_source {
+        "aws:sqs" => Some(sqs(record)),
+        "aws:sns" => Some(sns(record)),
+        "aws:kinesis" => Some(kinesis(record)),
+        "aws:dynamodb" => Some(dynamodb(record)),
+        "aws:s3" => Some(s3(record)),
+        _ => None,
+    }
+}
+
+fn api_gateway_rest(payload: &Value, request_context: &Value) -> InferredSpan {
+    let domain = str_at(request_context, "/domainName");
+    let method = str_at(payload, "/httpMethod");
+    let path = str_at(payload, "/path");
+    let resource_path = str_at(payload, "/resource");
+    let resource = format!("{method} {resource_path}");
+
+    let meta = meta([
+        ("operation_name", "aws.apigateway.rest"),
+        ("http.url", &format!("{domain}{path}")),
+        ("endpoint", path),
+        ("http.method", method),
+        ("resource_names", &resource),
+        ("request_id", str_at(request_context, "/requestId")),
+        ("apiid", str_at(request_context, "/apiId")),
+        ("apiname", str_at(request_context, "/apiId")),
+        ("stage", str_at(request_context, "/stage")),
+    ]);
+    inferred_span(
+        "aws.apigateway",
+        domain,
+        resource,
+        "http",
+        ms_at(request_context, "/requestTimeEpoch"),
+        meta,
+        false,
+    )
+}
+
+/// HTTP APIs and Function URLs, which share the payload format 2.0.
+fn http_api(payload: &Value, request_context: &Value, name: &str) -> InferredSpan {
+    let domain = str_at(request_context, "/domainName");
+    let method = str_at(request_context, "/http/method");
+    let path = str_at(request_context, "/http/path");
+    let resource = if name == "aws.httpapi" {
+        str_at(payload, "/routeKey").replace("$default", &format!("{method} {path}"))
+    } else {
+        format!("{method} {path}")
+    };
+
+    let mut meta = meta([
+        ("operation_name", name),
+        ("http.url", &format!("{domain}{path}")),
+        ("endpoint", path),
+        ("http.method", method),
+        ("http.protocol", str_at(request_context, "/http/protocol")),
+        ("http.source_ip", str_at(request_context, "/http/sourceIp")),
+        (
+            "http.user_agent",
+            str_at(request_context, "/http/userAgent"),
+        ),
+        ("resource_names", &resource),
+        ("request_id", str_at(request_context, "/requestId")),
+    ]);
+    if name == "aws.httpapi" {
+        meta.insert(
+            "apiid".to_string(),
+            str_at(request_context, "/apiId").to_string(),
+        );
+        meta.insert(
+            "apiname".to_string(),
+            str_at(request_context, "/apiId").to_string(),
+        );
+        meta.insert(
+            "stage".to_string(),
+            str_at(request_context, "/stage").to_string(),
+        );
+    }
+    inferred_span(
+        name,
+        domain,
+        resource,
+        "http",
+        ms_at(request_context, "/timeEpoch"),
+        meta,
+        false,
+    )
+}
+
+fn api_gateway_websocket(request_context: &Value) -> InferredSpan {
+    let domain = str_at(request_context, "/domainName");
+    let route_key = str_at(request_context, "/routeKey");
+
+    let meta = meta([
+        ("operation_name", "aws.apigateway.websocket"),
+        ("http.url", &format!("{domain}{route_key}")),
+        ("endpoint", route_key),
+        ("resource_names", route_key),
+        ("request_id", str_at(request_context, "/requestId")),
+        ("apiid", str_at(request_context, "/apiId")),
+        ("apiname", str_at(request_context, "/apiId")),
+        ("stage", str_at(request_context, "/stage")),
+        ("connection_id", str_at(request_context, "/connectionId")),
+        ("event_type", str_at(request_context, "/eventType")),
+        (
+            "message_direction",
+            str_at(request_context, "/messageDirection"),
+        ),
+    ]);
+    inferred_span(
+        "aws.apigateway.websocket",
+        domain,
+        route_key.to_string(),
+        "web",
+        ms_at(request_context, "/requestTimeEpoch"),
+        meta,
+        false,
+    )
+}
+
+fn sqs(record: &Value) -> InferredSpan {
+    let arn = str_at(record, "/eventSourceARN");
+    let queue_name = arn.rsplit(':').next().unwrap_or_default();
+
+    let meta = meta([
+        ("operation_name", "aws.sqs"),
+        ("resource_names", queue_name),
+        ("queuename", queue_name),
+        ("event_source_arn", arn),
+        ("receipt_handle", str_at(record, "/receiptHandle")),
+        ("sender_id", str_at(record, "/attributes/SenderId")),
+    ]);
+    let start = str_at(record, "/attributes/SentTimestamp")
+        .parse::<i64>()
+        .map_or(0, ms_to_ns);
+    inferred_span(
+        "aws.sqs",
+        "sqs",
+        queue_name.to_string(),
+        "web",
+        start,
+        meta,
+        true,
+    )
+}
+
+fn sns(record: &Value) -> InferredSpan {
+    let topic_arn = str_at(record, "/Sns/TopicArn");
+    let topic_name = topic_arn.rsplit(':').next().unwrap_or_default();
+
+    let meta = meta([
+        ("operation_name", "aws.sns"),
+        ("resource_names", topic_name),
+        ("topicname", topic_name),
+        ("topic_arn", topic_arn),
+        ("message_id", str_at(record, "/Sns/MessageId")),
+        ("type", str_at(record, "/Sns/Type")),
+        ("subject", str_at(record, "/Sns/Subject")),
+    ]);
+    inferred_span(
+        "aws.sns",
+        "sns",
+        topic_name.to_string(),
+        "web",
+        rfc3339_at(record, "/Sns/Timestamp"),
+        meta,
+        true,
+    )
+}
+
+fn eventbridge(payload: &Value) -> InferredSpan {
+    let source = str_at(payload, "/source");
+
+    let meta = meta([
+        ("operation_name", "aws.eventbridge"),
+        ("resource_names", source),
+        ("detail_type", str_at(payload, "/detail-type")),
+    ]);
+    inferred_span(
+        "aws.eventbridge",
+        "eventbridge",
+        source.to_string(),
+        "web",
+        rfc3339_at(payload, "/time"),
+        meta,
+        true,
+    )
+}
+
+fn kinesis(record: &Value) -> InferredSpan {
+    let arn = str_at(record, "/eventSourceARN");
+    let stream_name = arn.rsplit('/').next().unwrap_or_default();
+    let event_id = str_at(record, "/eventID");
+    let shard_id = event_id.split(':').next().unwrap_or_default();
+
+    let meta = meta([
+        ("operation_name", "aws.kinesis"),
+        ("resource_names", stream_name),
+        ("streamname", stream_name),
+        ("shardid", shard_id),
+        ("event_source_arn", arn),
+        ("event_id", event_id),
+        ("event_name", str_at(record, "/eventName")),
+        ("event_version", str_at(record, "/eventVersion")),
+        ("partition_key", str_at(record, "/kinesis/partitionKey")),
+    ]);
+    inferred_span(
+        "aws.kinesis",
+        "kinesis",
+        stream_name.to_string(),
+        "web",
+        seconds_at(record, "/kinesis/approximateArrivalTimestamp"),
+        meta,
+        true,
+    )
+}
+
+fn dynamodb(record: &Value) -> InferredSpan {
+    let arn = str_at(record, "/eventSourceARN");
+    // arn:aws:dynamodb:<region>:<account>:table/<table>/stream/<label>
+    let table_name = arn.split('/').nth(1).unwrap_or_default();
+    let event_name = str_at(record, "/eventName");
+    let resource = format!("{event_name} {table_name}");
+
+    let size_bytes = record
+        .pointer("/dynamodb/SizeBytes")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+        .to_string();
+    let meta = meta([
+        ("operation_name", "aws.dynamodb"),
+        ("resource_names", &resource),
+        ("tablename", table_name),
+        ("event_source_arn", arn),
+        ("event_id", str_at(record, "/eventID")),
+        ("event_name", event_name),
+        ("event_version", str_at(record, "/eventVersion")),
+        (
+            "stream_view_type",
+            str_at(record, "/dynamodb/StreamViewType"),
+        ),
+        ("size_bytes", &size_bytes),
+    ]);
+    inferred_span(
+        "aws.dynamodb",
+        "aws.dynamodb",
+        resource,
+        "web",
+        seconds_at(record, "/dynamodb/ApproximateCreationDateTime"),
+        meta,
+        true,
+    )
+}
+
+fn s3(record: &Value) -> InferredSpan {
+    let bucket_name = str_at(record, "/s3/bucket/name");
+
+    let object_size = record
+        .pointer("/s3/object/size")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+        .to_string();
+    let meta = meta([
+        ("operation_name", "aws.s3"),
+        ("resource_names", bucket_name),
+        ("event_name", str_at(record, "/eventName")),
+        ("bucketname", bucket_name),
+        ("bucket_arn", str_at(record, "/s3/bucket/arn")),
+        ("object_key", str_at(record, "/s3/object/key")),
+        ("object_size", &object_size),
+        ("object_etag", str_at(record, "/s3/object/eTag")),
+    ]);
+    inferred_span(
+        "aws.s3",
+        "s3",
+        bucket_name.to_string(),
+        "web",
+        rfc3339_at(record, "/eventTime"),
+        meta,
+        true,
+    )
+}
+
+fn inferred_span(
+    name: &str,
+    service: &str,
+    resource: String,
+    r#type: &str,
+    start: i64,
+    mut meta: HashMap<String, String>,
+    is_async: bool,
+) -> InferredSpan {
+    let synchronicity = if is_async { "async" } else { "sync" };
+    meta.insert(
+        "_inferred_span.synchronicity".to_string(),
+        synchronicity.to_string(),
+    );
+    meta.insert("_inferred_span.tag_source".to_string(), "self".to_string());
+
+    InferredSpan {
+        span: pb::Span {
+            service: service.to_string(),
+            name: name.to_string(),
+            resource,
+            start,
+            meta,
+            r#type: r#type.to_string(),
+            ..pb::Span::default()
+        },
+        is_async,
+    }
+}
+
+fn meta<const N: usize>(tags: [(&str, &str); N]) -> HashMap<String, String> {
+    tags.into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn str_at<'a>(value: &'a Value, pointer: &str) -> &'a str {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+fn ms_to_ns(ms: i64) -> i64 {
+    ms.saturating_mul(1_000_000)
+}
+
+/// Timestamp in milliseconds since the epoch, as nanoseconds.
+fn ms_at(value: &Value, pointer: &str) -> i64 {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_i64)
+        .map_or(0, ms_to_ns)
+}
+
+/// Timestamp in fractional seconds since the epoch, as nanoseconds.
+#[allow(clippy::cast_possible_truncation)]
+fn seconds_at(value: &Value, pointer: &str) -> i64 {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_f64)
+        .map_or(0, |seconds| (seconds * 1_000_000_000.0) as i64)
+}
+
+/// RFC 3339 timestamp, as nanoseconds since the epoch.
+fn rfc3339_at(value: &Value, pointer: &str) -> i64 {
+    DateTime::parse_from_rfc3339(str_at(value, pointer))
+        .ok()
+        .and_then(|time| time.timestamp_nanos_opt())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_api_gateway_rest() {
+        let payload = json!({
+            "resource": "/users/{id}",
+            "path": "/users/42",
+            "httpMethod": "GET",
+            "requestContext": {
+                "apiId": "1234567890",
+                "domainName": "1234567890.execute-api.us-east-1.amazonaws.com",
+                "requestId": "c6af9ac6-7b61-11e6-9a41-93e8deadbeef",
+                "requestTimeEpoch": 1_428_582_896_000_i64,
+                "stage": "prod"
+            }
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert!(!inferred.is_async);
+        assert_eq!(inferred.span.name, "aws.apigateway");
+        assert_eq!(
+            inferred.span.service,
+            "1234567890.execute-api.us-east-1.amazonaws.com"
+        );
+        assert_eq!(inferred.span.resource, "GET /users/{id}");
+        assert_eq!(inferred.span.start, 1_428_582_896_000_000_000);
+        assert_eq!(
+            inferred.span.meta["http.url"],
+            "1234567890.execute-api.us-east-1.amazonaws.com/users/42"
+        );
+        assert_eq!(inferred.span.meta["stage"], "prod");
+        assert_eq!(inferred.span.meta["_inferred_span.synchronicity"], "sync");
+    }
+
+    #[test]
+    fn test_http_api() {
+        let payload = json!({
+            "version": "2.0",
+            "routeKey": "POST /orders",
+            "requestContext": {
+                "apiId": "x02yirxc7a",
+                "domainName": "x02yirxc7a.execute-api.sa-east-1.amazonaws.com",
+                "http": {
+                    "method": "POST",
+                    "path": "/orders",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "38.122.226.210",
+                    "userAgent": "curl/7.64.1"
+                },
+                "requestId": "FaHnXjKCGjQEJ7A=",
+                "stage": "$default",
+                "timeEpoch": 1_631_212_283_738_i64
+            }
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.httpapi");
+        assert_eq!(inferred.span.resource, "POST /orders");
+        assert_eq!(inferred.span.start, 1_631_212_283_738_000_000);
+        assert_eq!(inferred.span.meta["http.user_agent"], "curl/7.64.1");
+    }
+
+    #[test]
+    fn test_function_url() {
+        let payload = json!({
+            "version": "2.0",
+            "routeKey": "$default",
+            "requestContext": {
+                "apiId": "a8hyhsshac",
+                "domainName": "a8hyhsshac.lambda-url.eu-south-1.amazonaws.com",
+                "http": { "method": "GET", "path": "/" },
+                "requestId": "ec4d58f8-2b8b-4ceb-a1d5-2be7bff58505",
+                "timeEpoch": 1_659_687_279_885_i64
+            }
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.lambda.url");
+        assert_eq!(
+            inferred.span.service,
+            "a8hyhsshac.lambda-url.eu-south-1.amazonaws.com"
+        );
+        assert_eq!(inferred.span.resource, "GET /");
+        assert!(!inferred.is_async);
+    }
+
+    #[test]
+    fn test_api_gateway_websocket() {
+        let payload = json!({
+            "requestContext": {
+                "routeKey": "$connect",
+                "eventType": "CONNECT",
+                "apiId": "p62c47itsb",
+                "connectionId": "Fc5SzcoYGjQCJlg=",
+                "domainName": "p62c47itsb.execute-api.sa-east-1.amazonaws.com",
+                "requestId": "Fc5S3EvdGjQFtwQ=",
+                "requestTimeEpoch": 1_666_633_666_203_i64,
+                "stage": "dev"
+            }
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.apigateway.websocket");
+        assert_eq!(inferred.span.resource, "$connect");
+        assert_eq!(inferred.span.meta["connection_id"], "Fc5SzcoYGjQCJlg=");
+    }
+
+    #[test]
+    fn test_sqs() {
+        let payload = json!({
+            "Records": [{
+                "receiptHandle": "MessageReceiptHandle",
+                "attributes": {
+                    "SentTimestamp": "1523232000000",
+                    "SenderId": "123456789012"
+                },
+                "eventSource": "aws:sqs",
+                "eventSourceARN": "arn:aws:sqs:us-east-1:123456789012:MyQueue"
+            }]
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert!(inferred.is_async);
+        assert_eq!(inferred.span.name, "aws.sqs");
+        assert_eq!(inferred.span.service, "sqs");
+        assert_eq!(inferred.span.resource, "MyQueue");
+        assert_eq!(inferred.span.start, 1_523_232_000_000_000_000);
+        assert_eq!(inferred.span.meta["_inferred_span.synchronicity"], "async");
+    }
+
+    #[test]
+    fn test_sns() {
+        let payload = json!({
+            "Records": [{
+                "EventSource": "aws:sns",
+                "Sns": {
+                    "Type": "Notification",
+                    "MessageId": "95df01b4-ee98-5cb9-9903-4c221d41eb5e",
+                    "TopicArn": "arn:aws:sns:us-east-1:123456789012:sns-lambda",
+                    "Subject": "TestInvoke",
+                    "Timestamp": "2019-01-02T12:45:07.000Z"
+                }
+            }]
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.sns");
+        assert_eq!(inferred.span.resource, "sns-lambda");
+        assert_eq!(inferred.span.start, 1_546_433_107_000_000_000);
+        assert_eq!(inferred.span.meta["subject"], "TestInvoke");
+    }
+
+    #[test]
+    fn test_eventbridge() {
+        let payload = json!({
+            "detail-type": "EC2 Instance State-change Notification",
+            "source": "aws.ec2",
+            "time": "2015-11-11T21:29:54Z",
+            "detail": {}
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.eventbridge");
+        assert_eq!(inferred.span.resource, "aws.ec2");
+        assert_eq!(inferred.span.start, 1_447_277_394_000_000_000);
+    }
+
+    #[test]
+    fn test_kinesis() {
+        let payload = json!({
+            "Records": [{
+                "kinesis": {
+                    "partitionKey": "1",
+                    "approximateArrivalTimestamp": 1_545_084_650.987
+                },
+                "eventSource": "aws:kinesis",
+                "eventVersion": "1.0",
+                "eventID": "shardId-000000000006:49590338271490256608559692538361571095921575989136588898",
+                "eventName": "aws:kinesis:record",
+                "eventSourceARN": "arn:aws:kinesis:us-east-2:123456789012:stream/lambda-stream"
+            }]
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.kinesis");
+        assert_eq!(inferred.span.resource, "lambda-stream");
+        assert_eq!(inferred.span.meta["shardid"], "shardId-000000000006");
+        assert_eq!(inferred.span.start / 1_000_000, 1_545_084_650_987);
+    }
+
+    #[test]
+    fn test_dynamodb() {
+        let payload = json!({
+            "Records": [{
+                "eventID": "c4ca4238a0b923820dcc509a6f75849b",
+                "eventName": "INSERT",
+                "eventVersion": "1.1",
+                "eventSource": "aws:dynamodb",
+                "dynamodb": {
+                    "ApproximateCreationDateTime": 1_428_537_600,
+                    "SizeBytes": 26,
+                    "StreamViewType": "NEW_AND_OLD_IMAGES"
+                },
+                "eventSourceARN": "arn:aws:dynamodb:us-east-1:123456789012:table/ExampleTableWithStream/stream/2015-06-27T00:48:05.899"
+            }]
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.dynamodb");
+        assert_eq!(inferred.span.resource, "INSERT ExampleTableWithStream");
+        assert_eq!(inferred.span.meta["size_bytes"], "26");
+        assert_eq!(inferred.span.start, 1_428_537_600_000_000_000);
+    }
+
+    #[test]
+    fn test_s3() {
+        let payload = json!({
+            "Records": [{
+                "eventSource": "aws:s3",
+                "eventTime": "1970-01-01T00:00:00.000Z",
+                "eventName": "ObjectCreated:Put",
+                "s3": {
+                    "bucket": {
+                        "name": "example-bucket",
+                        "arn": "arn:aws:s3:::example-bucket"
+                    },
+                    "object": {
+                        "key": "test/key",
+                        "size": 1024,
+                        "eTag": "0123456789abcdef0123456789abcdef"
+                    }
+                }
+            }]
+        });
+
+        let inferred = infer_span(&payload).unwrap();
+        assert_eq!(inferred.span.name, "aws.s3");
+        assert_eq!(inferred.span.resource, "example-bucket");
+        assert_eq!(inferred.span.meta["object_key"], "test/key");
+        assert_eq!(inferred.span.meta["object_size"], "1024");
+    }
+
+    #[test]
+    fn test_unknown_trigger() {
+        assert!(infer_span(&json!({"hello": "world"})).is_none());
+        assert!(infer_span(&json!({"Records": [{"eventSource": "aws:ses"}]})).is_none());
+    }
+}
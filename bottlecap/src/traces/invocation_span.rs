@@ -0,0 +1,1001 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use datadog_trace_protobuf::pb;
+use datadog_trace_utils::config_utils::trace_intake_url;
+use datadog_trace_utils::trace_utils::{self, SendData, TracerHeaderTags};
+use datadog_trace_utils::tracer_payload::TraceEncoding;
+use ddcommon::Endpoint;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::config;
+use crate::lifecycle::invocation_context::InvocationContextBuffer;
+use crate::tags::provider;
+use crate::telemetry::events::{InitType, Status};
+use crate::traces::propagation::{self, SpanContext};
+use crate::traces::stats_concentrator::StatsConcentrator;
+use crate::traces::{inferred_span, payload_tags};
+
+const INVOCATION_SPAN_NAME: &str = "aws.lambda";
+const COLD_START_SPAN_NAME: &str = "aws.lambda.cold_start";
+const INVOCATION_SPAN_TYPE: &str = "serverless";
+/// Service of the spans when none is configured.
+const DEFAULT_SERVICE: &str = "aws.lambda";
+const FUNCTION_NAME_KEY: &str = "functionname";
+/// Keeps the traces of the invocation spans, the extension doesn't sample.
+const SAMPLING_PRIORITY_AUTO_KEEP: f64 = 1.0;
+
+/// Invocation waiting for its end. The Runtime API proxy can report it before
+/// its start is received.
+#[derive(Default)]
+struct Invocation {
+    // Root span first, empty until the invocation start is received
+    spans: Vec<pb::Span>,
+    // Event which triggered the invocation
+    payload: Option<Value>,
+    response: Option<Value>,
+    // Error reported by the runtime
+    error: Option<Value>,
+    // Whether the trace context of the payload was looked for
+    context_resolved: bool,
+}
+
+/// Builds the `aws.lambda` span of each invocation from the telemetry the
+/// extension receives, for runtimes without a Datadog tracer, the
+/// `aws.lambda.cold_start` span of the initialization, and the spans inferred
+/// from the invocation triggers.
+pub struct SpanGenerator {
+    config: Arc<config::Config>,
+    tags_provider: Arc<provider::Provider>,
+    resolved_api_key: String,
+    // Root span ids of the invocations, to correlate their logs
+    invocation_context_buffer: Arc<Mutex<InvocationContextBuffer>>,
+    // Computes the stats of the generated spans, like for the tracers' ones
+    stats_concentrator: Arc<StatsConcentrator>,
+    // Whether the next invocation is the first one of the execution environment
+    cold_start: bool,
+    // Start of the initialization, or of the restore for `SnapStart`
+    init_start: Option<(i64, InitType)>,
+    // Cold start span waiting for the first invocation span to be its parent
+    cold_start_span: Option<pb::Span>,
+    first_request_id: Option<String>,
+    // Trace and span ids of the first invocation span, once sent
+    first_span_ids: Option<(u64, u64)>,
+    last_request_id: Option<String>,
+    // Invocation payload received before the invocation start
+    next_payload: Option<Value>,
+    pending_invocations: HashMap<String, Invocation>,
+}
+
+impl SpanGenerator {
+    #[must_use]
+    pub fn new(
+        config: Arc<config::Config>,
+        tags_provider: Arc<provider::Provider>,
+        resolved_api_key: String,
+        invocation_context_buffer: Arc<Mutex<InvocationContextBuffer>>,
+        stats_concentrator: Arc<StatsConcentrator>,
+    ) -> SpanGenerator {
+        SpanGenerator {
+            config,
+            tags_provider,
+            resolved_api_key,
+            invocation_context_buffer,
+            stats_concentrator,
+            cold_start: true,
+            init_start: None,
+            cold_start_span: None,
+            first_request_id: None,
+            first_span_ids: None,
+            last_request_id: None,
+            next_payload: None,
+            pending_invocations: HashMap::new(),
+        }
+    }
+
+    /// Records the start of the initialization, or of the restore for
+    /// `SnapStart` functions.
+    pub fn on_init_start(&mut self, time: DateTime<Utc>, initialization_type: InitType) {
+        if initialization_type == InitType::ProvisionedConcurrency {
+            // Initialized ahead of the invocations, they don't wait for it
+            self.cold_start = false;
+            return;
+        }
+        self.init_start = Some((
+            time.timestamp_nanos_opt().unwrap_or_default(),
+            initialization_type,
+        ));
+    }
+
+    /// Builds the cold start span once the initialization, or the restore, is
+    /// reported. Returns it when the first invocation span was already sent, to
+    /// be sent on the next flush.
+    pub fn on_init_report(&mut self, duration_ms: f64) -> Option<pb::Span> {
+        let (start, initialization_type) = self.init_start.take()?;
+
+        let mut span = self.new_span(COLD_START_SPAN_NAME, start);
+        span.duration = ms_to_ns(duration_ms);
+        if initialization_type == InitType::SnapStart {
+            span.meta
+                .insert("snap_start".to_string(), "true".to_string());
+        }
+        if let Some((trace_id, span_id)) = self.first_span_ids {
+            span.trace_id = trace_id;
+            span.parent_id = span_id;
+            span.metrics.insert(
+                "_sampling_priority_v1".to_string(),
+                SAMPLING_PRIORITY_AUTO_KEEP,
+            );
+            return Some(span);
+        }
+        self.cold_start_span = Some(span);
+        self.attach_cold_start_span();
+        None
+    }
+
+    /// Cold start span which couldn't be parented to an invocation span, for
+    /// example when the environment shuts down without being invoked.
+    pub fn take_cold_start_span(&mut self) -> Option<pb::Span> {
+        let mut span = self.cold_start_span.take()?;
+        span.metrics.insert("_top_level".to_string(), 1.0);
+        span.metrics.insert(
+            "_sampling_priority_v1".to_string(),
+            SAMPLING_PRIORITY_AUTO_KEEP,
+        );
+        Some(span)
+    }
+
+    /// Starts the span of an invocation, returning its trace and span ids.
+    ///
+    /// Called as soon as the extension is invoked, the ids are recorded right
+    /// away so that every log of the invocation can be correlated with them.
+    pub fn on_invocation_start(&mut self, request_id: &str, time: DateTime<Utc>) -> (u64, u64) {
+        let mut span = self.new_span(
+            INVOCATION_SPAN_NAME,
+            time.timestamp_nanos_opt().unwrap_or_default(),
+        );
+        span.meta
+            .insert("request_id".to_string(), request_id.to_string());
+        span.meta
+            .insert("cold_start".to_string(), self.cold_start.to_string());
+        span.metrics.insert("_top_level".to_string(), 1.0);
+        span.metrics.insert(
+            "_sampling_priority_v1".to_string(),
+            SAMPLING_PRIORITY_AUTO_KEEP,
+        );
+        self.cold_start = false;
+
+        let next_payload = self.next_payload.take();
+        let invocation = self
+            .pending_invocations
+            .entry(request_id.to_string())
+            .or_default();
+        invocation.spans.insert(0, span);
+        if invocation.payload.is_none() {
+            invocation.payload = next_payload;
+        }
+        let ids = (invocation.spans[0].trace_id, invocation.spans[0].span_id);
+        self.record_span_ids(request_id, ids);
+        let ids = self.resolve_context(request_id).unwrap_or(ids);
+
+        self.last_request_id = Some(request_id.to_string());
+        if self.first_request_id.is_none() {
+            self.first_request_id = Some(request_id.to_string());
+            self.attach_cold_start_span();
+        }
+        ids
+    }
+
+    /// Sets the start of the span of an invocation to the one reported by the
+    /// telemetry, starting it if the extension wasn't invoked for it.
+    pub fn on_platform_start(&mut self, request_id: &str, time: DateTime<Utc>) {
+        match self
+            .pending_invocations
+            .get_mut(request_id)
+            .and_then(|invocation| invocation.spans.first_mut())
+        {
+            Some(span) => span.start = time.timestamp_nanos_opt().unwrap_or_default(),
+            None => {
+                self.on_invocation_start(request_id, time);
+            }
+        }
+    }
+
+    /// Records the event which triggered an invocation. Without `request_id`,
+    /// when sent by the tracer, it's for the current invocation, or the next
+    /// one if its start isn't known yet.
+    pub fn on_invocation_payload(&mut self, request_id: Option<&str>, payload: Value) {
+        if let Some(request_id) = request_id {
+            self.pending_invocations
+                .entry(request_id.to_string())
+                .or_default()
+                .payload = Some(payload);
+            self.resolve_context(request_id);
+            return;
+        }
+
+        let Some(request_id) = self.last_request_id.clone() else {
+            self.next_payload = Some(payload);
+            return;
+        };
+        match self.pending_invocations.get_mut(&request_id) {
+            Some(invocation) if invocation.payload.is_none() => {
+                invocation.payload = Some(payload);
+                self.resolve_context(&request_id);
+            }
+            _ => self.next_payload = Some(payload),
+        }
+    }
+
+    /// Continues the trace of the caller of an invocation, once both its start
+    /// and its payload are known. Done only once, since the ids of its span are
+    /// handed out to correlate its logs from then on. Returns the new ids.
+    fn resolve_context(&mut self, request_id: &str) -> Option<(u64, u64)> {
+        let invocation = self.pending_invocations.get_mut(request_id)?;
+        if invocation.context_resolved || invocation.spans.is_empty() {
+            return None;
+        }
+        let payload = invocation.payload.as_ref()?;
+        invocation.context_resolved = true;
+
+        let context = propagation::extract_from_event(payload)?;
+        apply_context(&mut invocation.spans, &context);
+        let ids = (invocation.spans[0].trace_id, invocation.spans[0].span_id);
+        self.record_span_ids(request_id, ids);
+        Some(ids)
+    }
+
+    fn record_span_ids(&self, request_id: &str, (trace_id, span_id): (u64, u64)) {
+        self.invocation_context_buffer
+            .lock()
+            .expect("lock poisoned")
+            .add_span_ids(&request_id.to_string(), trace_id, span_id);
+    }
+
+    /// Records the response of an invocation, captured by the Runtime API proxy.
+    pub fn on_invocation_response(&mut self, request_id: &str, response: Value) {
+        self.pending_invocations
+            .entry(request_id.to_string())
+            .or_default()
+            .response = Some(response);
+    }
+
+    /// Records the error of an invocation, captured by the Runtime API proxy.
+    pub fn on_invocation_error(&mut self, request_id: &str, error: Value) {
+        self.pending_invocations
+            .entry(request_id.to_string())
+            .or_default()
+            .error = Some(error);
+    }
+
+    /// Completes the span of an invocation, with its status and the runtime
+    /// duration, returning the spans of its trace. Returns `None` when the
+    /// invocation start wasn't seen.
+    pub fn on_invocation_end(
+        &mut self,
+        request_id: &str,
+        status: Status,
+        error_type: Option<String>,
+        duration_ms: f64,
+    ) -> Option<Vec<pb::Span>> {
+        let Invocation {
+            mut spans,
+            payload,
+            response,
+            error,
+            ..
+        } = self.pending_invocations.remove(request_id)?;
+        if spans.is_empty() {
+            return None;
+        }
+
+        if self.first_request_id.as_deref() == Some(request_id) {
+            self.first_span_ids = Some((spans[0].trace_id, spans[0].span_id));
+        }
+
+        let span = &mut spans[0];
+        span.duration = ms_to_ns(duration_ms);
+        if let Some(error) = error {
+            add_error_tags(span, &error);
+        }
+        if status != Status::Success {
+            span.error = 1;
+            let error_type = error_type.unwrap_or_else(|| format!("{status:?}"));
+            span.meta
+                .entry("error.type".to_string())
+                .or_insert(error_type);
+            span.meta
+                .entry("error.msg".to_string())
+                .or_insert_with(|| format!("Invocation ended with status: {status:?}"));
+        }
+
+        if self.config.capture_lambda_payload {
+            let max_depth = self.config.capture_lambda_payload_max_depth;
+            let redacted_keys = &self.config.capture_lambda_payload_redacted_keys;
+            if let Some(payload) = &payload {
+                span.meta.extend(payload_tags::payload_tags(
+                    "function.request",
+                    payload,
+                    max_depth,
+                    redacted_keys,
+                ));
+            }
+            if let Some(response) = &response {
+                span.meta.extend(payload_tags::payload_tags(
+                    "function.response",
+                    response,
+                    max_depth,
+                    redacted_keys,
+                ));
+            }
+        }
+
+        if let Some(payload) = payload {
+            if let Some(inferred) = inferred_span::infer_span(&payload) {
+                let inferred_span = link_inferred_span(inferred, span);
+                spans.push(inferred_span);
+            } else {
+                debug!("No span inferred from the payload of invocation {request_id}");
+            }
+        }
+        Some(spans)
+    }
+
+    /// Forgets what's left of an invocation once it's reported: data received
+    /// after its end, or for an invocation whose start wasn't received.
+    pub fn on_invocation_report(&mut self, request_id: &str) {
+        if self.pending_invocations.remove(request_id).is_some() {
+            debug!("Dropping the data left of invocation {request_id}");
+        }
+    }
+
+    /// Payload sending spans to the trace intake, for the trace flusher. Their
+    /// stats are computed at the same time, unless disabled.
+    #[must_use]
+    pub fn send_data(&self, spans: Vec<pb::Span>) -> SendData {
+        self.add_stats(&spans);
+        let size = spans.iter().map(span_size).sum();
+        let header_tags = TracerHeaderTags::default();
+        let payload = trace_utils::collect_trace_chunks(
+            vec![spans],
+            &header_tags,
+            |_chunk, _root_span_index| {},
+            true,
+            TraceEncoding::V07,
+        );
+
+        let intake_url = trace_intake_url(&self.config.site);
+        let endpoint = Endpoint {
+            url: hyper::Uri::from_str(&intake_url).expect("can't parse trace intake URL, exiting"),
+            api_key: Some(self.resolved_api_key.clone().into()),
+        };
+        SendData::new(size, payload, header_tags, &endpoint)
+    }
+
+    fn add_stats(&self, spans: &[pb::Span]) {
+        if !self.config.compute_trace_stats {
+            return;
+        }
+        self.stats_concentrator.add(&pb::TracerPayload {
+            chunks: vec![pb::TraceChunk {
+                spans: spans.to_vec(),
+                ..pb::TraceChunk::default()
+            }],
+            ..pb::TracerPayload::default()
+        });
+    }
+
+    fn new_span(&self, name: &str, start: i64) -> pb::Span {
+        let tags_map = self.tags_provider.get_tags_map();
+        let mut meta = tags_map.clone();
+        meta.insert("_dd.origin".to_string(), "lambda".to_string());
+        meta.insert("origin".to_string(), "lambda".to_string());
+
+        pb::Span {
+            service: self
+                .config
+                .service
+                .clone()
+                .unwrap_or_else(|| DEFAULT_SERVICE.to_string()),
+            name: name.to_string(),
+            resource: tags_map
+                .get(FUNCTION_NAME_KEY)
+                .cloned()
+                .unwrap_or_else(|| name.to_string()),
+            trace_id: generate_id(),
+            span_id: generate_id(),
+            parent_id: 0,
+            start,
+            meta,
+            r#type: INVOCATION_SPAN_TYPE.to_string(),
+            ..pb::Span::default()
+        }
+    }
+
+    /// Parents the cold start span to the span of the first invocation, if
+    /// both are known and the invocation is still running.
+    fn attach_cold_start_span(&mut self) {
+        let Some(invocation) = self
+            .first_request_id
+            .as_ref()
+            .and_then(|request_id| self.pending_invocations.get_mut(request_id))
+        else {
+            return;
+        };
+        let Some(root_span) = invocation.spans.first() else {
+            return;
+        };
+        if let Some(mut span) = self.cold_start_span.take() {
+            span.trace_id = root_span.trace_id;
+            span.parent_id = root_span.span_id;
+            invocation.spans.push(span);
+        }
+    }
+}
+
+/// Continues the trace of the caller of the invocation.
+fn apply_context(spans: &mut [pb::Span], context: &SpanContext) {
+    for span in spans.iter_mut() {
+        span.trace_id = context.trace_id;
+    }
+    let root_span = &mut spans[0];
+    root_span.parent_id = context.span_id;
+    if let Some(sampling_priority) = context.sampling_priority {
+        root_span
+            .metrics
+            .insert("_sampling_priority_v1".to_string(), sampling_priority);
+    }
+    if let Some(origin) = &context.origin {
+        root_span
+            .meta
+            .insert("_dd.origin".to_string(), origin.clone());
+    }
+    root_span.meta.extend(context.tags.clone());
+}
+
+/// Error tags from the error reported by the runtime, i.e.
+/// `{"errorMessage": "...", "errorType": "...", "stackTrace": ["..."]}`.
+fn add_error_tags(span: &mut pb::Span, error: &Value) {
+    span.error = 1;
+    if let Some(message) = error.get("errorMessage").and_then(Value::as_str) {
+        span.meta
+            .insert("error.msg".to_string(), message.to_string());
+    }
+    if let Some(error_type) = error.get("errorType").and_then(Value::as_str) {
+        span.meta
+            .insert("error.type".to_string(), error_type.to_string());
+    }
+    let stack = match error.get("stackTrace") {
+        Some(Value::Array(frames)) => frames
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(Value::String(stack)) => stack.clone(),
+        _ => String::new(),
+    };
+    if !stack.is_empty() {
+        span.meta.insert("error.stack".to_string(), stack);
+    }
+}
+
+/// Makes the inferred span the root of the invocation trace, as the parent of
+/// the invocation span.
+fn link_inferred_span(
+    inferred: inferred_span::InferredSpan,
+    invocation_span: &mut pb::Span,
+) -> pb::Span {
+    let mut span = inferred.span;
+    span.trace_id = invocation_span.trace_id;
+    span.span_id = generate_id();
+    // The inferred span takes the place of the invocation span in the trace
+    span.parent_id = invocation_span.parent_id;
+    invocation_span.parent_id = span.span_id;
+
+    // Timestamps from the payload can be missing, or skewed
+    if span.start <= 0 || span.start > invocation_span.start {
+        span.start = invocation_span.start;
+    }
+    let end = if inferred.is_async {
+        invocation_span.start
+    } else {
+        invocation_span.start + invocation_span.duration
+    };
+    span.duration = end - span.start;
+
+    let sampling_priority = invocation_span
+        .metrics
+        .get("_sampling_priority_v1")
+        .copied()
+        .unwrap_or(SAMPLING_PRIORITY_AUTO_KEEP);
+    span.metrics.insert("_top_level".to_string(), 1.0);
+    span.metrics
+        .insert("_sampling_priority_v1".to_string(), sampling_priority);
+    span
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn ms_to_ns(duration_ms: f64) -> i64 {
+    (duration_ms * 1_000_000.0) as i64
+}
+
+/// Random id for a trace or a span.
+fn generate_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_i64(Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    // Ids are sent as signed integers by some tracers, keep them positive
+    hasher.finish() >> 1
+}
+
+/// Approximate size of a span once serialized.
+fn span_size(span: &pb::Span) -> usize {
+    let meta_size: usize = span.meta.iter().map(|(k, v)| k.len() + v.len()).sum();
+    let metrics_size: usize = span.metrics.keys().map(|k| k.len() + 8).sum();
+    span.service.len()
+        + span.name.len()
+        + span.resource.len()
+        + span.r#type.len()
+        + meta_size
+        + metrics_size
+        + 64
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::LAMBDA_RUNTIME_SLUG;
+
+    fn span_generator() -> SpanGenerator {
+        let config = Arc::new(config::Config {
+            service: Some("test-service".to_string()),
+            ..config::Config::default()
+        });
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::from([(
+                "function_arn".to_string(),
+                "arn:aws:lambda:us-east-1:123456789012:function:my-function".to_string(),
+            )]),
+        ));
+        SpanGenerator::new(
+            config,
+            tags_provider,
+            "api-key".to_string(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+            Arc::new(StatsConcentrator::new(None, None)),
+        )
+    }
+
+    #[test]
+    fn test_invocation_span() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        // Invoked slightly before the start reported by the telemetry
+        let (trace_id, span_id) =
+            generator.on_invocation_start("request-1", time - chrono::Duration::milliseconds(2));
+        generator.on_platform_start("request-1", time);
+        let span = generator
+            .on_invocation_end("request-1", Status::Success, None, 12.5)
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(span.trace_id, trace_id);
+        assert_eq!(span.span_id, span_id);
+        assert_eq!(span.parent_id, 0);
+        assert_eq!(span.name, "aws.lambda");
+        assert_eq!(span.service, "test-service");
+        assert_eq!(span.resource, "my-function");
+        assert_eq!(span.start, 1_673_061_827_000_000_000);
+        assert_eq!(span.duration, 12_500_000);
+        assert_eq!(span.error, 0);
+        assert_eq!(span.meta["request_id"], "request-1");
+        assert_eq!(span.meta["cold_start"], "true");
+        assert_eq!(
+            span.meta["function_arn"],
+            "arn:aws:lambda:us-east-1:123456789012:function:my-function"
+        );
+        assert!(span.metrics.contains_key("_top_level"));
+    }
+
+    #[test]
+    fn test_invocation_span_error() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_invocation_start("request-1", time);
+        let span = generator
+            .on_invocation_end(
+                "request-1",
+                Status::Error,
+                Some("Runtime.ExitError".to_string()),
+                5.0,
+            )
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(span.error, 1);
+        assert_eq!(span.meta["error.type"], "Runtime.ExitError");
+    }
+
+    #[test]
+    fn test_cold_start_only_on_first_invocation() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_invocation_start("request-1", time);
+        generator.on_invocation_start("request-2", time);
+        let first = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap()
+            .remove(0);
+        let second = generator
+            .on_invocation_end("request-2", Status::Success, None, 1.0)
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(first.meta["cold_start"], "true");
+        assert_eq!(second.meta["cold_start"], "false");
+        assert_ne!(first.trace_id, second.trace_id);
+    }
+
+    #[test]
+    fn test_invocation_end_without_start() {
+        let mut generator = span_generator();
+        assert!(generator
+            .on_invocation_end("unknown", Status::Success, None, 1.0)
+            .is_none());
+    }
+
+    #[test]
+    fn test_invocation_report_without_start() {
+        let mut generator = span_generator();
+        generator.on_invocation_payload(Some("unknown"), serde_json::json!({}));
+        generator.on_invocation_response("unknown", serde_json::json!({}));
+        assert!(generator
+            .on_invocation_end("unknown", Status::Success, None, 1.0)
+            .is_none());
+
+        // Received after the end
+        generator.on_invocation_error("unknown", serde_json::json!({}));
+        generator.on_invocation_report("unknown");
+        assert!(generator.pending_invocations.is_empty());
+    }
+
+    #[test]
+    fn test_cold_start_span() {
+        let mut generator = span_generator();
+        let init_time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 46).unwrap();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_init_start(init_time, InitType::OnDemand);
+        assert!(generator.on_init_report(800.0).is_none());
+        let (trace_id, span_id) = generator.on_invocation_start("request-1", time);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+
+        assert_eq!(spans.len(), 2);
+        let cold_start_span = &spans[1];
+        assert_eq!(cold_start_span.name, "aws.lambda.cold_start");
+        assert_eq!(cold_start_span.trace_id, trace_id);
+        assert_eq!(cold_start_span.parent_id, span_id);
+        assert_eq!(cold_start_span.start, 1_673_061_826_000_000_000);
+        assert_eq!(cold_start_span.duration, 800_000_000);
+        assert!(generator.take_cold_start_span().is_none());
+    }
+
+    #[test]
+    fn test_cold_start_span_reported_during_first_invocation() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_init_start(time, InitType::SnapStart);
+        let (_, span_id) = generator.on_invocation_start("request-1", time);
+        assert!(generator.on_init_report(200.0).is_none());
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[1].parent_id, span_id);
+        assert_eq!(spans[1].meta["snap_start"], "true");
+    }
+
+    #[test]
+    fn test_cold_start_span_reported_after_first_invocation() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_init_start(time, InitType::OnDemand);
+        let (trace_id, span_id) = generator.on_invocation_start("request-1", time);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+        assert_eq!(spans.len(), 1);
+
+        let span = generator.on_init_report(800.0).unwrap();
+        assert_eq!(span.trace_id, trace_id);
+        assert_eq!(span.parent_id, span_id);
+        assert!(
+            (span.metrics["_sampling_priority_v1"] - SAMPLING_PRIORITY_AUTO_KEEP).abs()
+                < f64::EPSILON
+        );
+        assert!(generator.take_cold_start_span().is_none());
+    }
+
+    #[test]
+    fn test_cold_start_span_without_invocation() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_init_start(time, InitType::OnDemand);
+        assert!(generator.on_init_report(800.0).is_none());
+        let span = generator.take_cold_start_span().unwrap();
+
+        assert_eq!(span.parent_id, 0);
+        assert!(span.metrics.contains_key("_top_level"));
+    }
+
+    #[test]
+    fn test_no_cold_start_with_provisioned_concurrency() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_init_start(time, InitType::ProvisionedConcurrency);
+        assert!(generator.on_init_report(800.0).is_none());
+        generator.on_invocation_start("request-1", time);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].meta["cold_start"], "false");
+    }
+
+    #[test]
+    fn test_inferred_span() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+        let payload = serde_json::json!({
+            "Records": [{
+                "attributes": { "SentTimestamp": "1673061826000" },
+                "eventSource": "aws:sqs",
+                "eventSourceARN": "arn:aws:sqs:us-east-1:123456789012:MyQueue"
+            }]
+        });
+
+        // Sent by the tracer before the invocation start is received
+        generator.on_invocation_payload(None, payload);
+        let (trace_id, span_id) = generator.on_invocation_start("request-1", time);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+
+        assert_eq!(spans.len(), 2);
+        let inferred = &spans[1];
+        assert_eq!(inferred.name, "aws.sqs");
+        assert_eq!(inferred.trace_id, trace_id);
+        assert_eq!(inferred.parent_id, 0);
+        assert_eq!(spans[0].span_id, span_id);
+        assert_eq!(spans[0].parent_id, inferred.span_id);
+        assert_eq!(inferred.start, 1_673_061_826_000_000_000);
+        // Asynchronous, ends when the invocation starts
+        assert_eq!(inferred.duration, 1_000_000_000);
+    }
+
+    #[test]
+    fn test_inferred_span_sync() {
+        let mut generator = span_generator();
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+        let payload = serde_json::json!({
+            "version": "2.0",
+            "routeKey": "GET /",
+            "requestContext": {
+                "apiId": "x02yirxc7a",
+                "domainName": "x02yirxc7a.execute-api.sa-east-1.amazonaws.com",
+                "http": { "method": "GET", "path": "/" },
+                "timeEpoch": 1_673_061_826_500_i64
+            }
+        });
+
+        generator.on_invocation_start("request-1", time);
+        generator.on_invocation_payload(None, payload);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 250.0)
+            .unwrap();
+
+        assert_eq!(spans[1].name, "aws.httpapi");
+        assert_eq!(spans[1].duration, 750_000_000);
+    }
+
+    #[test]
+    fn test_invocation_from_proxy() {
+        let config = Arc::new(config::Config {
+            capture_lambda_payload: true,
+            ..config::Config::default()
+        });
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::new(),
+        ));
+        let mut generator = SpanGenerator::new(
+            config,
+            tags_provider,
+            "api-key".to_string(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+            Arc::new(StatsConcentrator::new(None, None)),
+        );
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        // The proxy sees the invocation before its start is received
+        generator.on_invocation_payload(
+            Some("request-1"),
+            serde_json::json!({
+                "headers": {
+                    "x-datadog-trace-id": "1234",
+                    "x-datadog-parent-id": "5678",
+                    "authorization": "Bearer abc"
+                }
+            }),
+        );
+        generator.on_invocation_error(
+            "request-1",
+            serde_json::json!({
+                "errorMessage": "Something went wrong",
+                "errorType": "ValueError",
+                "stackTrace": ["at handler (index.js:3)", "at run (index.js:10)"]
+            }),
+        );
+        let (trace_id, _) = generator.on_invocation_start("request-1", time);
+        let span = generator
+            .on_invocation_end("request-1", Status::Error, None, 1.0)
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(trace_id, 1234);
+        assert_eq!(span.trace_id, 1234);
+        assert_eq!(span.parent_id, 5678);
+        assert_eq!(span.error, 1);
+        assert_eq!(span.meta["error.msg"], "Something went wrong");
+        assert_eq!(span.meta["error.type"], "ValueError");
+        assert_eq!(
+            span.meta["error.stack"],
+            "at handler (index.js:3)\nat run (index.js:10)"
+        );
+        assert_eq!(
+            span.meta["function.request.headers.authorization"],
+            "redacted"
+        );
+    }
+
+    #[test]
+    fn test_context_from_payload_after_start() {
+        let invocation_context_buffer = Arc::new(Mutex::new(InvocationContextBuffer::default()));
+        let config = Arc::new(config::Config::default());
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::new(),
+        ));
+        let mut generator = SpanGenerator::new(
+            config,
+            tags_provider,
+            "api-key".to_string(),
+            Arc::clone(&invocation_context_buffer),
+            Arc::new(StatsConcentrator::new(None, None)),
+        );
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+        let payload = |trace_id: &str| {
+            serde_json::json!({
+                "headers": {
+                    "x-datadog-trace-id": trace_id,
+                    "x-datadog-parent-id": "5678",
+                }
+            })
+        };
+
+        let (_, span_id) = generator.on_invocation_start("request-1", time);
+        generator.on_invocation_payload(Some("request-1"), payload("1234"));
+        {
+            let buffer = invocation_context_buffer.lock().unwrap();
+            let context = buffer.get(&"request-1".to_string()).unwrap();
+            assert_eq!(context.trace_id, 1234);
+            assert_eq!(context.span_id, span_id);
+        }
+
+        // Frozen once resolved
+        generator.on_invocation_payload(Some("request-1"), payload("4321"));
+        let span = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(span.trace_id, 1234);
+        assert_eq!(span.span_id, span_id);
+        assert_eq!(span.parent_id, 5678);
+    }
+
+    #[test]
+    fn test_invocation_response_capture() {
+        let config = Arc::new(config::Config {
+            capture_lambda_payload: true,
+            ..config::Config::default()
+        });
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::new(),
+        ));
+        let mut generator = SpanGenerator::new(
+            config,
+            tags_provider,
+            "api-key".to_string(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+            Arc::new(StatsConcentrator::new(None, None)),
+        );
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_invocation_start("request-1", time);
+        generator.on_invocation_response(
+            "request-1",
+            serde_json::json!({"statusCode": 200, "body": "ok"}),
+        );
+        let span = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap()
+            .remove(0);
+
+        assert_eq!(span.error, 0);
+        assert_eq!(span.meta["function.response.statusCode"], "200");
+        assert_eq!(span.meta["function.response.body"], "ok");
+    }
+
+    #[test]
+    fn test_generated_span_stats() {
+        let config = Arc::new(config::Config::default());
+        let tags_provider = Arc::new(provider::Provider::new(
+            Arc::clone(&config),
+            LAMBDA_RUNTIME_SLUG.to_string(),
+            &HashMap::new(),
+        ));
+        let stats_concentrator = Arc::new(StatsConcentrator::new(None, None));
+        let mut generator = SpanGenerator::new(
+            config,
+            tags_provider,
+            "api-key".to_string(),
+            Arc::new(Mutex::new(InvocationContextBuffer::default())),
+            Arc::clone(&stats_concentrator),
+        );
+        let time = Utc.with_ymd_and_hms(2023, 1, 7, 3, 23, 47).unwrap();
+
+        generator.on_invocation_start("request-1", time);
+        let spans = generator
+            .on_invocation_end("request-1", Status::Success, None, 1.0)
+            .unwrap();
+        generator.add_stats(&spans);
+
+        let stats = stats_concentrator.flush();
+        let grouped = &stats[0].stats[0].stats[0];
+        assert_eq!(grouped.name, "aws.lambda");
+        assert_eq!(grouped.hits, 1);
+        assert_eq!(grouped.top_level_hits, 1);
+    }
+}
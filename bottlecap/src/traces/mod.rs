@@ -1,8 +1,17 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod inferred_span;
+pub mod invocation_span;
+pub mod otlp;
+pub mod payload_tags;
+pub mod priority_sampler;
+pub mod propagation;
+pub mod samplers;
+pub mod stats_concentrator;
 pub mod stats_flusher;
 pub mod stats_processor;
 pub mod trace_agent;
+pub mod trace_filter;
 pub mod trace_flusher;
 pub mod trace_processor;
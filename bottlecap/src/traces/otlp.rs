@@ -0,0 +1,601 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use base64::prelude::*;
+use datadog_trace_protobuf::pb;
+use flate2::read::GzDecoder;
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{any_value, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::trace::v1::span::{Link, SpanKind};
+use opentelemetry_proto::tonic::trace::v1::status::StatusCode;
+use opentelemetry_proto::tonic::trace::v1::Span;
+use prost::Message;
+use serde_json::Value;
+
+const DEFAULT_SERVICE_NAME: &str = "otlpresourcenoservicename";
+const DEFAULT_SCOPE_NAME: &str = "opentelemetry";
+const EXCEPTION_EVENT_NAME: &str = "exception";
+
+type Attributes<'a> = HashMap<&'a str, &'a any_value::Value>;
+
+/// Encoding of an OTLP/HTTP request, and of its response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
+
+impl Encoding {
+    #[must_use]
+    pub fn from_content_type(content_type: Option<&str>) -> Encoding {
+        match content_type {
+            Some(content_type) if content_type.starts_with("application/json") => Encoding::Json,
+            _ => Encoding::Protobuf,
+        }
+    }
+
+    #[must_use]
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Protobuf => "application/x-protobuf",
+            Encoding::Json => "application/json",
+        }
+    }
+}
+
+/// Decodes the body of an OTLP/HTTP export request, gzip compressed or not.
+///
+/// # Errors
+///
+/// Function will error if the body cannot be decompressed or decoded.
+pub fn decode_request(
+    body: &[u8],
+    encoding: Encoding,
+    gzipped: bool,
+) -> Result<ExportTraceServiceRequest, Box<dyn std::error::Error + Send + Sync>> {
+    let mut decompressed = Vec::new();
+    let body = if gzipped {
+        GzDecoder::new(body).read_to_end(&mut decompressed)?;
+        decompressed.as_slice()
+    } else {
+        body
+    };
+
+    match encoding {
+        Encoding::Protobuf => Ok(ExportTraceServiceRequest::decode(body)?),
+        Encoding::Json => Ok(serde_json::from_slice(body)?),
+    }
+}
+
+/// Body of the response to a fully accepted export request.
+#[must_use]
+pub fn encode_response(encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Protobuf => ExportTraceServiceResponse::default().encode_to_vec(),
+        Encoding::Json => b"{}".to_vec(),
+    }
+}
+
+/// Language of the OpenTelemetry SDK which sent the request.
+#[must_use]
+pub fn sdk_language(request: &ExportTraceServiceRequest) -> Option<&str> {
+    request
+        .resource_spans
+        .iter()
+        .filter_map(|resource_spans| resource_spans.resource.as_ref())
+        .find_map(|resource| {
+            as_str(attributes(&resource.attributes).get("telemetry.sdk.language")?)
+        })
+}
+
+/// Converts OTLP spans to Datadog spans, grouped by trace, following the
+/// mapping of the Datadog Agent's OTLP receiver.
+#[must_use]
+pub fn to_dd_traces(request: &ExportTraceServiceRequest) -> Vec<Vec<pb::Span>> {
+    let mut traces: Vec<Vec<pb::Span>> = Vec::new();
+    let mut trace_indexes: HashMap<&[u8], usize> = HashMap::new();
+    for resource_spans in &request.resource_spans {
+        let resource = resource_spans
+            .resource
+            .as_ref()
+            .map(|resource| attributes(&resource.attributes))
+            .unwrap_or_default();
+        for scope_spans in &resource_spans.scope_spans {
+            for span in &scope_spans.spans {
+                let index = *trace_indexes
+                    .entry(span.trace_id.as_slice())
+                    .or_insert_with(|| {
+                        traces.push(Vec::new());
+                        traces.len() - 1
+                    });
+                traces[index].push(convert_span(span, &resource, scope_spans.scope.as_ref()));
+            }
+        }
+    }
+    traces
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn convert_span(
+    span: &Span,
+    resource: &Attributes,
+    scope: Option<&InstrumentationScope>,
+) -> pb::Span {
+    let span_attributes = attributes(&span.attributes);
+    // Span attributes take precedence over the resource's
+    let mut all_attributes = resource.clone();
+    all_attributes.extend(span_attributes.iter().map(|(key, value)| (*key, *value)));
+    let kind = span.kind();
+
+    let mut meta: HashMap<String, String> = resource
+        .iter()
+        .map(|(key, value)| ((*key).to_string(), to_string(value)))
+        .collect();
+    let mut metrics = HashMap::new();
+    for (key, value) in &span_attributes {
+        match value {
+            any_value::Value::IntValue(i) => {
+                metrics.insert((*key).to_string(), *i as f64);
+            }
+            any_value::Value::DoubleValue(d) => {
+                metrics.insert((*key).to_string(), *d);
+            }
+            _ => {
+                meta.insert((*key).to_string(), to_string(value));
+            }
+        }
+    }
+
+    if let Some(env) = str_attribute(&all_attributes, "deployment.environment.name")
+        .or_else(|| str_attribute(&all_attributes, "deployment.environment"))
+    {
+        meta.insert("env".to_string(), env.to_string());
+    }
+    if let Some(version) = str_attribute(&all_attributes, "service.version") {
+        meta.insert("version".to_string(), version.to_string());
+    }
+    if let Some(status_code) = all_attributes
+        .get("http.response.status_code")
+        .or_else(|| all_attributes.get("http.status_code"))
+    {
+        meta.insert("http.status_code".to_string(), to_string(status_code));
+    }
+
+    let (trace_id, trace_id_high) = trace_id_parts(&span.trace_id);
+    meta.insert("otel.trace_id".to_string(), hex::encode(&span.trace_id));
+    if trace_id_high != 0 {
+        meta.insert("_dd.p.tid".to_string(), format!("{trace_id_high:016x}"));
+    }
+    meta.insert("span.kind".to_string(), span_kind_name(kind).to_string());
+    if let Some(scope) = scope.filter(|scope| !scope.name.is_empty()) {
+        meta.insert("otel.library.name".to_string(), scope.name.clone());
+        if !scope.version.is_empty() {
+            meta.insert("otel.library.version".to_string(), scope.version.clone());
+        }
+    }
+
+    let error = add_status_tags(&mut meta, span);
+
+    let parent_id = span_id(&span.parent_span_id);
+    match kind {
+        SpanKind::Server | SpanKind::Consumer => {
+            metrics.insert("_top_level".to_string(), 1.0);
+        }
+        SpanKind::Client | SpanKind::Producer => {
+            metrics.insert("_dd.measured".to_string(), 1.0);
+        }
+        SpanKind::Unspecified | SpanKind::Internal => {}
+    }
+    if parent_id == 0 {
+        metrics.insert("_top_level".to_string(), 1.0);
+    }
+
+    let start = i64::try_from(span.start_time_unix_nano).unwrap_or(i64::MAX);
+    let end = i64::try_from(span.end_time_unix_nano).unwrap_or(i64::MAX);
+    pb::Span {
+        service: str_attribute(&all_attributes, "service.name")
+            .unwrap_or(DEFAULT_SERVICE_NAME)
+            .to_string(),
+        name: operation_name(kind, &all_attributes, scope),
+        resource: resource_name(span, kind, &all_attributes),
+        trace_id,
+        span_id: span_id(&span.span_id),
+        parent_id,
+        start,
+        duration: end.saturating_sub(start).max(0),
+        error,
+        meta,
+        metrics,
+        r#type: span_type(kind, &all_attributes),
+        span_links: span.links.iter().map(span_link).collect(),
+        ..pb::Span::default()
+    }
+}
+
+/// `otel.status_code` and `otel.status_description`, with the error tags of
+/// failed spans. Returns the span's `error`.
+fn add_status_tags(meta: &mut HashMap<String, String>, span: &Span) -> i32 {
+    let status = span.status.clone().unwrap_or_default();
+    let status_code = status.code();
+    meta.insert(
+        "otel.status_code".to_string(),
+        match status_code {
+            StatusCode::Unset => "Unset",
+            StatusCode::Ok => "Ok",
+            StatusCode::Error => "Error",
+        }
+        .to_string(),
+    );
+    if !status.message.is_empty() {
+        meta.insert(
+            "otel.status_description".to_string(),
+            status.message.clone(),
+        );
+    }
+    if status_code != StatusCode::Error {
+        return 0;
+    }
+
+    // From the exception recorded on the span, otherwise the status message
+    if let Some(exception) = span
+        .events
+        .iter()
+        .rev()
+        .find(|event| event.name == EXCEPTION_EVENT_NAME)
+    {
+        let exception = attributes(&exception.attributes);
+        for (attribute, tag) in [
+            ("exception.message", "error.msg"),
+            ("exception.type", "error.type"),
+            ("exception.stacktrace", "error.stack"),
+        ] {
+            if let Some(value) = str_attribute(&exception, attribute) {
+                meta.insert(tag.to_string(), value.to_string());
+            }
+        }
+    }
+    if !meta.contains_key("error.msg") && !status.message.is_empty() {
+        meta.insert("error.msg".to_string(), status.message);
+    }
+    1
+}
+
+fn span_link(link: &Link) -> pb::SpanLink {
+    let (trace_id, trace_id_high) = trace_id_parts(&link.trace_id);
+    pb::SpanLink {
+        trace_id,
+        trace_id_high,
+        span_id: span_id(&link.span_id),
+        attributes: attributes(&link.attributes)
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), to_string(value)))
+            .collect(),
+        tracestate: link.trace_state.clone(),
+        flags: link.flags,
+    }
+}
+
+/// `operation.name` when set, otherwise the instrumentation scope and span
+/// kind, i.e. `opentelemetry.server`.
+fn operation_name(
+    kind: SpanKind,
+    attributes: &Attributes,
+    scope: Option<&InstrumentationScope>,
+) -> String {
+    if let Some(name) = str_attribute(attributes, "operation.name") {
+        return name.to_string();
+    }
+    let scope_name = scope
+        .map(|scope| scope.name.as_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(DEFAULT_SCOPE_NAME);
+    format!("{scope_name}.{}", span_kind_name(kind))
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn resource_name(span: &Span, kind: SpanKind, attributes: &Attributes) -> String {
+    let get = |key| str_attribute(attributes, key);
+    if let Some(resource) = get("resource.name") {
+        return resource.to_string();
+    }
+    if let Some(method) = get("http.request.method").or_else(|| get("http.method")) {
+        let method = if method == "_OTHER" { "HTTP" } else { method };
+        return match get("http.route") {
+            Some(route) if kind == SpanKind::Server => format!("{method} {route}"),
+            _ => method.to_string(),
+        };
+    }
+    if let Some(operation) = get("messaging.operation") {
+        return match get("messaging.destination.name").or_else(|| get("messaging.destination")) {
+            Some(destination) => format!("{operation} {destination}"),
+            None => operation.to_string(),
+        };
+    }
+    if let Some(method) = get("rpc.method") {
+        return match get("rpc.service") {
+            Some(service) => format!("{method} {service}"),
+            None => method.to_string(),
+        };
+    }
+    if let Some(operation_type) = get("graphql.operation.type") {
+        return match get("graphql.operation.name") {
+            Some(name) => format!("{operation_type} {name}"),
+            None => operation_type.to_string(),
+        };
+    }
+    if get("db.system").is_some() {
+        if let Some(statement) = get("db.query.text").or_else(|| get("db.statement")) {
+            return statement.to_string();
+        }
+    }
+    span.name.clone()
+}
+
+fn span_type(kind: SpanKind, attributes: &Attributes) -> String {
+    if let Some(span_type) = str_attribute(attributes, "span.type") {
+        return span_type.to_string();
+    }
+    match kind {
+        SpanKind::Server => "web",
+        SpanKind::Client => match str_attribute(attributes, "db.system") {
+            Some("redis") => "redis",
+            Some("memcached") => "memcached",
+            Some("mongodb") => "mongodb",
+            Some("elasticsearch") => "elasticsearch",
+            Some("opensearch") => "opensearch",
+            Some("cassandra") => "cassandra",
+            Some(
+                "postgresql" | "mysql" | "mariadb" | "mssql" | "oracle" | "db2" | "sqlite"
+                | "cockroachdb" | "redshift" | "snowflake" | "other_sql",
+            ) => "sql",
+            Some(_) => "db",
+            None => "http",
+        },
+        _ => "custom",
+    }
+    .to_string()
+}
+
+fn span_kind_name(kind: SpanKind) -> &'static str {
+    match kind {
+        SpanKind::Unspecified => "unspecified",
+        SpanKind::Internal => "internal",
+        SpanKind::Server => "server",
+        SpanKind::Client => "client",
+        SpanKind::Producer => "producer",
+        SpanKind::Consumer => "consumer",
+    }
+}
+
+/// Lower and upper 64 bits of a 128 bits trace id.
+fn trace_id_parts(trace_id: &[u8]) -> (u64, u64) {
+    let Ok(trace_id) = <[u8; 16]>::try_from(trace_id) else {
+        return (0, 0);
+    };
+    let id = u128::from_be_bytes(trace_id);
+    #[allow(clippy::cast_possible_truncation)]
+    (id as u64, (id >> 64) as u64)
+}
+
+fn span_id(span_id: &[u8]) -> u64 {
+    <[u8; 8]>::try_from(span_id).map_or(0, u64::from_be_bytes)
+}
+
+fn attributes(attributes: &[KeyValue]) -> Attributes {
+    attributes
+        .iter()
+        .filter_map(|kv| Some((kv.key.as_str(), kv.value.as_ref()?.value.as_ref()?)))
+        .collect()
+}
+
+fn str_attribute<'a>(attributes: &Attributes<'a>, key: &str) -> Option<&'a str> {
+    as_str(attributes.get(key)?)
+}
+
+fn as_str(value: &any_value::Value) -> Option<&str> {
+    match value {
+        any_value::Value::StringValue(s) if !s.is_empty() => Some(s),
+        _ => None,
+    }
+}
+
+fn to_string(value: &any_value::Value) -> String {
+    match value {
+        any_value::Value::StringValue(s) => s.clone(),
+        _ => to_json(value).to_string(),
+    }
+}
+
+fn to_json(value: &any_value::Value) -> Value {
+    match value {
+        any_value::Value::StringValue(s) => Value::from(s.as_str()),
+        any_value::Value::BoolValue(b) => Value::from(*b),
+        any_value::Value::IntValue(i) => Value::from(*i),
+        any_value::Value::DoubleValue(d) => Value::from(*d),
+        any_value::Value::BytesValue(bytes) => Value::from(BASE64_STANDARD.encode(bytes)),
+        any_value::Value::ArrayValue(array) => array
+            .values
+            .iter()
+            .map(|value| value.value.as_ref().map_or(Value::Null, to_json))
+            .collect(),
+        any_value::Value::KvlistValue(list) => Value::Object(
+            list.values
+                .iter()
+                .map(|kv| {
+                    let value = kv
+                        .value
+                        .as_ref()
+                        .and_then(|value| value.value.as_ref())
+                        .map_or(Value::Null, to_json);
+                    (kv.key.clone(), value)
+                })
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn export_request() -> Value {
+        json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        {"key": "service.name", "value": {"stringValue": "checkout"}},
+                        {"key": "deployment.environment", "value": {"stringValue": "prod"}},
+                        {"key": "telemetry.sdk.language", "value": {"stringValue": "python"}}
+                    ]
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "opentelemetry.instrumentation.flask", "version": "0.48b0"},
+                    "spans": [
+                        {
+                            "traceId": "5b8efff798038103d269b633813fc60c",
+                            "spanId": "eee19b7ec3c1b174",
+                            "name": "GET /orders/<id>",
+                            "kind": 2,
+                            "startTimeUnixNano": "1544712660000000000",
+                            "endTimeUnixNano": "1544712661000000000",
+                            "attributes": [
+                                {"key": "http.request.method", "value": {"stringValue": "GET"}},
+                                {"key": "http.route", "value": {"stringValue": "/orders/<id>"}},
+                                {"key": "http.response.status_code", "value": {"intValue": "500"}}
+                            ],
+                            "events": [{
+                                "name": "exception",
+                                "attributes": [
+                                    {"key": "exception.type", "value": {"stringValue": "KeyError"}},
+                                    {"key": "exception.message", "value": {"stringValue": "'id'"}}
+                                ]
+                            }],
+                            "status": {"code": 2, "message": "KeyError: 'id'"}
+                        },
+                        {
+                            "traceId": "5b8efff798038103d269b633813fc60c",
+                            "spanId": "0000000000000002",
+                            "parentSpanId": "eee19b7ec3c1b174",
+                            "name": "SELECT",
+                            "kind": 3,
+                            "startTimeUnixNano": "1544712660100000000",
+                            "endTimeUnixNano": "1544712660200000000",
+                            "attributes": [
+                                {"key": "db.system", "value": {"stringValue": "postgresql"}},
+                                {"key": "db.statement", "value": {"stringValue": "SELECT * FROM orders"}}
+                            ]
+                        }
+                    ]
+                }]
+            }, {
+                "scopeSpans": [{
+                    "spans": [{
+                        "traceId": "00000000000000000000000000000001",
+                        "spanId": "0000000000000003",
+                        "name": "work",
+                        "kind": 1,
+                        "startTimeUnixNano": "1544712660000000000",
+                        "endTimeUnixNano": "1544712660000000000"
+                    }]
+                }]
+            }]
+        })
+    }
+
+    #[test]
+    fn test_to_dd_traces() {
+        let body = export_request().to_string();
+        let request = decode_request(body.as_bytes(), Encoding::Json, false).unwrap();
+        assert_eq!(sdk_language(&request), Some("python"));
+
+        let traces = to_dd_traces(&request);
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].len(), 2);
+
+        let server = &traces[0][0];
+        assert_eq!(server.service, "checkout");
+        assert_eq!(server.name, "opentelemetry.instrumentation.flask.server");
+        assert_eq!(server.resource, "GET /orders/<id>");
+        assert_eq!(server.r#type, "web");
+        assert_eq!(server.trace_id, 0xd269_b633_813f_c60c);
+        assert_eq!(server.span_id, 0xeee1_9b7e_c3c1_b174);
+        assert_eq!(server.parent_id, 0);
+        assert_eq!(server.start, 1_544_712_660_000_000_000);
+        assert_eq!(server.duration, 1_000_000_000);
+        assert_eq!(server.error, 1);
+        assert_eq!(server.meta["_dd.p.tid"], "5b8efff798038103");
+        assert_eq!(
+            server.meta["otel.trace_id"],
+            "5b8efff798038103d269b633813fc60c"
+        );
+        assert_eq!(server.meta["env"], "prod");
+        assert_eq!(server.meta["span.kind"], "server");
+        assert_eq!(server.meta["http.status_code"], "500");
+        assert_eq!(server.meta["error.msg"], "'id'");
+        assert_eq!(server.meta["error.type"], "KeyError");
+        assert_eq!(server.meta["otel.status_code"], "Error");
+        assert_eq!(server.meta["otel.library.version"], "0.48b0");
+        assert!(server.metrics.contains_key("_top_level"));
+
+        let client = &traces[0][1];
+        assert_eq!(client.parent_id, server.span_id);
+        assert_eq!(client.resource, "SELECT * FROM orders");
+        assert_eq!(client.r#type, "sql");
+        assert_eq!(client.error, 0);
+        assert!(client.metrics.contains_key("_dd.measured"));
+        assert!(!client.metrics.contains_key("_top_level"));
+
+        let internal = &traces[1][0];
+        assert_eq!(internal.service, DEFAULT_SERVICE_NAME);
+        assert_eq!(internal.name, "opentelemetry.internal");
+        assert_eq!(internal.resource, "work");
+        assert_eq!(internal.r#type, "custom");
+        assert_eq!(internal.trace_id, 1);
+        assert!(!internal.meta.contains_key("_dd.p.tid"));
+    }
+
+    #[test]
+    fn test_decode_gzipped_request() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(export_request().to_string().as_bytes())
+            .unwrap();
+        let body = encoder.finish().unwrap();
+
+        let request = decode_request(&body, Encoding::Json, true).unwrap();
+        assert_eq!(request.resource_spans.len(), 2);
+        assert!(decode_request(b"not gzip", Encoding::Json, true).is_err());
+    }
+
+    #[test]
+    fn test_encoding() {
+        assert_eq!(
+            Encoding::from_content_type(Some("application/json; charset=utf-8")),
+            Encoding::Json
+        );
+        assert_eq!(
+            Encoding::from_content_type(Some("application/x-protobuf")),
+            Encoding::Protobuf
+        );
+        assert_eq!(Encoding::from_content_type(None), Encoding::Protobuf);
+        assert_eq!(encode_response(Encoding::Json), b"{}");
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+const REDACTED_VALUE: &str = "redacted";
+
+/// Flattens a request or response payload into span tags, like the Datadog
+/// Lambda libraries do with `DD_CAPTURE_LAMBDA_PAYLOAD`, i.e.
+/// `function.request.body.id`.
+///
+/// Values deeper than `max_depth` are kept as JSON, and values of the
+/// `redacted_keys` are replaced. Strings holding JSON are expanded.
+#[must_use]
+pub fn payload_tags(
+    prefix: &str,
+    payload: &Value,
+    max_depth: usize,
+    redacted_keys: &[String],
+) -> HashMap<String, String> {
+    let mut tags = HashMap::new();
+    add_tags(&mut tags, prefix, payload, 0, max_depth, redacted_keys);
+    tags
+}
+
+fn add_tags(
+    tags: &mut HashMap<String, String>,
+    key: &str,
+    value: &Value,
+    depth: usize,
+    max_depth: usize,
+    redacted_keys: &[String],
+) {
+    match value {
+        Value::Object(map) if depth < max_depth => {
+            for (name, value) in map {
+                let key = format!("{key}.{name}");
+                if redacted_keys
+                    .iter()
+                    .any(|redacted| redacted.eq_ignore_ascii_case(name))
+                {
+                    tags.insert(key, REDACTED_VALUE.to_string());
+                } else {
+                    add_tags(tags, &key, value, depth + 1, max_depth, redacted_keys);
+                }
+            }
+        }
+        Value::Array(values) if depth < max_depth => {
+            for (index, value) in values.iter().enumerate() {
+                let key = format!("{key}.{index}");
+                add_tags(tags, &key, value, depth + 1, max_depth, redacted_keys);
+            }
+        }
+        Value::String(s) => match serde_json::from_str::<Value>(s) {
+            Ok(parsed) if depth < max_depth && (parsed.is_object() || parsed.is_array()) => {
+                add_tags(tags, key, &parsed, depth, max_depth, redacted_keys);
+            }
+            _ => {
+                tags.insert(key.to_string(), s.clone());
+            }
+        },
+        _ => {
+            tags.insert(key.to_string(), value.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload_tags() {
+        let payload = json!({
+            "headers": {"Authorization": "Bearer abc", "accept": "*/*"},
+            "body": "{\"id\": 42, \"items\": [\"a\", \"b\"]}",
+            "isBase64Encoded": false,
+            "nothing": null
+        });
+
+        let tags = payload_tags(
+            "function.request",
+            &payload,
+            10,
+            &["authorization".to_string()],
+        );
+        assert_eq!(
+            tags,
+            HashMap::from([
+                (
+                    "function.request.headers.Authorization".to_string(),
+                    "redacted".to_string()
+                ),
+                (
+                    "function.request.headers.accept".to_string(),
+                    "*/*".to_string()
+                ),
+                ("function.request.body.id".to_string(), "42".to_string()),
+                ("function.request.body.items.0".to_string(), "a".to_string()),
+                ("function.request.body.items.1".to_string(), "b".to_string()),
+                (
+                    "function.request.isBase64Encoded".to_string(),
+                    "false".to_string()
+                ),
+                ("function.request.nothing".to_string(), "null".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_payload_tags_max_depth() {
+        let payload = json!({"a": {"b": {"c": 1}}});
+
+        let tags = payload_tags("function.response", &payload, 1, &[]);
+        assert_eq!(
+            tags,
+            HashMap::from([(
+                "function.response.a".to_string(),
+                r#"{"b":{"c":1}}"#.to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn test_payload_tags_scalar() {
+        let tags = payload_tags("function.response", &json!("ok"), 10, &[]);
+        assert_eq!(
+            tags,
+            HashMap::from([("function.response".to_string(), "ok".to_string())])
+        );
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use datadog_trace_protobuf::pb;
+
+const BUCKET_DURATION_SECS: u64 = 5;
+const NUM_BUCKETS: usize = 6;
+const SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
+const SAMPLE_RATE_KEY: &str = "_sample_rate";
+/// Key of the rate tracers apply to services they haven't received a rate for.
+const DEFAULT_RATE_KEY: &str = "service:,env:";
+
+/// Traces seen for a service and env, in buckets of `BUCKET_DURATION_SECS`.
+#[derive(Default)]
+struct Counts {
+    buckets: [(u64, f64); NUM_BUCKETS],
+}
+
+impl Counts {
+    fn add(&mut self, bucket_id: u64, weight: f64) {
+        let bucket = &mut self.buckets[Self::index(bucket_id)];
+        if bucket.0 != bucket_id {
+            *bucket = (bucket_id, 0.0);
+        }
+        bucket.1 += weight;
+    }
+
+    /// Traces per second over the busiest bucket of the window, so rates
+    /// adapt to spikes right away.
+    #[allow(clippy::cast_precision_loss)]
+    fn tps(&self, bucket_id: u64) -> f64 {
+        let oldest = bucket_id.saturating_sub(NUM_BUCKETS as u64 - 1);
+        self.buckets
+            .iter()
+            .filter(|(id, _)| *id >= oldest && *id <= bucket_id)
+            .map(|(_, count)| *count)
+            .fold(0.0, f64::max)
+            / BUCKET_DURATION_SECS as f64
+    }
+
+    fn index(bucket_id: u64) -> usize {
+        usize::try_from(bucket_id % NUM_BUCKETS as u64).unwrap_or_default()
+    }
+}
+
+/// Computes the sampling rates sent back to tracers as `rate_by_service`, so
+/// that the traces they keep with an automatic priority amount to about
+/// `target_tps` per second, shared between services and envs.
+pub struct PrioritySampler {
+    target_tps: f64,
+    default_env: String,
+    seen: Mutex<HashMap<String, Counts>>,
+}
+
+impl PrioritySampler {
+    #[must_use]
+    pub fn new(target_tps: f64, default_env: Option<&str>) -> PrioritySampler {
+        PrioritySampler {
+            target_tps,
+            default_env: default_env.unwrap_or_default().to_lowercase(),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a trace in the traffic of its service and env, if its sampling
+    /// priority was set automatically by the tracer.
+    pub fn count_trace(&self, trace: &[pb::Span]) {
+        self.count_trace_at(now_secs(), trace);
+    }
+
+    /// Sampling rates by `service:<service>,env:<env>`, with the default rate.
+    #[must_use]
+    pub fn rates_by_service(&self) -> HashMap<String, f64> {
+        self.rates_by_service_at(now_secs())
+    }
+
+    fn count_trace_at(&self, now: u64, trace: &[pb::Span]) {
+        let Some(root_span) = root_span(trace) else {
+            return;
+        };
+        // User priorities aren't driven by the rates
+        match root_span.metrics.get(SAMPLING_PRIORITY_KEY) {
+            Some(priority) if (0.0..=1.0).contains(priority) => {}
+            _ => return,
+        }
+
+        // Traces dropped by the tracer count as well
+        let weight = match root_span.metrics.get(SAMPLE_RATE_KEY) {
+            Some(rate) if *rate > 0.0 && *rate <= 1.0 => 1.0 / rate,
+            _ => 1.0,
+        };
+        let env = root_span
+            .meta
+            .get("env")
+            .map_or_else(|| self.default_env.clone(), |env| env.to_lowercase());
+        let key = format!("service:{},env:{env}", root_span.service);
+
+        self.seen
+            .lock()
+            .expect("lock poisoned")
+            .entry(key)
+            .or_default()
+            .add(now / BUCKET_DURATION_SECS, weight);
+    }
+
+    fn rates_by_service_at(&self, now: u64) -> HashMap<String, f64> {
+        let bucket_id = now / BUCKET_DURATION_SECS;
+        let mut seen = self.seen.lock().expect("lock poisoned");
+        seen.retain(|_, counts| counts.tps(bucket_id) > 0.0);
+
+        let mut tps: Vec<(&String, f64)> = seen
+            .iter()
+            .map(|(key, counts)| (key, counts.tps(bucket_id)))
+            .collect();
+        let total_tps: f64 = tps.iter().map(|(_, tps)| tps).sum();
+
+        // Quieter services keep all their traces, the rest is shared evenly
+        tps.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let mut remaining_tps = self.target_tps;
+        let mut rates = HashMap::new();
+        let count = tps.len();
+        for (index, (key, tps)) in tps.iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let share = remaining_tps / (count - index) as f64;
+            let kept_tps = tps.min(share);
+            remaining_tps -= kept_tps;
+            rates.insert((*key).clone(), kept_tps / tps);
+        }
+        let default_rate = if total_tps > self.target_tps {
+            self.target_tps / total_tps
+        } else {
+            1.0
+        };
+        rates.insert(DEFAULT_RATE_KEY.to_string(), default_rate);
+        rates
+    }
+}
+
+fn root_span(trace: &[pb::Span]) -> Option<&pb::Span> {
+    trace
+        .iter()
+        .find(|span| span.parent_id == 0)
+        .or_else(|| trace.first())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(service: &str, env: Option<&str>, priority: f64) -> Vec<pb::Span> {
+        let mut span = pb::Span {
+            service: service.to_string(),
+            span_id: 1,
+            ..pb::Span::default()
+        };
+        span.metrics
+            .insert(SAMPLING_PRIORITY_KEY.to_string(), priority);
+        if let Some(env) = env {
+            span.meta.insert("env".to_string(), env.to_string());
+        }
+        vec![span]
+    }
+
+    #[test]
+    fn test_rates_by_service() {
+        let sampler = PrioritySampler::new(10.0, Some("Prod"));
+        let now = 1_000_000;
+        // 40 traces per second for the busy service, 2 for the quiet one
+        for _ in 0..200 {
+            sampler.count_trace_at(now, &trace("busy", None, 1.0));
+        }
+        for _ in 0..10 {
+            sampler.count_trace_at(now, &trace("quiet", Some("staging"), 0.0));
+        }
+        // Kept by the user, not counted
+        sampler.count_trace_at(now, &trace("manual", None, 2.0));
+
+        let rates = sampler.rates_by_service_at(now);
+        assert_eq!(rates.len(), 3);
+        assert!((rates["service:quiet,env:staging"] - 1.0).abs() < f64::EPSILON);
+        assert!((rates["service:busy,env:prod"] - 0.2).abs() < 1e-9);
+        assert!((rates[DEFAULT_RATE_KEY] - 10.0 / 42.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rates_by_service_weighted_and_expired() {
+        let sampler = PrioritySampler::new(10.0, None);
+        let now = 1_000_000;
+        let mut sampled_trace = trace("svc", None, 1.0);
+        sampled_trace[0]
+            .metrics
+            .insert(SAMPLE_RATE_KEY.to_string(), 0.5);
+        // 50 traces sent, 100 seen by the tracer, i.e. 20 traces per second
+        for _ in 0..50 {
+            sampler.count_trace_at(now, &sampled_trace);
+        }
+        let rates = sampler.rates_by_service_at(now);
+        assert!((rates["service:svc,env:"] - 0.5).abs() < 1e-9);
+
+        // Out of the window
+        let rates = sampler.rates_by_service_at(now + 60);
+        assert_eq!(rates.len(), 1);
+        assert!((rates[DEFAULT_RATE_KEY] - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,530 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use base64::prelude::*;
+use serde_json::Value;
+
+const DATADOG_TRACE_ID_KEY: &str = "x-datadog-trace-id";
+const DATADOG_PARENT_ID_KEY: &str = "x-datadog-parent-id";
+const DATADOG_SAMPLING_PRIORITY_KEY: &str = "x-datadog-sampling-priority";
+const DATADOG_ORIGIN_KEY: &str = "x-datadog-origin";
+const DATADOG_TAGS_KEY: &str = "x-datadog-tags";
+const TRACEPARENT_KEY: &str = "traceparent";
+const TRACESTATE_KEY: &str = "tracestate";
+const B3_TRACE_ID_KEY: &str = "x-b3-traceid";
+const B3_SPAN_ID_KEY: &str = "x-b3-spanid";
+const B3_SAMPLED_KEY: &str = "x-b3-sampled";
+const B3_FLAGS_KEY: &str = "x-b3-flags";
+const B3_SINGLE_KEY: &str = "b3";
+const XRAY_KEY: &str = "x-amzn-trace-id";
+
+/// Message attribute, or `detail` field for `EventBridge`, where the Datadog
+/// Lambda libraries and tracers put the trace context.
+const DATADOG_ATTRIBUTE: &str = "_datadog";
+/// Tag with the upper 64 bits of 128 bits trace ids, in hexadecimal.
+const TRACE_ID_HIGH_TAG: &str = "_dd.p.tid";
+
+const SAMPLING_PRIORITY_AUTO_REJECT: f64 = 0.0;
+const SAMPLING_PRIORITY_AUTO_KEEP: f64 = 1.0;
+const SAMPLING_PRIORITY_USER_KEEP: f64 = 2.0;
+
+/// Trace context of the caller of an invocation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpanContext {
+    pub trace_id: u64,
+    pub span_id: u64,
+    pub sampling_priority: Option<f64>,
+    pub origin: Option<String>,
+    /// Propagated tags, to set on the root span
+    pub tags: HashMap<String, String>,
+}
+
+/// Trace context from the event which triggered an invocation: from its HTTP
+/// headers, its SQS or SNS message attributes, or its `EventBridge` detail.
+///
+/// Datadog headers are looked up first, then W3C Trace Context, B3 multi and
+/// single header, and AWS X-Ray.
+#[must_use]
+pub fn extract_from_event(payload: &Value) -> Option<SpanContext> {
+    let carrier = carrier_from_event(payload)?;
+    extract_datadog(&carrier)
+        .or_else(|| extract_tracecontext(&carrier))
+        .or_else(|| extract_b3_multi(&carrier))
+        .or_else(|| extract_b3_single(&carrier))
+        .or_else(|| extract_xray(&carrier))
+}
+
+/// Headers carrying the trace context in the event, keyed by their lowercase name.
+fn carrier_from_event(payload: &Value) -> Option<HashMap<String, String>> {
+    if let Some(headers) = payload.get("headers") {
+        return to_carrier(headers);
+    }
+    if let Some(detail) = payload.get("detail") {
+        return to_carrier(detail.get(DATADOG_ATTRIBUTE)?);
+    }
+
+    let record = payload.get("Records")?.get(0)?;
+    if let Some(sns) = record.get("Sns") {
+        return sns_carrier(sns);
+    }
+    if record.get("eventSource").and_then(Value::as_str) != Some("aws:sqs") {
+        return None;
+    }
+
+    if let Some(attribute) = record.pointer("/messageAttributes/_datadog") {
+        let value = match attribute.get("stringValue").and_then(Value::as_str) {
+            Some(value) => value.to_string(),
+            None => decode_base64(attribute.get("binaryValue")?.as_str()?)?,
+        };
+        return to_carrier(&serde_json::from_str(&value).ok()?);
+    }
+    // SNS notifications delivered to SQS keep their attributes in the body
+    if let Some(carrier) = record
+        .get("body")
+        .and_then(Value::as_str)
+        .and_then(|body| serde_json::from_str::<Value>(body).ok())
+        .and_then(|body| sns_carrier(&body))
+    {
+        return Some(carrier);
+    }
+    let xray_header = record.pointer("/attributes/AWSTraceHeader")?.as_str()?;
+    Some(HashMap::from([(
+        XRAY_KEY.to_string(),
+        xray_header.to_string(),
+    )]))
+}
+
+fn sns_carrier(sns: &Value) -> Option<HashMap<String, String>> {
+    let attribute = sns.get("MessageAttributes")?.get(DATADOG_ATTRIBUTE)?;
+    let value = attribute.get("Value")?.as_str()?;
+    let value = match attribute.get("Type").and_then(Value::as_str) {
+        Some("Binary") => decode_base64(value)?,
+        _ => value.to_string(),
+    };
+    to_carrier(&serde_json::from_str(&value).ok()?)
+}
+
+fn to_carrier(headers: &Value) -> Option<HashMap<String, String>> {
+    Some(
+        headers
+            .as_object()?
+            .iter()
+            .filter_map(|(name, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Number(n) => n.to_string(),
+                    _ => return None,
+                };
+                Some((name.to_lowercase(), value))
+            })
+            .collect(),
+    )
+}
+
+fn decode_base64(value: &str) -> Option<String> {
+    String::from_utf8(BASE64_STANDARD.decode(value).ok()?).ok()
+}
+
+fn extract_datadog(carrier: &HashMap<String, String>) -> Option<SpanContext> {
+    let trace_id = carrier.get(DATADOG_TRACE_ID_KEY)?.parse().ok()?;
+    let span_id = carrier.get(DATADOG_PARENT_ID_KEY)?.parse().ok()?;
+    if trace_id == 0 {
+        return None;
+    }
+
+    let tags = carrier
+        .get(DATADOG_TAGS_KEY)
+        .map(|tags| {
+            tags.split(',')
+                .filter_map(|tag| tag.split_once('='))
+                .filter(|(key, _)| key.starts_with("_dd.p."))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampling_priority: carrier
+            .get(DATADOG_SAMPLING_PRIORITY_KEY)
+            .and_then(|priority| priority.parse().ok()),
+        origin: carrier.get(DATADOG_ORIGIN_KEY).cloned(),
+        tags,
+    })
+}
+
+/// W3C Trace Context, i.e. `traceparent: 00-<trace id>-<parent id>-<flags>`,
+/// and the Datadog member of `tracestate`, i.e. `dd=s:2;o:rum`.
+fn extract_tracecontext(carrier: &HashMap<String, String>) -> Option<SpanContext> {
+    let traceparent = carrier.get(TRACEPARENT_KEY)?;
+    let mut parts = traceparent.trim().split('-');
+    let (Some(version), Some(trace_id), Some(span_id), Some(flags)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if version == "ff" || trace_id.len() != 32 || span_id.len() != 16 {
+        return None;
+    }
+    let (trace_id, mut tags) = parse_trace_id(trace_id)?;
+    let span_id = u64::from_str_radix(span_id, 16).ok()?;
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+
+    let mut sampling_priority = if sampled {
+        SAMPLING_PRIORITY_AUTO_KEEP
+    } else {
+        SAMPLING_PRIORITY_AUTO_REJECT
+    };
+    let mut origin = None;
+    let datadog_state = carrier.get(TRACESTATE_KEY).and_then(|tracestate| {
+        tracestate
+            .split(',')
+            .find_map(|member| member.trim().strip_prefix("dd="))
+    });
+    for (key, value) in datadog_state
+        .into_iter()
+        .flat_map(|state| state.split(';'))
+        .filter_map(|field| field.split_once(':'))
+    {
+        match key {
+            // Keep the priority when it agrees with the sampled flag
+            "s" => {
+                if let Ok(priority) = value.parse::<f64>() {
+                    if (priority > 0.0) == sampled {
+                        sampling_priority = priority;
+                    }
+                }
+            }
+            "o" => origin = Some(value.replace('~', "=")),
+            _ => {
+                if let Some(key) = key.strip_prefix("t.") {
+                    tags.insert(format!("_dd.p.{key}"), value.replace('~', "="));
+                }
+            }
+        }
+    }
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampling_priority: Some(sampling_priority),
+        origin,
+        tags,
+    })
+}
+
+fn extract_b3_multi(carrier: &HashMap<String, String>) -> Option<SpanContext> {
+    let (trace_id, tags) = parse_trace_id(carrier.get(B3_TRACE_ID_KEY)?)?;
+    let span_id = u64::from_str_radix(carrier.get(B3_SPAN_ID_KEY)?, 16).ok()?;
+    let sampling_priority = if carrier.get(B3_FLAGS_KEY).map(String::as_str) == Some("1") {
+        Some(SAMPLING_PRIORITY_USER_KEEP)
+    } else {
+        carrier
+            .get(B3_SAMPLED_KEY)
+            .and_then(|sampled| b3_sampling_priority(sampled))
+    };
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampling_priority,
+        origin: None,
+        tags,
+    })
+}
+
+/// B3 single header, i.e. `b3: <trace id>-<span id>-<sampling>-<parent span id>`.
+fn extract_b3_single(carrier: &HashMap<String, String>) -> Option<SpanContext> {
+    let mut parts = carrier.get(B3_SINGLE_KEY)?.split('-');
+    let (trace_id, tags) = parse_trace_id(parts.next()?)?;
+    let span_id = u64::from_str_radix(parts.next()?, 16).ok()?;
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampling_priority: parts.next().and_then(b3_sampling_priority),
+        origin: None,
+        tags,
+    })
+}
+
+fn b3_sampling_priority(sampled: &str) -> Option<f64> {
+    match sampled {
+        "1" | "true" => Some(SAMPLING_PRIORITY_AUTO_KEEP),
+        "0" | "false" => Some(SAMPLING_PRIORITY_AUTO_REJECT),
+        "d" => Some(SAMPLING_PRIORITY_USER_KEEP),
+        _ => None,
+    }
+}
+
+/// AWS X-Ray header, as in `_X_AMZN_TRACE_ID`, i.e.
+/// `Root=1-<epoch>-<24 hex digits>;Parent=<16 hex digits>;Sampled=1`.
+fn extract_xray(carrier: &HashMap<String, String>) -> Option<SpanContext> {
+    let mut root = None;
+    let mut parent = None;
+    let mut sampled = None;
+    for (key, value) in carrier
+        .get(XRAY_KEY)?
+        .split(';')
+        .filter_map(|field| field.trim().split_once('='))
+    {
+        match key {
+            "Root" => root = Some(value),
+            "Parent" => parent = Some(value),
+            "Sampled" => sampled = Some(value),
+            _ => {}
+        }
+    }
+
+    // The lower 64 bits of the trace id are the end of the root's unique part
+    let unique_id = root?.rsplit('-').next()?;
+    if unique_id.len() != 24 {
+        return None;
+    }
+    let trace_id = u64::from_str_radix(&unique_id[8..], 16).ok()?;
+    let span_id = u64::from_str_radix(parent?, 16).ok()?;
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampling_priority: sampled.map(|sampled| {
+            if sampled == "1" {
+                SAMPLING_PRIORITY_AUTO_KEEP
+            } else {
+                SAMPLING_PRIORITY_AUTO_REJECT
+            }
+        }),
+        origin: None,
+        tags: HashMap::new(),
+    })
+}
+
+/// Lower 64 bits of a 64 or 128 bits hexadecimal trace id, with the upper bits
+/// as a tag when set.
+fn parse_trace_id(trace_id: &str) -> Option<(u64, HashMap<String, String>)> {
+    if trace_id.is_empty() || trace_id.len() > 32 {
+        return None;
+    }
+    let split = trace_id.len().saturating_sub(16);
+    let (high, low) = trace_id.split_at(split);
+    let low = u64::from_str_radix(low, 16).ok()?;
+    if low == 0 {
+        return None;
+    }
+
+    let mut tags = HashMap::new();
+    if !high.is_empty() && u64::from_str_radix(high, 16).ok()? != 0 {
+        tags.insert(TRACE_ID_HIGH_TAG.to_string(), high.to_lowercase());
+    }
+    Some((low, tags))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_datadog() {
+        let payload = json!({
+            "headers": {
+                "X-Datadog-Trace-Id": "1234",
+                "X-Datadog-Parent-Id": "5678",
+                "X-Datadog-Sampling-Priority": "2",
+                "X-Datadog-Origin": "synthetics",
+                "X-Datadog-Tags": "_dd.p.dm=-4,_dd.p.tid=640cfd8d00000000,other=value"
+            }
+        });
+
+        assert_eq!(
+            extract_from_event(&payload).unwrap(),
+            SpanContext {
+                trace_id: 1234,
+                span_id: 5678,
+                sampling_priority: Some(2.0),
+                origin: Some("synthetics".to_string()),
+                tags: HashMap::from([
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                    ("_dd.p.tid".to_string(), "640cfd8d00000000".to_string()),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_extract_tracecontext() {
+        let payload = json!({
+            "headers": {
+                "traceparent": "00-80f198ee56343ba864fe8b2a57d3eff7-00f067aa0ba902b7-01",
+                "tracestate": "foo=bar,dd=s:2;o:rum;t.dm:-4"
+            }
+        });
+
+        assert_eq!(
+            extract_from_event(&payload).unwrap(),
+            SpanContext {
+                trace_id: 0x64fe_8b2a_57d3_eff7,
+                span_id: 0x00f0_67aa_0ba9_02b7,
+                sampling_priority: Some(2.0),
+                origin: Some("rum".to_string()),
+                tags: HashMap::from([
+                    ("_dd.p.tid".to_string(), "80f198ee56343ba8".to_string()),
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_extract_tracecontext_not_sampled() {
+        let payload = json!({
+            "headers": {
+                "traceparent": "00-00000000000000000000000000000001-0000000000000002-00",
+                "tracestate": "dd=s:2"
+            }
+        });
+
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!(context.trace_id, 1);
+        assert_eq!(context.sampling_priority, Some(0.0));
+        assert!(context.tags.is_empty());
+    }
+
+    #[test]
+    fn test_extract_b3() {
+        let payload = json!({
+            "headers": {
+                "X-B3-TraceId": "463ac35c9f6413ad",
+                "X-B3-SpanId": "a2fb4a1d1a96d312",
+                "X-B3-Sampled": "1"
+            }
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!(context.trace_id, 0x463a_c35c_9f64_13ad);
+        assert_eq!(context.span_id, 0xa2fb_4a1d_1a96_d312);
+        assert_eq!(context.sampling_priority, Some(1.0));
+
+        let payload = json!({
+            "headers": {"b3": "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-d-05e3ac9a4f6e3b90"}
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!(context.trace_id, 0x64fe_8b2a_57d3_eff7);
+        assert_eq!(context.span_id, 0xe457_b5a2_e4d8_6bd1);
+        assert_eq!(context.sampling_priority, Some(2.0));
+    }
+
+    #[test]
+    fn test_extract_xray() {
+        let payload = json!({
+            "headers": {
+                "X-Amzn-Trace-Id": "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"
+            }
+        });
+
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!(context.trace_id, 0xe1be_46a9_9427_2793);
+        assert_eq!(context.span_id, 0x5399_5c3f_42cd_8ad8);
+        assert_eq!(context.sampling_priority, Some(1.0));
+    }
+
+    #[test]
+    fn test_extract_from_sqs() {
+        let payload = json!({
+            "Records": [{
+                "eventSource": "aws:sqs",
+                "messageAttributes": {
+                    "_datadog": {
+                        "stringValue": "{\"x-datadog-trace-id\":\"1234\",\"x-datadog-parent-id\":\"5678\",\"x-datadog-sampling-priority\":\"1\"}",
+                        "dataType": "String"
+                    }
+                }
+            }]
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!((context.trace_id, context.span_id), (1234, 5678));
+
+        let payload = json!({
+            "Records": [{
+                "eventSource": "aws:sqs",
+                "attributes": {
+                    "AWSTraceHeader": "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=0"
+                }
+            }]
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!(context.trace_id, 0xe1be_46a9_9427_2793);
+        assert_eq!(context.sampling_priority, Some(0.0));
+    }
+
+    #[test]
+    fn test_extract_from_sns() {
+        let datadog =
+            BASE64_STANDARD.encode(r#"{"x-datadog-trace-id":"1234","x-datadog-parent-id":"5678"}"#);
+        let payload = json!({
+            "Records": [{
+                "EventSource": "aws:sns",
+                "Sns": {
+                    "MessageAttributes": {
+                        "_datadog": {"Type": "Binary", "Value": datadog}
+                    }
+                }
+            }]
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!((context.trace_id, context.span_id), (1234, 5678));
+
+        // SNS notification delivered to SQS
+        let body = json!({
+            "Type": "Notification",
+            "MessageAttributes": {
+                "_datadog": {
+                    "Type": "String",
+                    "Value": "{\"x-datadog-trace-id\":\"4321\",\"x-datadog-parent-id\":\"8765\"}"
+                }
+            }
+        });
+        let payload = json!({
+            "Records": [{"eventSource": "aws:sqs", "body": body.to_string()}]
+        });
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!((context.trace_id, context.span_id), (4321, 8765));
+    }
+
+    #[test]
+    fn test_extract_from_eventbridge() {
+        let payload = json!({
+            "detail-type": "OrderCreated",
+            "source": "orders",
+            "detail": {
+                "order_id": 42,
+                "_datadog": {
+                    "x-datadog-trace-id": "1234",
+                    "x-datadog-parent-id": "5678",
+                    "x-datadog-sampling-priority": "1"
+                }
+            }
+        });
+
+        let context = extract_from_event(&payload).unwrap();
+        assert_eq!((context.trace_id, context.span_id), (1234, 5678));
+        assert_eq!(context.sampling_priority, Some(1.0));
+    }
+
+    #[test]
+    fn test_extract_from_event_without_context() {
+        assert!(extract_from_event(&json!({"headers": {"accept": "*/*"}})).is_none());
+        assert!(extract_from_event(&json!({"Records": []})).is_none());
+        assert!(extract_from_event(&json!({
+            "headers": {"x-datadog-trace-id": "abc", "x-datadog-parent-id": "1"}
+        }))
+        .is_none());
+        assert!(extract_from_event(&json!({
+            "headers": {"traceparent": "00-00000000000000000000000000000000-0000000000000002-01"}
+        }))
+        .is_none());
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use datadog_trace_protobuf::pb;
+
+use crate::config;
+
+/// Priority of the chunks whose root span has no sampling priority.
+pub const PRIORITY_NONE: i32 = -128;
+/// Priority of the traces dropped by the tracer's sampling.
+pub const PRIORITY_AUTO_DROP: i32 = 0;
+const TOP_LEVEL_KEY: &str = "_top_level";
+const MEASURED_KEY: &str = "_dd.measured";
+const RARE_KEY: &str = "_dd.rare";
+const ERROR_SAMPLED_KEY: &str = "_dd.error_sampled";
+const HTTP_STATUS_CODE_KEY: &str = "http.status_code";
+
+/// Token bucket allowing `tps` traces per second, with bursts of up to a
+/// second of traces.
+struct RateLimiter {
+    tps: f64,
+    tokens: f64,
+    last_secs: u64,
+}
+
+impl RateLimiter {
+    fn new(tps: f64) -> RateLimiter {
+        RateLimiter {
+            tps,
+            tokens: 0.0,
+            last_secs: 0,
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn allow(&mut self, now: u64) -> bool {
+        if self.tps <= 0.0 {
+            return false;
+        }
+        let elapsed = now.saturating_sub(self.last_secs) as f64;
+        self.last_secs = self.last_secs.max(now);
+        self.tokens = (self.tokens + elapsed * self.tps).min(self.tps.max(1.0));
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Keeps traces with errors which were dropped by the tracer's sampling, up
+/// to `tps` traces per second.
+pub struct ErrorSampler {
+    limiter: Mutex<RateLimiter>,
+}
+
+impl ErrorSampler {
+    #[must_use]
+    pub fn new(tps: f64) -> ErrorSampler {
+        ErrorSampler {
+            limiter: Mutex::new(RateLimiter::new(tps)),
+        }
+    }
+
+    /// Whether a trace dropped by the tracer should be kept for its errors. Its
+    /// first span with an error is marked with `_dd.error_sampled`.
+    pub fn sample(&self, chunk: &mut pb::TraceChunk) -> bool {
+        self.sample_at(now_secs(), chunk)
+    }
+
+    fn sample_at(&self, now: u64, chunk: &mut pb::TraceChunk) -> bool {
+        let Some(span) = chunk.spans.iter_mut().find(|span| span.error != 0) else {
+            return false;
+        };
+        if !self.limiter.lock().expect("lock poisoned").allow(now) {
+            return false;
+        }
+        span.metrics.insert(ERROR_SAMPLED_KEY.to_string(), 1.0);
+        true
+    }
+}
+
+/// Top level and measured spans are considered rare by these fields.
+#[derive(Debug, Hash, PartialEq, Eq)]
+struct Signature {
+    env: String,
+    service: String,
+    name: String,
+    resource: String,
+    http_status_code: String,
+    r#type: String,
+    error: i32,
+}
+
+/// Keeps traces dropped by the tracer's sampling which have a top level or
+/// measured span unlike the spans of the traces kept within the cooldown.
+pub struct RareSampler {
+    enabled: bool,
+    cooldown_secs: u64,
+    cardinality: usize,
+    limiter: Mutex<RateLimiter>,
+    /// Signatures of the spans seen in kept traces, with their expiry time.
+    seen: Mutex<HashMap<Signature, u64>>,
+}
+
+impl RareSampler {
+    #[must_use]
+    pub fn new(config: &config::Config) -> RareSampler {
+        RareSampler {
+            enabled: config.apm_enable_rare_sampler,
+            cooldown_secs: config.apm_rare_sampler_cooldown,
+            cardinality: config.apm_rare_sampler_cardinality,
+            limiter: Mutex::new(RateLimiter::new(config.apm_rare_sampler_tps)),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records the spans of kept traces, and tells whether a trace dropped by
+    /// the tracer should be kept as rare. Its rare span is marked with
+    /// `_dd.rare`.
+    ///
+    /// Every trace goes through the rare sampler, so that the spans of the
+    /// traces the tracer keeps aren't rare.
+    pub fn sample(&self, chunk: &mut pb::TraceChunk) -> bool {
+        self.sample_at(now_secs(), chunk)
+    }
+
+    fn sample_at(&self, now: u64, chunk: &mut pb::TraceChunk) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let mut seen = self.seen.lock().expect("lock poisoned");
+        seen.retain(|_, expires| *expires > now);
+
+        let signatures: Vec<(usize, Signature)> = chunk
+            .spans
+            .iter()
+            .enumerate()
+            .filter(|(_, span)| is_set(span, TOP_LEVEL_KEY) || is_set(span, MEASURED_KEY))
+            .map(|(index, span)| (index, signature(span)))
+            .collect();
+
+        let kept = chunk.priority > PRIORITY_AUTO_DROP || chunk.priority == PRIORITY_NONE;
+        let rare = if kept {
+            None
+        } else {
+            signatures
+                .iter()
+                .find(|(_, signature)| !seen.contains_key(signature))
+                .map(|(index, _)| *index)
+                .filter(|_| self.limiter.lock().expect("lock poisoned").allow(now))
+        };
+        if let Some(index) = rare {
+            chunk.spans[index].metrics.insert(RARE_KEY.to_string(), 1.0);
+        }
+
+        if kept || rare.is_some() {
+            for (_, signature) in signatures {
+                if seen.len() < self.cardinality || seen.contains_key(&signature) {
+                    seen.insert(signature, now + self.cooldown_secs);
+                }
+            }
+        }
+        rare.is_some()
+    }
+}
+
+fn signature(span: &pb::Span) -> Signature {
+    let meta = |key: &str| span.meta.get(key).cloned().unwrap_or_default();
+    Signature {
+        env: meta("env"),
+        service: span.service.clone(),
+        name: span.name.clone(),
+        resource: span.resource.clone(),
+        http_status_code: meta(HTTP_STATUS_CODE_KEY),
+        r#type: span.r#type.clone(),
+        error: span.error,
+    }
+}
+
+fn is_set(span: &pb::Span, key: &str) -> bool {
+    span.metrics.get(key).is_some_and(|value| *value > 0.0)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(priority: i32, resource: &str) -> pb::TraceChunk {
+        let mut span = pb::Span {
+            service: "svc".to_string(),
+            name: "aws.lambda".to_string(),
+            resource: resource.to_string(),
+            ..pb::Span::default()
+        };
+        span.metrics.insert(TOP_LEVEL_KEY.to_string(), 1.0);
+        pb::TraceChunk {
+            priority,
+            spans: vec![span],
+            ..pb::TraceChunk::default()
+        }
+    }
+
+    #[test]
+    fn test_error_sampler() {
+        let sampler = ErrorSampler::new(2.0);
+        let now = 1_000_000;
+        let mut ok_chunk = chunk(PRIORITY_AUTO_DROP, "GET /");
+        let mut error_chunk = ok_chunk.clone();
+        error_chunk.spans[0].error = 1;
+
+        assert!(!sampler.sample_at(now, &mut ok_chunk));
+        assert!(!ok_chunk.spans[0].metrics.contains_key(ERROR_SAMPLED_KEY));
+        assert!(sampler.sample_at(now, &mut error_chunk.clone()));
+        assert!(sampler.sample_at(now, &mut error_chunk));
+        assert!(error_chunk.spans[0].metrics.contains_key(ERROR_SAMPLED_KEY));
+        // Over budget until the next second
+        assert!(!sampler.sample_at(now, &mut error_chunk.clone()));
+        assert!(sampler.sample_at(now + 1, &mut error_chunk.clone()));
+
+        let mut unsampled_chunk = chunk(PRIORITY_AUTO_DROP, "GET /");
+        unsampled_chunk.spans[0].error = 1;
+        assert!(!ErrorSampler::new(0.0).sample_at(now, &mut unsampled_chunk));
+        assert!(!unsampled_chunk.spans[0]
+            .metrics
+            .contains_key(ERROR_SAMPLED_KEY));
+    }
+
+    #[test]
+    fn test_rare_sampler() {
+        let sampler = RareSampler::new(&config::Config {
+            apm_enable_rare_sampler: true,
+            ..config::Config::default()
+        });
+        let now = 1_000_000;
+
+        // Seen in a trace kept by the tracer
+        assert!(!sampler.sample_at(now, &mut chunk(1, "GET /")));
+        assert!(!sampler.sample_at(now, &mut chunk(PRIORITY_AUTO_DROP, "GET /")));
+
+        let mut rare_chunk = chunk(PRIORITY_AUTO_DROP, "POST /");
+        assert!(sampler.sample_at(now, &mut rare_chunk));
+        assert!(rare_chunk.spans[0].metrics.contains_key(RARE_KEY));
+        assert!(!sampler.sample_at(now, &mut chunk(PRIORITY_AUTO_DROP, "POST /")));
+
+        // Rare again after the cooldown
+        let later = now + config::Config::default().apm_rare_sampler_cooldown;
+        assert!(sampler.sample_at(later, &mut chunk(PRIORITY_AUTO_DROP, "GET /")));
+    }
+
+    #[test]
+    fn test_rare_sampler_disabled() {
+        let sampler = RareSampler::new(&config::Config::default());
+        assert!(!sampler.sample_at(1_000_000, &mut chunk(PRIORITY_AUTO_DROP, "GET /")));
+    }
+}
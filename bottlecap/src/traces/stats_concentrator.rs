@@ -0,0 +1,322 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use datadog_ddsketch::DDSketch;
+use datadog_trace_protobuf::pb;
+use tracing::debug;
+
+/// Duration of the buckets stats are aggregated in, as in the Datadog Agent.
+const BUCKET_DURATION_NS: u64 = 10_000_000_000;
+const TOP_LEVEL_KEY: &str = "_top_level";
+const MEASURED_KEY: &str = "_dd.measured";
+const PARTIAL_VERSION_KEY: &str = "_dd.partial_version";
+const HTTP_STATUS_CODE_KEY: &str = "http.status_code";
+const SPAN_KIND_KEY: &str = "span.kind";
+
+/// Spans are aggregated by these fields.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    r#type: String,
+    http_status_code: u32,
+    synthetics: bool,
+    span_kind: String,
+    peer_tags: Vec<String>,
+}
+
+/// Stats are sent in a payload per env and version.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct PayloadKey {
+    env: String,
+    version: String,
+}
+
+#[derive(Default)]
+struct GroupedStats {
+    hits: u64,
+    top_level_hits: u64,
+    errors: u64,
+    duration: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+type Buckets = BTreeMap<u64, HashMap<AggregationKey, GroupedStats>>;
+
+/// Computes trace stats from the spans of tracers which don't compute them,
+/// like the Datadog Agent's concentrator: hits, errors and duration
+/// distributions of the top level and measured spans, in 10 seconds buckets.
+pub struct StatsConcentrator {
+    peer_tags: Vec<String>,
+    default_env: String,
+    buckets: Mutex<HashMap<PayloadKey, Buckets>>,
+}
+
+impl StatsConcentrator {
+    /// `peer_tags` are the tags client and producer spans are also aggregated
+    /// by, `None` when peer tags aggregation is disabled.
+    #[must_use]
+    pub fn new(peer_tags: Option<Vec<String>>, default_env: Option<&str>) -> StatsConcentrator {
+        StatsConcentrator {
+            peer_tags: peer_tags.unwrap_or_default(),
+            default_env: default_env.unwrap_or_default().to_string(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds the eligible spans of a processed tracer payload to the stats.
+    pub fn add(&self, payload: &pb::TracerPayload) {
+        let mut buckets = self.buckets.lock().expect("lock poisoned");
+        for chunk in &payload.chunks {
+            let synthetics = chunk.origin.starts_with("synthetics");
+            for span in chunk.spans.iter().filter(|span| is_eligible(span)) {
+                let payload_key = PayloadKey {
+                    env: span
+                        .meta
+                        .get("env")
+                        .cloned()
+                        .or_else(|| Some(payload.env.clone()).filter(|env| !env.is_empty()))
+                        .unwrap_or_else(|| self.default_env.clone()),
+                    version: span
+                        .meta
+                        .get("version")
+                        .cloned()
+                        .unwrap_or_else(|| payload.app_version.clone()),
+                };
+                let end = u64::try_from(span.start.saturating_add(span.duration)).unwrap_or(0);
+                let stats = buckets
+                    .entry(payload_key)
+                    .or_default()
+                    .entry(end - end % BUCKET_DURATION_NS)
+                    .or_default()
+                    .entry(self.aggregation_key(span, synthetics))
+                    .or_default();
+                add_span(stats, span);
+            }
+        }
+    }
+
+    /// Takes the stats computed so far, as they have to be sent before the
+    /// execution environment is frozen.
+    #[must_use]
+    pub fn flush(&self) -> Vec<pb::ClientStatsPayload> {
+        let buckets = std::mem::take(&mut *self.buckets.lock().expect("lock poisoned"));
+        buckets
+            .into_iter()
+            .map(|(payload_key, buckets)| pb::ClientStatsPayload {
+                env: payload_key.env,
+                version: payload_key.version,
+                stats: buckets
+                    .into_iter()
+                    .map(|(start, stats)| pb::ClientStatsBucket {
+                        start,
+                        duration: BUCKET_DURATION_NS,
+                        stats: stats.into_iter().map(grouped_stats).collect(),
+                        agent_time_shift: 0,
+                    })
+                    .collect(),
+                ..pb::ClientStatsPayload::default()
+            })
+            .collect()
+    }
+
+    fn aggregation_key(&self, span: &pb::Span, synthetics: bool) -> AggregationKey {
+        let span_kind = span
+            .meta
+            .get(SPAN_KIND_KEY)
+            .map(|kind| kind.to_lowercase())
+            .unwrap_or_default();
+        let peer_tags = if matches!(span_kind.as_str(), "client" | "producer" | "consumer") {
+            self.peer_tags
+                .iter()
+                .filter_map(|tag| Some(format!("{tag}:{}", span.meta.get(tag)?)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        AggregationKey {
+            service: span.service.clone(),
+            name: span.name.clone(),
+            resource: span.resource.clone(),
+            r#type: span.r#type.clone(),
+            http_status_code: http_status_code(span),
+            synthetics,
+            span_kind,
+            peer_tags,
+        }
+    }
+}
+
+/// Top level and measured spans, and spans with a kind other than internal,
+/// except partial snapshots of long running spans.
+fn is_eligible(span: &pb::Span) -> bool {
+    if span.metrics.contains_key(PARTIAL_VERSION_KEY) {
+        return false;
+    }
+    let is_set = |key| span.metrics.get(key).is_some_and(|value| *value > 0.0);
+    is_set(TOP_LEVEL_KEY)
+        || is_set(MEASURED_KEY)
+        || span.meta.get(SPAN_KIND_KEY).is_some_and(|kind| {
+            matches!(kind.as_str(), "server" | "consumer" | "client" | "producer")
+        })
+}
+
+fn http_status_code(span: &pb::Span) -> u32 {
+    if let Some(code) = span.meta.get(HTTP_STATUS_CODE_KEY) {
+        return code.parse().unwrap_or_default();
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    span.metrics
+        .get(HTTP_STATUS_CODE_KEY)
+        .map_or(0, |code| *code as u32)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn add_span(stats: &mut GroupedStats, span: &pb::Span) {
+    let duration = u64::try_from(span.duration).unwrap_or_default();
+    stats.hits += 1;
+    stats.duration += duration;
+    if span
+        .metrics
+        .get(TOP_LEVEL_KEY)
+        .is_some_and(|value| *value > 0.0)
+    {
+        stats.top_level_hits += 1;
+    }
+    let summary = if span.error == 0 {
+        &mut stats.ok_summary
+    } else {
+        stats.errors += 1;
+        &mut stats.error_summary
+    };
+    if let Err(e) = summary.add(duration as f64) {
+        debug!("Failed to add span duration to the stats: {e}");
+    }
+}
+
+fn grouped_stats((key, stats): (AggregationKey, GroupedStats)) -> pb::ClientGroupedStats {
+    pb::ClientGroupedStats {
+        service: key.service,
+        name: key.name,
+        resource: key.resource,
+        http_status_code: key.http_status_code,
+        r#type: key.r#type,
+        hits: stats.hits,
+        errors: stats.errors,
+        duration: stats.duration,
+        ok_summary: stats.ok_summary.encode_to_vec(),
+        error_summary: stats.error_summary.encode_to_vec(),
+        synthetics: key.synthetics,
+        top_level_hits: stats.top_level_hits,
+        span_kind: key.span_kind,
+        peer_tags: key.peer_tags,
+        ..pb::ClientGroupedStats::default()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn span(name: &str, start: i64, duration: i64, error: i32) -> pb::Span {
+        pb::Span {
+            service: "svc".to_string(),
+            name: name.to_string(),
+            resource: "GET /".to_string(),
+            start,
+            duration,
+            error,
+            ..pb::Span::default()
+        }
+    }
+
+    fn payload(spans: Vec<pb::Span>) -> pb::TracerPayload {
+        pb::TracerPayload {
+            env: "prod".to_string(),
+            chunks: vec![pb::TraceChunk {
+                spans,
+                ..pb::TraceChunk::default()
+            }],
+            ..pb::TracerPayload::default()
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let concentrator = StatsConcentrator::new(None, None);
+        let start = 1_700_000_000_000_000_000;
+
+        let mut root = span("aws.lambda", start, 100, 0);
+        root.metrics.insert(TOP_LEVEL_KEY.to_string(), 1.0);
+        root.meta
+            .insert(HTTP_STATUS_CODE_KEY.to_string(), "200".to_string());
+        let mut failed_root = root.clone();
+        failed_root.error = 1;
+        failed_root.duration = 300;
+        // Not top level nor measured
+        let child = span("child", start, 50, 0);
+        concentrator.add(&payload(vec![root, failed_root, child]));
+
+        let stats = concentrator.flush();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].env, "prod");
+        assert_eq!(stats[0].stats.len(), 1);
+        let bucket = &stats[0].stats[0];
+        assert_eq!(bucket.start, 1_700_000_000_000_000_000);
+        assert_eq!(bucket.duration, BUCKET_DURATION_NS);
+        assert_eq!(bucket.stats.len(), 1);
+        let grouped = &bucket.stats[0];
+        assert_eq!(grouped.name, "aws.lambda");
+        assert_eq!(grouped.http_status_code, 200);
+        assert_eq!(grouped.hits, 2);
+        assert_eq!(grouped.top_level_hits, 2);
+        assert_eq!(grouped.errors, 1);
+        assert_eq!(grouped.duration, 400);
+        assert!(!grouped.ok_summary.is_empty());
+        assert!(!grouped.error_summary.is_empty());
+
+        assert!(concentrator.flush().is_empty());
+    }
+
+    #[test]
+    fn test_stats_by_span_kind_and_peer_tags() {
+        let concentrator =
+            StatsConcentrator::new(Some(vec!["db.instance".to_string()]), Some("dev"));
+        let start = 1_700_000_000_000_000_000;
+
+        let mut client = span("postgres.query", start, 10, 0);
+        client
+            .meta
+            .insert(SPAN_KIND_KEY.to_string(), "client".to_string());
+        client
+            .meta
+            .insert("db.instance".to_string(), "orders".to_string());
+        // Partial snapshot of a long running span
+        let mut partial = client.clone();
+        partial.metrics.insert(PARTIAL_VERSION_KEY.to_string(), 1.0);
+        // In the next bucket
+        let late_client = pb::Span {
+            start: start + i64::try_from(BUCKET_DURATION_NS).unwrap(),
+            ..client.clone()
+        };
+        let mut payload = payload(vec![client, partial, late_client]);
+        payload.env = String::new();
+        concentrator.add(&payload);
+
+        let stats = concentrator.flush();
+        assert_eq!(stats[0].env, "dev");
+        assert_eq!(stats[0].stats.len(), 2);
+        let grouped = &stats[0].stats[0].stats[0];
+        assert_eq!(grouped.hits, 1);
+        assert_eq!(grouped.top_level_hits, 0);
+        assert_eq!(grouped.span_kind, "client");
+        assert_eq!(grouped.peer_tags, vec!["db.instance:orders".to_string()]);
+    }
+}
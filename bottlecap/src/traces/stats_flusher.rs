@@ -2,23 +2,31 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use async_trait::async_trait;
+use std::collections::VecDeque;
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::sync::{mpsc::Receiver, Mutex};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::config;
+use crate::retry::{self, Attempt, MAX_ATTEMPTS};
+use crate::traces::stats_concentrator::StatsConcentrator;
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::config_utils::trace_stats_url;
 use datadog_trace_utils::stats_utils;
 use ddcommon::Endpoint;
 
+/// Maximum size in bytes of the payloads kept after a failed flush, before
+/// dropping the oldest ones.
+const MAX_FAILED_PAYLOADS_SIZE_BYTES: usize = 10 * 1024 * 1024;
+
 #[async_trait]
 pub trait StatsFlusher {
     /// Starts a stats flusher that listens for stats payloads sent to the tokio mpsc Receiver,
     /// implementing flushing logic that calls flush_stats.
     async fn start_stats_flusher(&self, mut rx: Receiver<pb::ClientStatsPayload>);
-    /// Flushes stats to the Datadog trace stats intake.
+    /// Flushes stats to the Datadog trace stats intake, along with the payloads
+    /// which failed on previous flushes.
     async fn flush_stats(&self, traces: Vec<pb::ClientStatsPayload>);
 
     async fn manual_flush(&self);
@@ -28,8 +36,12 @@ pub trait StatsFlusher {
 #[derive(Clone)]
 pub struct ServerlessStatsFlusher {
     pub buffer: Arc<Mutex<Vec<pb::ClientStatsPayload>>>,
+    /// Serialized payloads which failed to be sent, oldest first.
+    pub failed_payloads: Arc<Mutex<VecDeque<Vec<u8>>>>,
     pub config: Arc<config::Config>,
     pub resolved_api_key: String,
+    /// Stats computed by the extension, flushed along with the tracers' stats.
+    pub stats_concentrator: Arc<StatsConcentrator>,
 }
 
 #[async_trait]
@@ -46,29 +58,33 @@ impl StatsFlusher for ServerlessStatsFlusher {
     }
 
     async fn manual_flush(&self) {
-        let mut buffer = self.buffer.lock().await;
-        if !buffer.is_empty() {
-            self.flush_stats(buffer.to_vec()).await;
-            buffer.clear();
-        }
+        // Don't hold the buffer while sending, new payloads keep coming
+        let mut stats = std::mem::take(&mut *self.buffer.lock().await);
+        stats.extend(self.stats_concentrator.flush());
+        self.flush_stats(stats).await;
     }
     async fn flush_stats(&self, stats: Vec<pb::ClientStatsPayload>) {
-        if stats.is_empty() {
-            return;
-        }
-        debug!("Flushing {} stats", stats.len());
+        // Payloads which failed on a previous flush go first
+        let mut payloads: Vec<Vec<u8>> = self.failed_payloads.lock().await.drain(..).collect();
 
-        let stats_payload = stats_utils::construct_stats_payload(stats);
+        if !stats.is_empty() {
+            debug!("Flushing {} stats", stats.len());
 
-        debug!("Stats payload to be sent: {stats_payload:?}");
+            let stats_payload = stats_utils::construct_stats_payload(stats);
 
-        let serialized_stats_payload = match stats_utils::serialize_stats_payload(stats_payload) {
-            Ok(res) => res,
-            Err(err) => {
-                error!("Failed to serialize stats payload, dropping stats: {err}");
-                return;
+            debug!("Stats payload to be sent: {stats_payload:?}");
+
+            match stats_utils::serialize_stats_payload(stats_payload) {
+                Ok(res) => payloads.push(res),
+                Err(err) => {
+                    error!("Failed to serialize stats payload, dropping stats: {err}");
+                }
             }
-        };
+        }
+
+        if payloads.is_empty() {
+            return;
+        }
 
         let stats_url = trace_stats_url(&self.config.site);
 
@@ -77,17 +93,78 @@ impl StatsFlusher for ServerlessStatsFlusher {
             api_key: Some(self.resolved_api_key.clone().into()),
         };
 
-        match stats_utils::send_stats_payload(
-            serialized_stats_payload,
-            &endpoint,
-            &self.config.api_key,
-        )
-        .await
-        {
-            Ok(()) => debug!("Successfully flushed stats"),
-            Err(e) => {
-                error!("Error sending stats: {e:?}");
+        let mut failed = VecDeque::new();
+        for payload in payloads {
+            if let Err(payload) = self.send(payload, &endpoint).await {
+                failed.push_back(payload);
+            }
+        }
+
+        if !failed.is_empty() {
+            let mut failed_payloads = self.failed_payloads.lock().await;
+            failed_payloads.extend(failed);
+            drop_oldest(&mut failed_payloads);
+        }
+    }
+}
+
+impl ServerlessStatsFlusher {
+    /// Sends a serialized payload to the stats intake, with retries. Returns the
+    /// payload back if every attempt failed.
+    async fn send(&self, payload: Vec<u8>, endpoint: &Endpoint) -> Result<(), Vec<u8>> {
+        let outcome = retry::send_with_backoff(|attempt| {
+            let payload = payload.clone();
+            async move {
+                match stats_utils::send_stats_payload(payload, endpoint, &self.config.api_key).await
+                {
+                    Ok(()) => Attempt::Sent,
+                    Err(e) => {
+                        debug!("Error sending stats on attempt {attempt}: {e:?}");
+                        Attempt::Failed
+                    }
+                }
             }
+        })
+        .await;
+        if outcome == Attempt::Sent {
+            debug!("Successfully flushed stats");
+            return Ok(());
         }
+
+        error!("Error sending stats after {MAX_ATTEMPTS} attempts, will retry on next flush");
+        Err(payload)
+    }
+}
+
+/// Drops the oldest payloads until they fit in the maximum size.
+fn drop_oldest(payloads: &mut VecDeque<Vec<u8>>) {
+    let dropped = retry::oldest_to_drop(
+        payloads.iter().map(Vec::len),
+        MAX_FAILED_PAYLOADS_SIZE_BYTES,
+    );
+    for dropped in payloads.drain(..dropped) {
+        warn!(
+            "Stats retry buffer is full, dropping {} bytes",
+            dropped.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_oldest() {
+        let half = MAX_FAILED_PAYLOADS_SIZE_BYTES / 2;
+        let mut payloads = VecDeque::from([vec![0; half], vec![1; half]]);
+        drop_oldest(&mut payloads);
+        assert_eq!(payloads.len(), 2);
+
+        payloads.push_back(vec![2; 1]);
+        drop_oldest(&mut payloads);
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0][0], 1);
+        assert_eq!(payloads[1], vec![2]);
     }
 }
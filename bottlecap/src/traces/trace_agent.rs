@@ -1,6 +1,7 @@
 // Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
 // SPDX-License-Identifier: Apache-2.0
 
+use hyper::body::{Bytes, HttpBody};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{http, Body, Method, Request, Response, Server, StatusCode};
 use serde_json::json;
@@ -12,8 +13,10 @@ use tokio::sync::mpsc::{self, Receiver, Sender};
 use tracing::{debug, error};
 
 use crate::config;
+use crate::events::Event;
 use crate::tags::provider;
 use crate::traces::{stats_flusher, stats_processor, trace_flusher, trace_processor};
+use crate::{DOGSTATSD_PORT, EXTENSION_VERSION};
 use datadog_trace_mini_agent::http_utils::log_and_create_http_response;
 use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils::SendData;
@@ -21,12 +24,57 @@ use datadog_trace_utils::trace_utils::SendData;
 const TRACE_AGENT_PORT: usize = 8126;
 const V4_TRACE_ENDPOINT_PATH: &str = "/v0.4/traces";
 const V5_TRACE_ENDPOINT_PATH: &str = "/v0.5/traces";
+const V7_TRACE_ENDPOINT_PATH: &str = "/v0.7/traces";
 const STATS_ENDPOINT_PATH: &str = "/v0.6/stats";
+const OTLP_TRACE_ENDPOINT_PATH: &str = "/v1/traces";
 const INFO_ENDPOINT_PATH: &str = "/info";
+const START_INVOCATION_PATH: &str = "/lambda/start-invocation";
 const TRACER_PAYLOAD_CHANNEL_BUFFER_SIZE: usize = 10;
 const STATS_PAYLOAD_CHANNEL_BUFFER_SIZE: usize = 10;
 pub const MAX_CONTENT_LENGTH: usize = 10 * 1024 * 1024;
 
+/// Peer tags aggregated in stats when `apm_peer_tags_aggregation` is enabled, on
+/// top of the configured `apm_peer_tags`.
+const BASE_PEER_TAGS: &[&str] = &[
+    "_dd.base_service",
+    "amqp.destination",
+    "amqp.exchange",
+    "amqp.queue",
+    "aws.queue.name",
+    "aws.s3.bucket",
+    "bucketname",
+    "cassandra.keyspace",
+    "db.cassandra.contact.points",
+    "db.couchbase.seed.nodes",
+    "db.hostname",
+    "db.instance",
+    "db.name",
+    "db.namespace",
+    "db.system",
+    "grpc.host",
+    "hostname",
+    "http.host",
+    "http.server_name",
+    "messaging.destination",
+    "messaging.destination.name",
+    "messaging.kafka.bootstrap.servers",
+    "messaging.rabbitmq.exchange",
+    "messaging.system",
+    "mongodb.db",
+    "msmq.queue.path",
+    "net.peer.name",
+    "network.destination.name",
+    "peer.hostname",
+    "peer.service",
+    "queuename",
+    "rpc.service",
+    "rpc.system",
+    "server.address",
+    "streamname",
+    "tablename",
+    "topicname",
+];
+
 pub struct TraceAgent {
     pub config: Arc<config::Config>,
     pub trace_processor: Arc<dyn trace_processor::TraceProcessor + Send + Sync>,
@@ -34,12 +82,14 @@ pub struct TraceAgent {
     pub stats_processor: Arc<dyn stats_processor::StatsProcessor + Send + Sync>,
     pub stats_flusher: Arc<dyn stats_flusher::StatsFlusher + Send + Sync>,
     pub tags_provider: Arc<provider::Provider>,
+    pub event_bus: Sender<Event>,
 }
 
 #[derive(Clone, Copy)]
 pub enum ApiVersion {
     V04,
     V05,
+    V07,
 }
 
 impl TraceAgent {
@@ -80,6 +130,7 @@ impl TraceAgent {
         let stats_processor = self.stats_processor.clone();
         let endpoint_config = self.config.clone();
         let tags_provider = self.tags_provider.clone();
+        let event_bus = self.event_bus.clone();
 
         let make_svc = make_service_fn(move |_| {
             let trace_processor = trace_processor.clone();
@@ -90,6 +141,7 @@ impl TraceAgent {
 
             let endpoint_config = endpoint_config.clone();
             let tags_provider = tags_provider.clone();
+            let event_bus = event_bus.clone();
 
             let service = service_fn(move |req| {
                 TraceAgent::trace_endpoint_handler(
@@ -100,6 +152,7 @@ impl TraceAgent {
                     stats_processor.clone(),
                     stats_tx.clone(),
                     tags_provider.clone(),
+                    event_bus.clone(),
                 )
             });
 
@@ -127,6 +180,7 @@ impl TraceAgent {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn trace_endpoint_handler(
         config: Arc<config::Config>,
         req: Request<Body>,
@@ -135,6 +189,7 @@ impl TraceAgent {
         stats_processor: Arc<dyn stats_processor::StatsProcessor + Send + Sync>,
         stats_tx: Sender<pb::ClientStatsPayload>,
         tags_provider: Arc<provider::Provider>,
+        event_bus: Sender<Event>,
     ) -> http::Result<Response<Body>> {
         match (req.method(), req.uri().path()) {
             (&Method::PUT | &Method::POST, V4_TRACE_ENDPOINT_PATH) => {
@@ -161,6 +216,30 @@ impl TraceAgent {
                     ),
                 }
             }
+            (&Method::POST, OTLP_TRACE_ENDPOINT_PATH) if config.otlp_config_traces_enabled => {
+                match trace_processor
+                    .process_otlp_traces(config, req, trace_tx, tags_provider)
+                    .await
+                {
+                    Ok(result) => Ok(result),
+                    Err(err) => log_and_create_http_response(
+                        &format!("Error processing OTLP traces: {err}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                }
+            }
+            (&Method::PUT | &Method::POST, V7_TRACE_ENDPOINT_PATH) => {
+                match trace_processor
+                    .process_traces(config, req, trace_tx, tags_provider, ApiVersion::V07)
+                    .await
+                {
+                    Ok(result) => Ok(result),
+                    Err(err) => log_and_create_http_response(
+                        &format!("Error processing traces: {err}"),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                }
+            }
             (&Method::PUT | &Method::POST, STATS_ENDPOINT_PATH) => {
                 match stats_processor.process_stats(req, stats_tx).await {
                     Ok(result) => Ok(result),
@@ -170,7 +249,16 @@ impl TraceAgent {
                     ),
                 }
             }
-            (_, INFO_ENDPOINT_PATH) => match Self::info_handler() {
+            (&Method::POST, START_INVOCATION_PATH) => {
+                match Self::start_invocation_handler(req, event_bus).await {
+                    Ok(result) => Ok(result),
+                    Err(err) => log_and_create_http_response(
+                        &format!("Error processing invocation payload: {err}"),
+                        StatusCode::BAD_REQUEST,
+                    ),
+                }
+            }
+            (_, INFO_ENDPOINT_PATH) => match Self::info_handler(&config) {
                 Ok(result) => Ok(result),
                 Err(err) => log_and_create_http_response(
                     &format!("Info endpoint error: {err}"),
@@ -185,15 +273,62 @@ impl TraceAgent {
         }
     }
 
-    fn info_handler() -> http::Result<Response<Body>> {
+    /// Receives the event which triggered the invocation from the tracer, to
+    /// infer the span of the trigger.
+    async fn start_invocation_handler(
+        req: Request<Body>,
+        event_bus: Sender<Event>,
+    ) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(body) = read_body(req.into_body(), MAX_CONTENT_LENGTH).await? else {
+            return Err("Invocation payload is too large".into());
+        };
+        let payload = serde_json::from_slice(&body)?;
+        event_bus
+            .send(Event::InvocationPayload(None, payload))
+            .await?;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::empty())?)
+    }
+
+    /// Capabilities of the agent, which tracers use to pick the endpoints and
+    /// the features they rely on.
+    fn info_handler(config: &config::Config) -> http::Result<Response<Body>> {
+        let mut endpoints = vec![
+            V4_TRACE_ENDPOINT_PATH,
+            V5_TRACE_ENDPOINT_PATH,
+            V7_TRACE_ENDPOINT_PATH,
+            STATS_ENDPOINT_PATH,
+            INFO_ENDPOINT_PATH,
+        ];
+        if config.otlp_config_traces_enabled {
+            endpoints.push(OTLP_TRACE_ENDPOINT_PATH);
+        }
+        // The invocation payload is only used by the spans the extension
+        // generates
+        if config.universal_instrumentation {
+            endpoints.push(START_INVOCATION_PATH);
+        }
+
         let response_json = json!(
             {
-                "endpoints": [
-                    V4_TRACE_ENDPOINT_PATH,
-                    STATS_ENDPOINT_PATH,
-                    INFO_ENDPOINT_PATH
-                ],
+                "version": EXTENSION_VERSION,
+                "endpoints": endpoints,
+                "feature_flags": config.apm_features,
                 "client_drop_p0s": true,
+                // Meta structs carry the AppSec data of the spans, and the
+                // extension fails over to the Go agent when AppSec is enabled
+                "span_meta_structs": false,
+                "long_running_spans": false,
+                "config": {
+                    "default_env": config.env.as_deref().unwrap_or("none"),
+                    "receiver_port": TRACE_AGENT_PORT,
+                    "receiver_socket": "",
+                    "max_request_bytes": MAX_CONTENT_LENGTH,
+                    "statsd_port": DOGSTATSD_PORT,
+                },
+                "peer_tags": peer_tags(config),
             }
         );
         Response::builder()
@@ -201,3 +336,100 @@ impl TraceAgent {
             .body(Body::from(response_json.to_string()))
     }
 }
+
+/// Reads a body of at most `limit` bytes, `None` when it's bigger.
+pub async fn read_body(mut body: Body, limit: usize) -> Result<Option<Bytes>, hyper::Error> {
+    if body.size_hint().lower() > limit as u64 {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(Bytes::from(bytes)))
+}
+
+/// Peer tags to aggregate stats by, `None` when peer tags aggregation is disabled.
+#[must_use]
+pub fn peer_tags(config: &config::Config) -> Option<Vec<String>> {
+    if !config.apm_peer_tags_aggregation {
+        return None;
+    }
+    let mut peer_tags: Vec<String> = BASE_PEER_TAGS
+        .iter()
+        .map(|tag| (*tag).to_string())
+        .chain(config.apm_peer_tags.iter().cloned())
+        .collect();
+    peer_tags.sort();
+    peer_tags.dedup();
+    Some(peer_tags)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn test_info_handler() {
+        let config = config::Config {
+            env: Some("prod".to_string()),
+            apm_features: vec!["enable_cid_stats".to_string()],
+            apm_peer_tags_aggregation: true,
+            apm_peer_tags: vec!["custom.peer".to_string(), "db.name".to_string()],
+            ..config::Config::default()
+        };
+
+        let response = TraceAgent::info_handler(&config).unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let info: Value = serde_json::from_slice(&body).unwrap();
+
+        let endpoints = info["endpoints"].as_array().unwrap();
+        assert!(endpoints.contains(&json!("/v0.5/traces")));
+        assert!(endpoints.contains(&json!("/v0.7/traces")));
+        assert!(endpoints.contains(&json!("/v1/traces")));
+        assert!(!endpoints.contains(&json!("/lambda/start-invocation")));
+        assert_eq!(info["version"], json!(EXTENSION_VERSION));
+        assert_eq!(info["feature_flags"], json!(["enable_cid_stats"]));
+        assert_eq!(info["span_meta_structs"], json!(false));
+        assert_eq!(info["config"]["default_env"], json!("prod"));
+
+        let peer_tags = info["peer_tags"].as_array().unwrap();
+        assert!(peer_tags.contains(&json!("custom.peer")));
+        assert_eq!(peer_tags.iter().filter(|tag| *tag == "db.name").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_info_handler_features() {
+        let config = config::Config {
+            otlp_config_traces_enabled: false,
+            universal_instrumentation: true,
+            ..config::Config::default()
+        };
+
+        let response = TraceAgent::info_handler(&config).unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let info: Value = serde_json::from_slice(&body).unwrap();
+
+        let endpoints = info["endpoints"].as_array().unwrap();
+        assert!(!endpoints.contains(&json!("/v1/traces")));
+        assert!(endpoints.contains(&json!("/lambda/start-invocation")));
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limit() {
+        let body = read_body(Body::from("{}"), 2).await.unwrap();
+        assert_eq!(body, Some(Bytes::from("{}")));
+        assert_eq!(read_body(Body::from("{}"), 1).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_peer_tags_disabled() {
+        assert_eq!(peer_tags(&config::Config::default()), None);
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2023-Present Datadog, Inc. https://www.datadoghq.com/
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use datadog_trace_protobuf::pb;
+use regex::Regex;
+use tracing::error;
+
+use crate::config;
+
+/// Addresses of the extension and the Lambda Runtime API, which spans
+/// shouldn't be sent for.
+const LOCALHOST_ADDRESSES: [&str; 3] = ["127.0.0.1", "0.0.0.0", "localhost"];
+
+/// Tag filter, matching spans with the `key` tag, and the given value if any.
+#[derive(Clone, Debug, PartialEq)]
+struct FilterTag {
+    key: String,
+    value: Option<String>,
+}
+
+impl FilterTag {
+    fn parse(tag: &str) -> FilterTag {
+        match tag.split_once(':') {
+            Some((key, value)) => FilterTag {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => FilterTag {
+                key: tag.to_string(),
+                value: None,
+            },
+        }
+    }
+
+    fn matches(&self, span: &pb::Span) -> bool {
+        match (span.meta.get(&self.key), &self.value) {
+            (Some(value), Some(expected)) => value == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Drops traces from their root span, following `apm_filter_tags_require`,
+/// `apm_filter_tags_reject` and `apm_ignore_resources`.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    require_tags: Vec<FilterTag>,
+    reject_tags: Vec<FilterTag>,
+    ignore_resources: Vec<Regex>,
+}
+
+impl TraceFilter {
+    #[must_use]
+    pub fn new(config: &config::Config) -> TraceFilter {
+        let ignore_resources = config
+            .apm_ignore_resources
+            .iter()
+            .filter_map(|pattern| match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    error!("Invalid ignored resource pattern `{pattern}`, skipping it: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        TraceFilter {
+            require_tags: config
+                .apm_filter_tags_require
+                .iter()
+                .map(|tag| FilterTag::parse(tag))
+                .collect(),
+            reject_tags: config
+                .apm_filter_tags_reject
+                .iter()
+                .map(|tag| FilterTag::parse(tag))
+                .collect(),
+            ignore_resources,
+        }
+    }
+
+    /// Whether a trace should be sent, judging from its root span. A trace
+    /// must have every required tag, none of the rejected ones, and a resource
+    /// that isn't ignored.
+    #[must_use]
+    pub fn should_keep_trace(&self, trace: &[pb::Span]) -> bool {
+        let Some(root_span) = root_span(trace) else {
+            return true;
+        };
+
+        self.require_tags.iter().all(|tag| tag.matches(root_span))
+            && !self.reject_tags.iter().any(|tag| tag.matches(root_span))
+            && !self
+                .ignore_resources
+                .iter()
+                .any(|regex| regex.is_match(&root_span.resource))
+    }
+}
+
+/// Whether a span is for a call to the extension or the Lambda Runtime API,
+/// made by the tracer or the runtime itself.
+#[must_use]
+pub fn is_extension_traffic(span: &pb::Span) -> bool {
+    if span.name == "dns.lookup" || LOCALHOST_ADDRESSES.contains(&span.resource.as_str()) {
+        return true;
+    }
+
+    span.meta
+        .get("http.url")
+        .and_then(|url| hyper::Uri::from_str(url).ok())
+        .is_some_and(|uri| {
+            uri.host()
+                .is_some_and(|host| LOCALHOST_ADDRESSES.contains(&host))
+        })
+}
+
+/// The span without a parent in the trace.
+fn root_span(trace: &[pb::Span]) -> Option<&pb::Span> {
+    let span_ids: HashSet<u64> = trace.iter().map(|span| span.span_id).collect();
+    trace
+        .iter()
+        .find(|span| span.parent_id == 0 || !span_ids.contains(&span.parent_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(span_id: u64, parent_id: u64, resource: &str, meta: &[(&str, &str)]) -> pb::Span {
+        pb::Span {
+            trace_id: 1,
+            span_id,
+            parent_id,
+            service: "test-service".to_string(),
+            name: "test_name".to_string(),
+            resource: resource.to_string(),
+            meta: meta
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect(),
+            ..pb::Span::default()
+        }
+    }
+
+    fn filter(require: &[&str], reject: &[&str], ignore_resources: &[&str]) -> TraceFilter {
+        let to_strings = |values: &[&str]| values.iter().map(|v| (*v).to_string()).collect();
+        TraceFilter::new(&config::Config {
+            apm_filter_tags_require: to_strings(require),
+            apm_filter_tags_reject: to_strings(reject),
+            apm_ignore_resources: to_strings(ignore_resources),
+            ..config::Config::default()
+        })
+    }
+
+    #[test]
+    fn test_keep_trace_without_filters() {
+        let trace = vec![span(1, 0, "GET /users", &[])];
+        assert!(TraceFilter::default().should_keep_trace(&trace));
+    }
+
+    #[test]
+    fn test_require_tags() {
+        let filter = filter(&["env:prod", "region"], &[], &[]);
+
+        let trace = vec![span(
+            1,
+            0,
+            "GET /",
+            &[("env", "prod"), ("region", "us-east-1")],
+        )];
+        assert!(filter.should_keep_trace(&trace));
+
+        let trace = vec![span(
+            1,
+            0,
+            "GET /",
+            &[("env", "dev"), ("region", "us-east-1")],
+        )];
+        assert!(!filter.should_keep_trace(&trace));
+
+        let trace = vec![span(1, 0, "GET /", &[("env", "prod")])];
+        assert!(!filter.should_keep_trace(&trace));
+    }
+
+    #[test]
+    fn test_reject_tags() {
+        let filter = filter(&[], &["http.status_code:404", "debug"], &[]);
+
+        let trace = vec![span(1, 0, "GET /", &[("http.status_code", "200")])];
+        assert!(filter.should_keep_trace(&trace));
+
+        let trace = vec![span(1, 0, "GET /", &[("http.status_code", "404")])];
+        assert!(!filter.should_keep_trace(&trace));
+
+        let trace = vec![span(1, 0, "GET /", &[("debug", "true")])];
+        assert!(!filter.should_keep_trace(&trace));
+    }
+
+    #[test]
+    fn test_filters_apply_to_root_span() {
+        let filter = filter(&[], &["env:dev"], &["^GET /health$"]);
+
+        // Only a child span has the rejected tag
+        let trace = vec![
+            span(2, 1, "SELECT", &[("env", "dev")]),
+            span(1, 0, "GET /users", &[]),
+        ];
+        assert!(filter.should_keep_trace(&trace));
+
+        // Root span of a partial trace, its parent isn't part of it
+        let trace = vec![span(2, 42, "GET /health", &[]), span(3, 2, "SELECT", &[])];
+        assert!(!filter.should_keep_trace(&trace));
+    }
+
+    #[test]
+    fn test_ignore_resources() {
+        let filter = filter(&[], &[], &["^GET /health$", "(invalid", "^OPTIONS "]);
+
+        assert!(!filter.should_keep_trace(&[span(1, 0, "GET /health", &[])]));
+        assert!(!filter.should_keep_trace(&[span(1, 0, "OPTIONS /users", &[])]));
+        assert!(filter.should_keep_trace(&[span(1, 0, "GET /healthz", &[])]));
+    }
+
+    #[test]
+    fn test_is_extension_traffic() {
+        assert!(is_extension_traffic(&span(1, 0, "127.0.0.1", &[])));
+        assert!(is_extension_traffic(&span(1, 0, "0.0.0.0", &[])));
+        assert!(is_extension_traffic(&span(
+            1,
+            0,
+            "GET",
+            &[(
+                "http.url",
+                "http://127.0.0.1:9001/2018-06-01/runtime/invocation/next"
+            )]
+        )));
+        assert!(is_extension_traffic(&span(
+            1,
+            0,
+            "POST",
+            &[("http.url", "http://localhost:8126/v0.4/traces")]
+        )));
+
+        let mut dns_span = span(1, 0, "example.com", &[]);
+        dns_span.name = "dns.lookup".to_string();
+        assert!(is_extension_traffic(&dns_span));
+
+        assert!(!is_extension_traffic(&span(
+            1,
+            0,
+            "GET",
+            &[("http.url", "https://example.com/users")]
+        )));
+        assert!(!is_extension_traffic(&span(1, 0, "GET /users", &[])));
+    }
+}
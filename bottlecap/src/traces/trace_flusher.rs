@@ -2,18 +2,27 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use async_trait::async_trait;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{mpsc::Receiver, Mutex};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use datadog_trace_utils::trace_utils::{self, SendData};
 
+use super::trace_agent::MAX_CONTENT_LENGTH;
+use crate::retry;
+
+/// Maximum size in bytes of the payloads waiting to be flushed, before
+/// dropping the oldest ones.
+const MAX_BUFFER_SIZE_BYTES: usize = MAX_CONTENT_LENGTH;
+
 #[async_trait]
 pub trait TraceFlusher {
     /// Starts a trace flusher that listens for trace payloads sent to the tokio mpsc Receiver,
     /// implementing flushing logic that calls flush_traces.
     async fn start_trace_flusher(&self, mut rx: Receiver<SendData>);
-    /// Flushes traces to the Datadog trace intake.
+    /// Flushes traces to the Datadog trace intake. Payloads which couldn't be
+    /// sent are put back in the buffer for the next flush.
     async fn flush_traces(&self, traces: Vec<SendData>);
 
     async fn manual_flush(&self);
@@ -33,16 +42,15 @@ impl TraceFlusher for ServerlessTraceFlusher {
             while let Some(tracer_payload) = rx.recv().await {
                 let mut buffer = buffer_producer.lock().await;
                 buffer.push(tracer_payload);
+                drop_oldest(&mut buffer);
             }
         });
     }
 
     async fn manual_flush(&self) {
-        let mut buffer = self.buffer.lock().await;
-        if !buffer.is_empty() {
-            self.flush_traces(buffer.to_vec()).await;
-            buffer.clear();
-        }
+        // Don't hold the buffer while sending, new payloads keep coming
+        let traces = std::mem::take(&mut *self.buffer.lock().await);
+        self.flush_traces(traces).await;
     }
 
     async fn flush_traces(&self, traces: Vec<SendData>) {
@@ -51,14 +59,84 @@ impl TraceFlusher for ServerlessTraceFlusher {
         }
         debug!("Flushing {} traces", traces.len());
 
+        let mut failed = Vec::new();
         for traces in trace_utils::coalesce_send_data(traces) {
-            match traces.send().await.last_result {
-                Ok(_) => debug!("Successfully flushed traces"),
-                Err(e) => {
-                    error!("Error sending trace: {e:?}");
-                    // TODO: Retries
-                }
+            if let Err(traces) = Self::send(traces).await {
+                failed.push(traces);
             }
         }
+
+        if !failed.is_empty() {
+            // Failed payloads are older than the buffered ones
+            let mut buffer = self.buffer.lock().await;
+            failed.append(&mut buffer);
+            *buffer = failed;
+            drop_oldest(&mut buffer);
+        }
+    }
+}
+
+impl ServerlessTraceFlusher {
+    /// Adds a payload generated by the extension to the buffer, to be sent on
+    /// the next flush.
+    pub async fn add(&self, traces: SendData) {
+        let mut buffer = self.buffer.lock().await;
+        buffer.push(traces);
+        drop_oldest(&mut buffer);
+    }
+
+    /// Sends a payload to the trace intake. Returns the payload back if it's
+    /// worth sending again on the next flush.
+    ///
+    /// `SendData::send` already retries with its own backoff, so the payload
+    /// is sent once per flush rather than going through `retry::send_with_backoff`.
+    async fn send(traces: SendData) -> Result<(), SendData> {
+        let result = traces.send().await;
+        match result.last_result {
+            Ok(_) => {
+                debug!("Successfully flushed traces");
+                Ok(())
+            }
+            Err(e) if !is_retryable(&result.responses_count_per_code) => {
+                error!("Error sending traces, dropping them: {e:?}");
+                Ok(())
+            }
+            Err(e) => {
+                error!("Error sending traces, will retry on next flush: {e:?}");
+                Err(traces)
+            }
+        }
+    }
+}
+
+/// Whether a failed send is worth retrying, from the status codes of its
+/// responses. Network errors don't get any.
+fn is_retryable(responses_count_per_code: &HashMap<u16, u64>) -> bool {
+    responses_count_per_code
+        .keys()
+        .all(|code| *code < 400 || retry::is_retryable(*code))
+}
+
+/// Drops the oldest payloads until the buffer fits in its maximum size.
+fn drop_oldest(buffer: &mut Vec<SendData>) {
+    let dropped = retry::oldest_to_drop(buffer.iter().map(SendData::len), MAX_BUFFER_SIZE_BYTES);
+    if dropped > 0 {
+        warn!("Traces buffer is full, dropping {dropped} payloads");
+        buffer.drain(..dropped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&HashMap::new()));
+        assert!(is_retryable(&HashMap::from([(500, 1)])));
+        assert!(is_retryable(&HashMap::from([(503, 2), (429, 1)])));
+        assert!(is_retryable(&HashMap::from([(408, 1)])));
+        assert!(!is_retryable(&HashMap::from([(400, 1)])));
+        assert!(!is_retryable(&HashMap::from([(403, 1), (500, 1)])));
     }
 }
@@ -4,23 +4,32 @@
 use crate::tags::provider;
 use datadog_trace_obfuscation::obfuscation_config;
 use datadog_trace_utils::config_utils::trace_intake_url;
-use datadog_trace_utils::tracer_payload::TraceEncoding;
+use datadog_trace_utils::tracer_payload::{TraceEncoding, TracerPayloadCollection};
 use ddcommon::Endpoint;
+use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use hyper::{http, Body, Request, Response, StatusCode};
+use hyper::{header, http, Body, Request, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::Sender;
 use tracing::debug;
 
 use crate::config;
 use datadog_trace_mini_agent::http_utils::{self, log_and_create_http_response};
 use datadog_trace_obfuscation::obfuscate::obfuscate_span;
+use datadog_trace_protobuf::pb;
 use datadog_trace_utils::trace_utils::SendData;
-use datadog_trace_utils::trace_utils::{self};
+use datadog_trace_utils::trace_utils::{self, TracerHeaderTags};
 
-use super::trace_agent::{ApiVersion, MAX_CONTENT_LENGTH};
+use super::otlp;
+use super::priority_sampler::PrioritySampler;
+use super::samplers::{ErrorSampler, RareSampler, PRIORITY_AUTO_DROP, PRIORITY_NONE};
+use super::stats_concentrator::StatsConcentrator;
+use super::trace_agent::{read_body, ApiVersion, MAX_CONTENT_LENGTH};
+use super::trace_filter::{is_extension_traffic, TraceFilter};
 
 #[async_trait]
 pub trait TraceProcessor {
@@ -34,12 +43,27 @@ pub trait TraceProcessor {
         tags_provider: Arc<provider::Provider>,
         version: ApiVersion,
     ) -> http::Result<Response<Body>>;
+
+    /// Converts OTLP traces from a hyper request body, encoded as protobuf or
+    /// JSON, to Datadog spans and sends them through the provided tokio mpsc Sender.
+    async fn process_otlp_traces(
+        &self,
+        config: Arc<config::Config>,
+        req: Request<Body>,
+        tx: Sender<trace_utils::SendData>,
+        tags_provider: Arc<provider::Provider>,
+    ) -> http::Result<Response<Body>>;
 }
 #[derive(Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub struct ServerlessTraceProcessor {
     pub obfuscation_config: Arc<obfuscation_config::ObfuscationConfig>,
     pub resolved_api_key: String,
+    pub trace_filter: TraceFilter,
+    pub priority_sampler: Arc<PrioritySampler>,
+    pub stats_concentrator: Arc<StatsConcentrator>,
+    pub error_sampler: Arc<ErrorSampler>,
+    pub rare_sampler: Arc<RareSampler>,
 }
 
 #[async_trait]
@@ -86,16 +110,164 @@ impl TraceProcessor for ServerlessTraceProcessor {
                     );
                 }
             },
+            ApiVersion::V07 => {
+                let body = match read_body(body, MAX_CONTENT_LENGTH).await {
+                    Ok(Some(body)) => body,
+                    Ok(None) => {
+                        return log_and_create_http_response(
+                            "Error processing traces: payload too large",
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                        );
+                    }
+                    Err(err) => {
+                        return log_and_create_http_response(
+                            &format!("Error reading trace request body: {err}"),
+                            StatusCode::BAD_REQUEST,
+                        );
+                    }
+                };
+                match get_v07_traces_from_request_body(&body) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        return log_and_create_http_response(
+                            &format!("Error deserializing trace from request body: {err}"),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        );
+                    }
+                }
+            }
         };
 
-        let payload = trace_utils::collect_trace_chunks(
+        for trace in &traces {
+            self.priority_sampler.count_trace(trace);
+        }
+
+        // send trace payload to our trace flusher
+        if let Err(err) = self
+            .send_traces(
+                config,
+                tx,
+                tags_provider,
+                tracer_header_tags,
+                body_size,
+                traces,
+            )
+            .await
+        {
+            return log_and_create_http_response(
+                &format!("Error sending traces to the trace flusher: {err}"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+
+        // tracers adjust their sampling to the rates in the response
+        debug!("Successfully buffered traces to be flushed.");
+        let response_json = json!({ "rate_by_service": self.priority_sampler.rates_by_service() });
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(response_json.to_string()))
+    }
+
+    async fn process_otlp_traces(
+        &self,
+        config: Arc<config::Config>,
+        req: Request<Body>,
+        tx: Sender<trace_utils::SendData>,
+        tags_provider: Arc<provider::Provider>,
+    ) -> http::Result<Response<Body>> {
+        debug!("Received OTLP traces to process");
+        let (parts, body) = req.into_parts();
+        let encoding = otlp::Encoding::from_content_type(
+            parts
+                .headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok()),
+        );
+        let gzipped = parts
+            .headers
+            .get(header::CONTENT_ENCODING)
+            .is_some_and(|value| value == "gzip");
+
+        let body = match read_body(body, MAX_CONTENT_LENGTH).await {
+            Ok(Some(body)) => body,
+            Ok(None) => {
+                return log_and_create_http_response(
+                    "Error processing OTLP traces: payload too large",
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                );
+            }
+            Err(err) => {
+                return log_and_create_http_response(
+                    &format!("Error reading OTLP request body: {err}"),
+                    StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+        let request = match otlp::decode_request(&body, encoding, gzipped) {
+            Ok(request) => request,
+            Err(err) => {
+                return log_and_create_http_response(
+                    &format!("Error decoding OTLP traces: {err}"),
+                    StatusCode::BAD_REQUEST,
+                );
+            }
+        };
+
+        // OTLP traces aren't counted by the priority sampler: OTLP SDKs sample
+        // on their own and never get the `rate_by_service` of Datadog tracers,
+        // whose rates would be lowered by traffic they don't sample.
+
+        // top level spans are set from the span kinds
+        let tracer_header_tags = TracerHeaderTags {
+            lang: otlp::sdk_language(&request).unwrap_or_default(),
+            client_computed_top_level: true,
+            ..TracerHeaderTags::default()
+        };
+        let traces = otlp::to_dd_traces(&request);
+        if let Err(err) = self
+            .send_traces(
+                config,
+                tx,
+                tags_provider,
+                tracer_header_tags,
+                body.len(),
+                traces,
+            )
+            .await
+        {
+            return log_and_create_http_response(
+                &format!("Error sending traces to the trace flusher: {err}"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, encoding.content_type())
+            .body(Body::from(otlp::encode_response(encoding)))
+    }
+}
+
+impl ServerlessTraceProcessor {
+    /// Filters, tags and obfuscates the traces, computes their stats, then
+    /// sends the sampled ones to the trace flusher.
+    async fn send_traces(
+        &self,
+        config: Arc<config::Config>,
+        tx: Sender<trace_utils::SendData>,
+        tags_provider: Arc<provider::Provider>,
+        tracer_header_tags: TracerHeaderTags<'_>,
+        body_size: usize,
+        mut traces: Vec<Vec<pb::Span>>,
+    ) -> Result<(), SendError<SendData>> {
+        traces.retain(|trace| self.trace_filter.should_keep_trace(trace));
+
+        let mut payload = trace_utils::collect_trace_chunks(
             traces,
             &tracer_header_tags,
             |chunk, _root_span_index| {
-                chunk.spans.retain(|span| {
-                    (span.resource != "127.0.0.1" || span.resource != "0.0.0.0")
-                        && span.name != "dns.lookup"
-                });
+                chunk.spans.retain(|span| !is_extension_traffic(span));
                 for span in &mut chunk.spans {
                     tags_provider.get_tags_map().iter().for_each(|(k, v)| {
                         span.meta.insert(k.clone(), v.clone());
@@ -110,6 +282,16 @@ impl TraceProcessor for ServerlessTraceProcessor {
             true,
             TraceEncoding::V07,
         );
+        // Without the extension's stats, the backend computes them from every
+        // trace, so traces can only be sampled out once counted here
+        if config.compute_trace_stats && !tracer_header_tags.client_computed_stats {
+            if let TracerPayloadCollection::V07(payloads) = &mut payload {
+                for payload in payloads {
+                    self.stats_concentrator.add(payload);
+                    payload.chunks.retain_mut(|chunk| self.sample_chunk(chunk));
+                }
+            }
+        }
         let intake_url = trace_intake_url(&config.site);
         let endpoint = Endpoint {
             url: hyper::Uri::from_str(&intake_url).expect("can't parse trace intake URL, exiting"),
@@ -117,25 +299,59 @@ impl TraceProcessor for ServerlessTraceProcessor {
         };
 
         let send_data = SendData::new(body_size, payload, tracer_header_tags, &endpoint);
+        tx.send(send_data).await
+    }
 
-        // send trace payload to our trace flusher
-        match tx.send(send_data).await {
-            Ok(()) => {
-                return log_and_create_http_response(
-                    "Successfully buffered traces to be flushed.",
-                    StatusCode::ACCEPTED,
-                );
-            }
-            Err(err) => {
-                return log_and_create_http_response(
-                    &format!("Error sending traces to the trace flusher: {err}"),
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                );
-            }
+    /// Keeps the traces the tracer kept, and the ones its sampling dropped
+    /// which have rare spans or errors, as the Datadog Agent does. Traces
+    /// dropped by the user are never kept.
+    fn sample_chunk(&self, chunk: &mut pb::TraceChunk) -> bool {
+        // Before the decision, so that the spans of kept traces aren't rare
+        let rare = self.rare_sampler.sample(chunk);
+        match chunk.priority {
+            PRIORITY_NONE => true,
+            PRIORITY_AUTO_DROP => rare || self.error_sampler.sample(chunk),
+            priority => priority > PRIORITY_AUTO_DROP,
         }
     }
 }
 
+/// Deserializes a msgpack encoded tracer payload, as sent to `/v0.7/traces`.
+///
+/// The chunks' sampling priority and propagated tags are moved to their root
+/// span, as the chunks are rebuilt by `collect_trace_chunks`.
+fn get_v07_traces_from_request_body(
+    body: &[u8],
+) -> Result<(usize, Vec<Vec<pb::Span>>), Box<dyn std::error::Error + Send + Sync>> {
+    let payload: pb::TracerPayload = rmp_serde::from_slice(body)?;
+
+    let traces = payload
+        .chunks
+        .into_iter()
+        .filter(|chunk| !chunk.spans.is_empty())
+        .map(|mut chunk| {
+            let span_ids: HashSet<u64> = chunk.spans.iter().map(|span| span.span_id).collect();
+            let root_index = chunk
+                .spans
+                .iter()
+                .position(|span| !span_ids.contains(&span.parent_id))
+                .unwrap_or_default();
+            let root_span = &mut chunk.spans[root_index];
+            if chunk.priority != i32::from(i8::MIN) {
+                root_span
+                    .metrics
+                    .entry("_sampling_priority_v1".to_string())
+                    .or_insert(f64::from(chunk.priority));
+            }
+            for (key, value) in chunk.tags {
+                root_span.meta.entry(key).or_insert(value);
+            }
+            chunk.spans
+        })
+        .collect();
+    Ok((body.len(), traces))
+}
+
 #[cfg(test)]
 mod tests {
     use datadog_trace_obfuscation::obfuscation_config::ObfuscationConfig;
@@ -150,6 +366,11 @@ mod tests {
 
     use crate::config::Config;
     use crate::tags::provider::Provider;
+    use crate::traces::priority_sampler::PrioritySampler;
+    use crate::traces::samplers::{ErrorSampler, RareSampler};
+    use crate::traces::stats_concentrator::StatsConcentrator;
+    use crate::traces::trace_agent::MAX_CONTENT_LENGTH;
+    use crate::traces::trace_filter::TraceFilter;
     use crate::traces::trace_processor::{self, TraceProcessor};
     use crate::LAMBDA_RUNTIME_SLUG;
     use datadog_trace_protobuf::pb;
@@ -173,6 +394,18 @@ mod tests {
         })
     }
 
+    fn create_test_processor() -> trace_processor::ServerlessTraceProcessor {
+        trace_processor::ServerlessTraceProcessor {
+            resolved_api_key: "foo".to_string(),
+            obfuscation_config: Arc::new(ObfuscationConfig::new().unwrap()),
+            trace_filter: TraceFilter::default(),
+            priority_sampler: Arc::new(PrioritySampler::new(10.0, None)),
+            stats_concentrator: Arc::new(StatsConcentrator::new(None, None)),
+            error_sampler: Arc::new(ErrorSampler::new(10.0)),
+            rare_sampler: Arc::new(RareSampler::new(&Config::default())),
+        }
+    }
+
     fn create_tags_provider(config: Arc<Config>) -> Arc<Provider> {
         let mut metadata = HashMap::new();
         metadata.insert(
@@ -275,10 +508,7 @@ mod tests {
             .body(hyper::body::Body::from(bytes))
             .expect("fail to build request");
 
-        let trace_processor = trace_processor::ServerlessTraceProcessor {
-            resolved_api_key: "foo".to_string(),
-            obfuscation_config: Arc::new(ObfuscationConfig::new().unwrap()),
-        };
+        let trace_processor = create_test_processor();
         let config = create_test_config();
         let tags_provider = create_tags_provider(config.clone());
         let res = trace_processor
@@ -289,8 +519,11 @@ mod tests {
                 tags_provider.clone(),
                 crate::traces::trace_agent::ApiVersion::V04,
             )
-            .await;
-        assert!(res.is_ok());
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"rate_by_service": {"service:,env:": 1.0}}));
 
         let tracer_payload = rx.recv().await;
 
@@ -327,5 +560,267 @@ mod tests {
             expected_tracer_payload,
             received_payload.expect("no payload received")
         );
+
+        let stats = trace_processor.stats_concentrator.flush();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].stats[0].stats[0].hits, 1);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    #[cfg_attr(miri, ignore)]
+    async fn test_process_trace_auto_drop() {
+        let start = get_current_timestamp_nanos();
+        let mut json_span = create_test_json_span(11, 222, 0, start);
+        json_span["metrics"] = json!({"_sampling_priority_v1": 0.0});
+        let bytes = rmp_serde::to_vec(&vec![vec![json_span]]).expect("invalid json");
+
+        for compute_trace_stats in [true, false] {
+            let (tx, mut rx): (
+                Sender<trace_utils::SendData>,
+                Receiver<trace_utils::SendData>,
+            ) = mpsc::channel(1);
+            let request = Request::builder()
+                .header("datadog-meta-lang", "nodejs")
+                .header("content-length", "100")
+                .body(hyper::body::Body::from(bytes.clone()))
+                .expect("fail to build request");
+
+            let config = Arc::new(Config {
+                compute_trace_stats,
+                ..Config::default()
+            });
+            let tags_provider = create_tags_provider(config.clone());
+            create_test_processor()
+                .process_traces(
+                    config,
+                    request,
+                    tx,
+                    tags_provider,
+                    crate::traces::trace_agent::ApiVersion::V04,
+                )
+                .await
+                .unwrap();
+
+            let send_data = rx.recv().await.expect("no payload");
+            let TracerPayloadCollection::V07(payloads) = send_data.get_payloads() else {
+                panic!("expected a v0.7 payload");
+            };
+            // Only dropped once counted in the extension's stats
+            assert_eq!(
+                payloads[0].chunks.is_empty(),
+                compute_trace_stats,
+                "compute_trace_stats: {compute_trace_stats}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    #[cfg_attr(miri, ignore)]
+    async fn test_process_v07_trace() {
+        let (tx, mut rx): (
+            Sender<trace_utils::SendData>,
+            Receiver<trace_utils::SendData>,
+        ) = mpsc::channel(1);
+
+        let start = get_current_timestamp_nanos();
+        let payload = json!({
+            "container_id": "",
+            "language_name": "python",
+            "language_version": "3.12",
+            "tracer_version": "2.10.0",
+            "runtime_id": "test-runtime-id-value",
+            "chunks": [{
+                "priority": 2,
+                "origin": "",
+                "spans": [
+                    create_test_json_span(11, 222, 0, start),
+                    create_test_json_span(11, 333, 222, start),
+                ],
+                "tags": {"_dd.p.dm": "-4"},
+                "dropped_trace": false,
+            }],
+            "tags": {},
+            "env": "test-env",
+            "hostname": "",
+            "app_version": "",
+        });
+        let bytes = rmp_serde::to_vec(&payload).expect("invalid json");
+        let request = Request::builder()
+            .header("datadog-meta-lang", "python")
+            .header("content-length", "100")
+            .body(hyper::body::Body::from(bytes))
+            .expect("fail to build request");
+
+        let trace_processor = create_test_processor();
+        let config = create_test_config();
+        let tags_provider = create_tags_provider(config.clone());
+        let res = trace_processor
+            .process_traces(
+                config,
+                request,
+                tx,
+                tags_provider,
+                crate::traces::trace_agent::ApiVersion::V07,
+            )
+            .await;
+        assert!(res.is_ok());
+
+        let send_data = rx.recv().await.unwrap();
+        let TracerPayloadCollection::V07(payloads) = send_data.get_payloads() else {
+            panic!("unexpected payload version");
+        };
+        let spans = &payloads[0].chunks[0].spans;
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].span_id, 222);
+        assert!(spans[0].metrics.contains_key("_sampling_priority_v1"));
+        assert_eq!(spans[0].meta["_dd.p.dm"], "-4");
+        assert!(!spans[1].meta.contains_key("_dd.p.dm"));
+    }
+
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    #[cfg_attr(miri, ignore)]
+    async fn test_process_sampled_out_traces() {
+        let (tx, mut rx): (
+            Sender<trace_utils::SendData>,
+            Receiver<trace_utils::SendData>,
+        ) = mpsc::channel(1);
+
+        let start = get_current_timestamp_nanos();
+        let mut error_span = create_test_json_span(11, 111, 0, start);
+        error_span["error"] = json!(1);
+        let chunk = |priority: i32, span: serde_json::Value| {
+            json!({
+                "priority": priority,
+                "origin": "",
+                "spans": [span],
+                "tags": {},
+                "dropped_trace": false,
+            })
+        };
+        let payload = json!({
+            "container_id": "",
+            "language_name": "python",
+            "language_version": "3.12",
+            "tracer_version": "2.10.0",
+            "runtime_id": "test-runtime-id-value",
+            "chunks": [
+                chunk(0, error_span.clone()),
+                chunk(0, create_test_json_span(22, 222, 0, start)),
+                chunk(-1, error_span),
+            ],
+            "tags": {},
+            "env": "test-env",
+            "hostname": "",
+            "app_version": "",
+        });
+        let bytes = rmp_serde::to_vec(&payload).expect("invalid json");
+        let request = Request::builder()
+            .header("datadog-meta-lang", "python")
+            .header("content-length", "100")
+            .body(hyper::body::Body::from(bytes))
+            .expect("fail to build request");
+
+        let trace_processor = create_test_processor();
+        let config = create_test_config();
+        let tags_provider = create_tags_provider(config.clone());
+        let res = trace_processor
+            .process_traces(
+                config,
+                request,
+                tx,
+                tags_provider,
+                crate::traces::trace_agent::ApiVersion::V07,
+            )
+            .await;
+        assert!(res.is_ok());
+
+        // Only the trace with an error dropped by the tracer's sampling is kept
+        let send_data = rx.recv().await.unwrap();
+        let TracerPayloadCollection::V07(payloads) = send_data.get_payloads() else {
+            panic!("unexpected payload version");
+        };
+        assert_eq!(payloads[0].chunks.len(), 1);
+        assert_eq!(payloads[0].chunks[0].spans[0].span_id, 111);
+        assert_eq!(payloads[0].chunks[0].priority, 0);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    #[cfg_attr(miri, ignore)]
+    async fn test_process_otlp_traces() {
+        let (tx, mut rx): (
+            Sender<trace_utils::SendData>,
+            Receiver<trace_utils::SendData>,
+        ) = mpsc::channel(1);
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        {"key": "service.name", "value": {"stringValue": "test-service"}},
+                        {"key": "telemetry.sdk.language", "value": {"stringValue": "go"}}
+                    ]
+                },
+                "scopeSpans": [{
+                    "spans": [{
+                        "traceId": "0000000000000000000000000000000b",
+                        "spanId": "00000000000000de",
+                        "name": "handler",
+                        "kind": 2,
+                        "startTimeUnixNano": "1544712660000000000",
+                        "endTimeUnixNano": "1544712660000000005"
+                    }]
+                }]
+            }]
+        });
+        let request = Request::builder()
+            .header("content-type", "application/json")
+            .body(hyper::body::Body::from(body.to_string()))
+            .expect("fail to build request");
+
+        let trace_processor = create_test_processor();
+        let config = create_test_config();
+        let tags_provider = create_tags_provider(config.clone());
+        let res = trace_processor
+            .process_otlp_traces(config, request, tx, tags_provider)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "{}");
+
+        let send_data = rx.recv().await.unwrap();
+        let TracerPayloadCollection::V07(payloads) = send_data.get_payloads() else {
+            panic!("unexpected payload version");
+        };
+        assert_eq!(payloads[0].language_name, "go");
+        let span = &payloads[0].chunks[0].spans[0];
+        assert_eq!((span.trace_id, span.span_id), (11, 222));
+        assert_eq!(span.service, "test-service");
+        assert_eq!(span.resource, "handler");
+        assert_eq!(span.duration, 5);
+        assert_eq!(span.meta["_dd.origin"], "lambda");
+        assert_eq!(span.meta["functionname"], "my-function");
+    }
+
+    #[tokio::test]
+    #[allow(clippy::unwrap_used)]
+    async fn test_process_otlp_traces_too_large() {
+        let (tx, _rx) = mpsc::channel(1);
+        let request = Request::builder()
+            .header("content-type", "application/x-protobuf")
+            .body(hyper::body::Body::from(vec![0; MAX_CONTENT_LENGTH + 1]))
+            .expect("fail to build request");
+
+        let config = create_test_config();
+        let tags_provider = create_tags_provider(config.clone());
+        let res = create_test_processor()
+            .process_otlp_traces(config, request, tx, tags_provider)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
     }
 }
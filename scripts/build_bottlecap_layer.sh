@@ -41,6 +41,7 @@ _docker_build_bottlecap_zip() {
         -t datadog/build-bottlecap-${arch} \
         -f ./scripts/Dockerfile.bottlecap.build \
         --build-arg PLATFORM=$PLATFORM \
+        --build-arg EXTENSION_VERSION="${VERSION}" \
         . --load
     local dockerId=$(docker create datadog/build-bottlecap-${arch})
     docker cp $dockerId:/datadog_extension.zip $TARGET_DIR/datadog_bottlecap-${arch}.zip